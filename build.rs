@@ -0,0 +1,39 @@
+//! Lists the versions of the migrations embedded in the binary, so the
+//! server can tell whether the database is up to date without running them
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    write_versions("migrations", "migrations.rs");
+    write_versions("migrations_sqlite", "sqlite_migrations.rs");
+}
+
+/// Writes the versions of the migrations in the directory to a file of the
+/// build output, to be included by the store applying them
+fn write_versions(dir: &str, file: &str) {
+    println!("cargo:rerun-if-changed={}", dir);
+
+    let mut versions = fs::read_dir(dir)
+        .unwrap_or_else(|_| panic!("{} directory is missing", dir))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join("up.sql").is_file())
+        .map(|entry| {
+            // the same versions diesel records, the name up to the first underscore
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.split('_').next().unwrap_or_default().replace('-', "")
+        })
+        .collect::<Vec<_>>();
+    versions.sort();
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join(file);
+    fs::write(
+        out,
+        format!(
+            "/// Versions of the embedded migrations, oldest first\nconst MIGRATION_VERSIONS: &[&str] = &{:?};\n",
+            versions
+        ),
+    )
+    .expect("failed to write the migration versions");
+}
@@ -0,0 +1,3 @@
+alter table users drop column deleted_at;
+alter table users drop column disabled;
+alter table users drop column role;
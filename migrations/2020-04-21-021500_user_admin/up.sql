@@ -0,0 +1,3 @@
+alter table users add column role varchar(20) not null default 'user';
+alter table users add column disabled boolean not null default false;
+alter table users add column deleted_at timestamp;
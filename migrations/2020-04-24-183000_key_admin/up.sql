@@ -0,0 +1,2 @@
+alter table keys add column created_at timestamp not null default current_timestamp;
+alter table keys add column revoked_at timestamp;
@@ -0,0 +1,15 @@
+-- every user holds a key of their own again, which users who redeemed the
+-- same multi-use key can't, so once that happened this can't be reverted
+do $$
+begin
+  if exists (select 1 from users group by key_id having count(*) > 1) then
+    raise exception 'beta keys redeemed by several users can''t be made single use again';
+  end if;
+end $$;
+
+alter table users add constraint users_key_id_key unique (key_id);
+drop table redemptions;
+alter table keys drop column redemption_count;
+alter table keys drop column max_redemptions;
+alter table keys drop column expires_at;
+alter table keys drop column label;
@@ -0,0 +1,19 @@
+alter table keys add column label varchar(100);
+alter table keys add column expires_at timestamp;
+alter table keys add column max_redemptions integer not null default 1 check (max_redemptions > 0);
+alter table keys add column redemption_count integer not null default 0 check (redemption_count >= 0);
+
+create table redemptions (
+  key_id uuid not null references keys(id),
+  user_id integer not null unique references users(id) on delete cascade,
+  redeemed_at timestamp not null default current_timestamp,
+  primary key (key_id, user_id)
+);
+
+insert into redemptions (key_id, user_id, redeemed_at)
+  select key_id, id, created_at from users;
+
+update keys set redemption_count = (select count(*) from redemptions where redemptions.key_id = keys.id);
+
+-- a key can now be redeemed by several users
+alter table users drop constraint users_key_id_key;
@@ -0,0 +1,2 @@
+alter table users drop column invited_by;
+alter table keys drop column created_by;
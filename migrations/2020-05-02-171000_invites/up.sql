@@ -0,0 +1,5 @@
+alter table keys add column created_by integer references users(id) on delete set null;
+alter table users add column invited_by integer references users(id) on delete set null;
+
+create index keys_created_by_idx on keys (created_by);
+create index users_invited_by_idx on users (invited_by);
@@ -0,0 +1,11 @@
+create table waitlist (
+  id serial primary key,
+  email varchar(40) not null unique,
+  source varchar(100),
+  token uuid not null unique,
+  created_at timestamp not null default current_timestamp,
+  approved_at timestamp,
+  key_id uuid references keys(id) on delete set null
+);
+
+create index waitlist_pending_idx on waitlist (id) where approved_at is null;
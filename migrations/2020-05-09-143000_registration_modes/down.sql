@@ -0,0 +1,10 @@
+-- users who signed up without a key can't be represented before this
+-- migration, so once anyone did this can't be reverted
+do $$
+begin
+  if exists (select 1 from users where key_id is null) then
+    raise exception 'users who signed up without a beta key can''t be given one again';
+  end if;
+end $$;
+
+alter table users alter column key_id set not null;
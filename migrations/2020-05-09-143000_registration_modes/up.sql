@@ -0,0 +1 @@
+alter table users alter column key_id drop not null;
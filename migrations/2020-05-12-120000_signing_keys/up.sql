@@ -0,0 +1,10 @@
+create table signing_keys (
+  kid varchar(64) primary key,
+  algorithm varchar(10) not null,
+  private_key text not null,
+  public_key text not null,
+  created_at timestamp not null default current_timestamp,
+  retired_at timestamp
+);
+
+create index signing_keys_retired_at_idx on signing_keys (retired_at);
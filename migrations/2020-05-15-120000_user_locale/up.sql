@@ -0,0 +1,2 @@
+-- language tag errors are written in for the user, such as fr
+alter table users add column locale varchar(35);
@@ -0,0 +1,5 @@
+drop table signing_keys;
+drop table waitlist;
+drop table redemptions;
+drop table users;
+drop table keys;
@@ -0,0 +1,54 @@
+-- the schema of the Postgres migrations up to signing keys, in SQLite types
+create table keys (
+  id text primary key not null,
+  created_at timestamp not null,
+  revoked_at timestamp,
+  label text,
+  expires_at timestamp,
+  max_redemptions integer not null default 1 check (max_redemptions > 0),
+  redemption_count integer not null default 0 check (redemption_count >= 0),
+  created_by integer references users(id) on delete set null
+);
+
+create table users (
+  id integer primary key autoincrement,
+  email text not null unique,
+  password text not null,
+  key_id text references keys(id),
+  created_at timestamp not null,
+  role text not null default 'user',
+  disabled boolean not null default 0,
+  deleted_at timestamp,
+  invited_by integer references users(id) on delete set null
+);
+
+create table redemptions (
+  key_id text not null references keys(id),
+  user_id integer not null unique references users(id) on delete cascade,
+  redeemed_at timestamp not null,
+  primary key (key_id, user_id)
+);
+
+create table waitlist (
+  id integer primary key autoincrement,
+  email text not null unique,
+  source text,
+  token text not null unique,
+  created_at timestamp not null,
+  approved_at timestamp,
+  key_id text references keys(id) on delete set null
+);
+
+create table signing_keys (
+  kid text primary key not null,
+  algorithm text not null,
+  private_key text not null,
+  public_key text not null,
+  created_at timestamp not null,
+  retired_at timestamp
+);
+
+create index keys_created_by_idx on keys (created_by);
+create index users_invited_by_idx on users (invited_by);
+create index waitlist_pending_idx on waitlist (id) where approved_at is null;
+create index signing_keys_retired_at_idx on signing_keys (retired_at);
@@ -0,0 +1,463 @@
+use actix_web::web;
+use failure::Fail;
+use std::time::Duration;
+
+use crate::db::Database;
+use crate::routes;
+use crate::settings::Settings;
+use crate::store::{self, SharedStore, StoreError};
+use crate::utils::keyring::{self, Keyring};
+use crate::utils::mailer::{self, SharedMailer};
+use crate::utils::metrics::Metrics;
+
+/// Representation of an error preventing the auth service from starting
+#[derive(Fail, Debug)]
+pub enum StartError {
+    #[fail(display = "{}", _0)]
+    Store(StoreError),
+    #[fail(display = "Could not load the signing keys: {}", _0)]
+    Keys(String),
+    #[fail(display = "Could not set up the mailer: {}", _0)]
+    Mail(String),
+}
+
+/// Everything the auth routes and validators need, shared between the workers
+/// of a server. Mount the routes with `App::new().configure(|cfg| auth.configure(cfg))`,
+/// or register the state with `Auth::data` and the routes under a scope of their
+/// own with `web::scope("/auth").configure(|cfg| auth.routes(cfg))`. Wrap the app
+/// with `auth.metrics.clone()` to record requests in the exposed metrics, and
+/// with `Localization` to write errors in the language of the client
+#[derive(Clone)]
+pub struct Auth {
+    pub db: Database,
+    pub keyring: Keyring,
+    pub mailer: SharedMailer,
+    pub metrics: Metrics,
+    pub settings: Settings,
+}
+
+impl Auth {
+    /// Opens the configured store and loads the signing keys, reloading them
+    /// in the background as they are rotated
+    pub async fn start(settings: Settings) -> Result<Auth, StartError> {
+        let store = store::open(&settings.database).map_err(StartError::Store)?;
+        Auth::start_with_store(store, settings).await
+    }
+
+    /// Starts the service on a store opened by the caller, loading the signing
+    /// keys the same way `Auth::start` does
+    pub async fn start_with_store(
+        store: SharedStore,
+        settings: Settings,
+    ) -> Result<Auth, StartError> {
+        let mailer = mailer::from_settings(&settings.mail).map_err(StartError::Mail)?;
+        let db = Database::start(store, &settings.database);
+
+        let keyring = Keyring::new(&settings.token);
+        let loading = keyring.clone();
+        db.run(move |store| loading.refresh(store))
+            .await
+            .map_err(|error| StartError::Keys(error.to_string()))?;
+
+        actix_rt::spawn(keyring::refresh_periodically(
+            keyring.clone(),
+            db.clone(),
+            Duration::from_secs(settings.token.refresh_secs),
+        ));
+
+        Ok(Auth {
+            db,
+            keyring,
+            mailer,
+            metrics: Metrics::new(),
+            settings,
+        })
+    }
+
+    /// Replaces the mailer invitations and waitlist approvals are sent with
+    pub fn with_mailer(mut self, mailer: SharedMailer) -> Self {
+        self.mailer = mailer;
+        self
+    }
+
+    /// Registers the shared state, which the validators of other routes
+    /// need as well
+    pub fn data(&self, cfg: &mut web::ServiceConfig) {
+        cfg.data(self.db.clone())
+            .data(self.keyring.clone())
+            .data(self.mailer.clone())
+            .data(self.metrics.clone())
+            .data(self.settings.clone());
+    }
+
+    /// Registers the auth routes, accepting bodies up to the configured limits
+    pub fn routes(&self, cfg: &mut web::ServiceConfig) {
+        routes::mount(cfg, &self.settings.server);
+    }
+
+    /// Registers the shared state and the auth routes
+    pub fn configure(&self, cfg: &mut web::ServiceConfig) {
+        self.data(cfg);
+        self.routes(cfg);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::tests::create_store;
+    use crate::models::key::{GenerateKeysForm, Key};
+    use crate::models::registration::{RegistrationMode, RegistrationPolicy};
+    use crate::models::user::{NewUserForm, User, ADMIN_ROLE};
+    use crate::settings::{DatabaseSettings, TokenSettings};
+    use crate::store::postgres::PgStore;
+    use crate::store::{Backend, KeyStore, UserStore};
+    use crate::utils::i18n::Localization;
+    use crate::utils::token::{self, Token};
+    use actix_web::dev::Service;
+    use actix_web::{http::StatusCode, test, App};
+    use actix_web_httpauth::middleware::HttpAuthentication;
+    use std::sync::Arc;
+
+    /// Settings of an in-memory service anyone can sign up to
+    pub fn settings() -> Settings {
+        Settings {
+            database: DatabaseSettings {
+                backend: Backend::Memory,
+                ..Default::default()
+            },
+            token: TokenSettings {
+                secret: String::from("secret"),
+                ..Default::default()
+            },
+            registration: RegistrationPolicy {
+                mode: RegistrationMode::Open,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Auth service running on a throwaway Postgres schema, with factories for
+    /// the keys, users and tokens tests start from. Mount it with
+    /// `App::new().configure(|cfg| harness.auth.configure(cfg))`
+    pub struct Harness {
+        pub auth: Auth,
+        pub store: PgStore,
+    }
+
+    impl Harness {
+        /// Starts a service anyone can sign up to
+        pub async fn start() -> Self {
+            Harness::start_with(settings()).await
+        }
+
+        /// Starts a service with the provided settings on a schema of its own
+        pub async fn start_with(mut settings: Settings) -> Self {
+            settings.database.backend = Backend::Postgres;
+            settings.database.max_connections = 2;
+
+            let store = create_store();
+            let auth = Auth::start_with_store(Arc::new(store.clone()), settings)
+                .await
+                .expect("failed to start");
+
+            Harness { auth, store }
+        }
+
+        /// Generates a key redeemable the provided number of times
+        pub fn key(&self, max_redemptions: i32) -> Key {
+            let form = GenerateKeysForm {
+                count: 1,
+                max_redemptions: Some(max_redemptions),
+                ..Default::default()
+            };
+
+            self.store.generate_keys(&form).unwrap().remove(0)
+        }
+
+        /// Creates a user with the provided email, whose password is "password"
+        pub fn user(&self, email: &str) -> User {
+            let open = RegistrationPolicy {
+                mode: RegistrationMode::Open,
+                ..Default::default()
+            };
+            let new_user = NewUserForm {
+                email: email.to_string(),
+                password: "password".to_string(),
+                key_id: None,
+                locale: None,
+            };
+
+            new_user.create(&open, &self.store).unwrap()
+        }
+
+        /// Creates a user with the provided email and grants them the admin role
+        pub fn admin(&self, email: &str) -> User {
+            let user = self.user(email);
+            self.store.set_role(user.id, ADMIN_ROLE).unwrap();
+
+            self.store.find_user(user.id).unwrap()
+        }
+
+        /// Issues a token for the user as signing up or logging in would
+        pub fn token(&self, user: &User) -> String {
+            let token = token::issue(user, &self.auth.settings.token);
+            self.auth.keyring.encode(&token).unwrap()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_mounts_the_routes_inside_another_app() {
+        let auth = Auth::start(settings()).await.unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .configure(|cfg| auth.data(cfg))
+                .service(web::scope("/auth").configure(crate::configure))
+                .service(
+                    web::resource("/me")
+                        .wrap(HttpAuthentication::bearer(crate::validator))
+                        .to(|token: Token| async move { token.email }),
+                ),
+        )
+        .await;
+
+        let signup = test::TestRequest::post()
+            .uri("/auth/signup")
+            .set_json(&serde_json::json!({"email": "host@app.com", "password": "password"}))
+            .to_request();
+        let token: String = test::read_response_json(&mut app, signup).await;
+        let bearer = format!("Bearer {}", token);
+
+        let me = test::TestRequest::get()
+            .uri("/me")
+            .header("authorization", bearer.as_str())
+            .to_request();
+        assert_eq!(test::read_response(&mut app, me).await, "host@app.com");
+
+        let users = test::TestRequest::get()
+            .uri("/auth/users")
+            .header("authorization", bearer.as_str())
+            .to_request();
+        let response = test::call_service(&mut app, users).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let anonymous = test::TestRequest::get().uri("/me").to_request();
+        let error = app.call(anonymous).await.err().unwrap();
+        assert_eq!(
+            error.as_response_error().error_response().status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_malformed_oversized_and_non_json_bodies() {
+        let mut settings = settings();
+        settings.server.json_limit_bytes = 64;
+        let auth = Auth::start(settings).await.unwrap();
+        let mut app = test::init_service(App::new().configure(|cfg| auth.configure(cfg))).await;
+
+        let signup = |body: &str, content_type: &str| {
+            test::TestRequest::post()
+                .uri("/signup")
+                .header("content-type", content_type)
+                .set_payload(body.to_string())
+                .to_request()
+        };
+
+        let invalid = [
+            ("{\"email\": ", "body", "MALFORMED_JSON"),
+            ("{\"password\": \"password\"}", "email", "MISSING_FIELD"),
+            (
+                "{\"email\": 5, \"password\": \"x\"}",
+                "email",
+                "INVALID_TYPE",
+            ),
+        ];
+
+        for (json, field, code) in invalid.iter() {
+            let response = test::call_service(&mut app, signup(json, "application/json")).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(
+                response.headers().get("content-type").unwrap(),
+                "application/problem+json"
+            );
+            let body: serde_json::Value =
+                serde_json::from_slice(&test::read_body(response).await).unwrap();
+            assert_eq!(body["code"], "VALIDATION_ERROR");
+            assert_eq!(body["errors"][0]["field"], *field);
+            assert_eq!(body["errors"][0]["code"], *code);
+        }
+
+        let text = signup("email=a@b.com", "text/plain");
+        let response = test::call_service(&mut app, text).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let large = format!(
+            "{{\"email\": \"{}@app.com\", \"password\": \"x\"}}",
+            "a".repeat(64)
+        );
+        let response = test::call_service(&mut app, signup(&large, "application/json")).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(response).await).unwrap();
+        assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+    }
+
+    #[actix_rt::test]
+    async fn it_writes_errors_in_the_locale_of_the_user() {
+        let auth = Auth::start(settings()).await.unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Localization)
+                .configure(|cfg| auth.configure(cfg)),
+        )
+        .await;
+
+        let signup = test::TestRequest::post()
+            .uri("/signup")
+            .set_json(&serde_json::json!({
+                "email": "locale@app.com",
+                "password": "password",
+                "locale": "fr-CA",
+            }))
+            .to_request();
+        let token: String = test::read_response_json(&mut app, signup).await;
+
+        let users = test::TestRequest::get()
+            .uri("/users?sort=password")
+            .header("authorization", format!("Bearer {}", token))
+            .header("accept-language", "en")
+            .to_request();
+        let response = test::call_service(&mut app, users).await;
+        assert_eq!(response.headers().get("content-language").unwrap(), "fr");
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(response).await).unwrap();
+        assert_eq!(body["code"], "VALIDATION_ERROR");
+        assert_eq!(body["errors"][0]["code"], "INVALID_SORT");
+        assert_eq!(
+            body["errors"][0]["message"],
+            "Doit être l'une des valeurs suivantes : id, email, précédée de - pour un tri décroissant"
+        );
+
+        let unsupported = test::TestRequest::post()
+            .uri("/signup")
+            .header("accept-language", "de")
+            .set_json(&serde_json::json!({
+                "email": "klingon@app.com",
+                "password": "password",
+                "locale": "tlh",
+            }))
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, unsupported).await;
+        assert_eq!(body["detail"], "Die Anfrage enthält Fehler");
+        assert_eq!(body["errors"][0]["field"], "locale");
+        assert_eq!(body["errors"][0]["code"], "INVALID_LOCALE");
+    }
+
+    #[actix_rt::test]
+    async fn it_reports_liveness_and_readiness() {
+        let auth = Auth::start(settings()).await.unwrap();
+        let mut app = test::init_service(App::new().configure(|cfg| auth.configure(cfg))).await;
+
+        let live = test::TestRequest::get().uri("/healthz").to_request();
+        let response = test::call_service(&mut app, live).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let ready = test::TestRequest::get().uri("/readyz").to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, ready).await;
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["checks"]["database"]["ok"], true);
+        assert_eq!(
+            body["checks"]["migrations"]["pending"],
+            serde_json::json!([])
+        );
+        assert_eq!(body["checks"]["signing_keys"]["verifying_keys"], 0);
+
+        // keys that were never loaded keep the service out of rotation
+        let unloaded = Auth {
+            keyring: Keyring::new(&auth.settings.token),
+            ..auth
+        };
+        let mut app = test::init_service(App::new().configure(|cfg| unloaded.configure(cfg))).await;
+
+        let ready = test::TestRequest::get().uri("/readyz").to_request();
+        let response = test::call_service(&mut app, ready).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(response).await).unwrap();
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["checks"]["signing_keys"]["ok"], false);
+        assert_eq!(body["checks"]["database"]["ok"], true);
+    }
+
+    #[actix_rt::test]
+    async fn it_exposes_request_and_authentication_metrics() {
+        let auth = Auth::start(settings()).await.unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(auth.metrics.clone())
+                .configure(|cfg| auth.configure(cfg)),
+        )
+        .await;
+
+        let signup = test::TestRequest::post()
+            .uri("/signup")
+            .set_json(&serde_json::json!({"email": "metrics@app.com", "password": "password"}))
+            .to_request();
+        let token: String = test::read_response_json(&mut app, signup).await;
+
+        let login = test::TestRequest::post()
+            .uri("/login")
+            .set_json(&serde_json::json!({"email": "metrics@app.com", "password": "wrong"}))
+            .to_request();
+        test::call_service(&mut app, login).await;
+
+        let users = test::TestRequest::get()
+            .uri("/users")
+            .header("authorization", format!("Bearer {}", token))
+            .to_request();
+        test::call_service(&mut app, users).await;
+
+        // rejected tokens are counted against the scope the validator wraps
+        let rejected = test::TestRequest::get()
+            .uri("/admin/users/42/referrals")
+            .header("authorization", "Bearer invalid")
+            .to_request();
+        assert!(app.call(rejected).await.is_err());
+
+        let status = test::TestRequest::get()
+            .uri(&format!("/waitlist/{}", uuid::Uuid::new_v4()))
+            .to_request();
+        test::call_service(&mut app, status).await;
+
+        let scan = test::TestRequest::get().uri("/wp-login.php").to_request();
+        test::call_service(&mut app, scan).await;
+
+        let metrics = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::read_response(&mut app, metrics).await;
+        let body = std::str::from_utf8(&body).unwrap();
+
+        for expected in &[
+            r#"http_requests_total{method="POST",route="/signup",status="200"} 1"#,
+            r#"http_requests_total{method="GET",route="/users",status="200"} 1"#,
+            r#"http_requests_total{method="GET",route="/admin/*",status="401"} 1"#,
+            r#"http_requests_total{method="GET",route="/waitlist/{token}",status="404"} 1"#,
+            r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#,
+            r#"auth_errors_total{code="INVALID_LOGIN"} 1"#,
+            r#"auth_errors_total{code="UNAUTHORIZED"} 1"#,
+            r#"auth_logins_total{outcome="failure"} 1"#,
+            r#"auth_signups_total 1"#,
+            r#"auth_token_validations_total{outcome="accepted"} 1"#,
+            r#"auth_token_validations_total{outcome="rejected"} 1"#,
+        ] {
+            assert!(
+                body.contains(expected),
+                "{} missing from\n{}",
+                expected,
+                body
+            );
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Administration of the auth service straight against its database, using
+//! the same configuration as the server
+
+use chrono::{Duration, Utc};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process;
+use validator::Validate;
+
+use auth::models::key::GenerateKeysForm;
+use auth::models::registration::{RegistrationMode, RegistrationPolicy};
+use auth::models::signing_key::SigningKey;
+use auth::models::user::{self, NewUserForm, ROLES};
+use auth::settings::Settings;
+use auth::store::{self, Store};
+use auth::utils::errors::ApiError;
+
+/// Environment variable the password of a new user is read from, before
+/// falling back to the first line of stdin
+const PASSWORD_VAR: &str = "AUTH_ADMIN_PASSWORD";
+
+fn main() {
+    let matches = cli().get_matches();
+
+    let settings = match Settings::load_file(matches.value_of("config")) {
+        Ok(settings) => settings,
+        Err(error) => exit(&error.to_string()),
+    };
+
+    let store = match store::open(&settings.database) {
+        Ok(store) => store,
+        Err(error) => exit(&error.to_string()),
+    };
+
+    let result = match matches.subcommand() {
+        ("migrate", Some(_)) => migrate(store.as_ref()),
+        ("create-user", Some(args)) => create_user(store.as_ref(), args),
+        ("assign-role", Some(args)) => assign_role(store.as_ref(), args),
+        ("mint-keys", Some(args)) => mint_keys(store.as_ref(), args),
+        ("rotate-signing-key", Some(_)) => rotate_signing_key(store.as_ref()),
+        _ => unreachable!("a subcommand is required"),
+    };
+
+    if let Err(error) = result {
+        exit(&describe(&error));
+    }
+}
+
+/// Describes the subcommands and their arguments
+fn cli() -> App<'static, 'static> {
+    let role = Arg::with_name("role")
+        .long("role")
+        .value_name("ROLE")
+        .possible_values(ROLES)
+        .takes_value(true);
+
+    App::new("auth-admin")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Administers the auth database")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("TOML config file, defaults to auth.toml when present")
+                .takes_value(true)
+                .global(true),
+        )
+        .subcommand(SubCommand::with_name("migrate").about("Applies pending migrations"))
+        .subcommand(
+            SubCommand::with_name("create-user")
+                .about(
+                    "Creates a user, whatever the registration mode, with the password \
+                     in AUTH_ADMIN_PASSWORD or on the first line of stdin",
+                )
+                .arg(email_arg())
+                .arg(
+                    role.clone()
+                        .help("Role to grant the user, defaults to user"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("assign-role")
+                .about("Grants a role to an existing user")
+                .arg(email_arg())
+                .arg(role.required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("mint-keys")
+                .about("Generates beta keys and prints them one per line")
+                .arg(number_arg("count", "Number of keys to generate").default_value("1"))
+                .arg(number_arg(
+                    "max-redemptions",
+                    "Times each key can be redeemed",
+                ))
+                .arg(number_arg("expires-in-days", "Days until the keys expire"))
+                .arg(
+                    Arg::with_name("label")
+                        .long("label")
+                        .value_name("LABEL")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rotate-signing-key")
+                .about("Generates a new key to sign tokens with and retires the current one"),
+        )
+}
+
+fn email_arg() -> Arg<'static, 'static> {
+    Arg::with_name("email")
+        .long("email")
+        .value_name("EMAIL")
+        .required(true)
+        .takes_value(true)
+}
+
+fn number_arg(name: &'static str, help: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name(name)
+        .long(name)
+        .value_name("N")
+        .help(help)
+        .takes_value(true)
+        .validator(|value| {
+            value
+                .parse::<u32>()
+                .map(|_| ())
+                .map_err(|_| String::from("must be a positive number"))
+        })
+}
+
+/// Applies the migrations embedded in the binary
+fn migrate(store: &dyn Store) -> Result<(), ApiError> {
+    let applied = store.migrate()?;
+
+    if applied.is_empty() {
+        println!("The database is up to date");
+    }
+
+    for version in applied {
+        println!("Applied migration {}", version);
+    }
+
+    Ok(())
+}
+
+/// Creates a user without requiring a beta key, optionally granting a role
+fn create_user(store: &dyn Store, args: &ArgMatches) -> Result<(), ApiError> {
+    let policy = RegistrationPolicy {
+        mode: RegistrationMode::Open,
+        ..Default::default()
+    };
+
+    let new_user = NewUserForm {
+        email: value(args, "email"),
+        password: read_password()
+            .unwrap_or_else(|error| exit(&format!("could not read the password: {}", error))),
+        key_id: None,
+        locale: None,
+    };
+
+    let role = args.value_of("role").unwrap_or(user::USER_ROLE);
+    let created = new_user.create_with_role(role, &policy, store)?;
+
+    println!(
+        "Created user {} ({}) with role {}",
+        created.id, created.email, created.role
+    );
+
+    Ok(())
+}
+
+/// Grants a role to the user with the provided email
+fn assign_role(store: &dyn Store, args: &ArgMatches) -> Result<(), ApiError> {
+    let role = value(args, "role");
+    user::validate_role(&role)?;
+
+    let found = store
+        .find_user_by_email(&value(args, "email"))?
+        .ok_or(ApiError::NotFound)?;
+    let updated = store.set_role(found.id, &role)?;
+
+    println!("Granted role {} to {}", updated.role, updated.email);
+
+    Ok(())
+}
+
+/// Generates beta keys and prints them one per line
+fn mint_keys(store: &dyn Store, args: &ArgMatches) -> Result<(), ApiError> {
+    let number = |name| {
+        args.value_of(name)
+            .and_then(|value| value.parse::<u32>().ok())
+    };
+
+    let form = GenerateKeysForm {
+        count: number("count").unwrap_or(1),
+        label: args.value_of("label").map(String::from),
+        expires_at: number("expires-in-days")
+            .map(|days| Utc::now().naive_utc() + Duration::days(i64::from(days))),
+        max_redemptions: number("max-redemptions").map(|max| max as i32),
+    };
+    form.validate()?;
+
+    for key in store.generate_keys(&form)? {
+        println!("{}", key.id);
+    }
+
+    Ok(())
+}
+
+/// Generates a new signing key, retiring the current one; running servers
+/// pick it up the next time they refresh their keys
+fn rotate_signing_key(store: &dyn Store) -> Result<(), ApiError> {
+    let key = SigningKey::generate()?;
+    store.rotate_signing_key(&key)?;
+
+    println!("Tokens are now signed with key {}", key.kid);
+
+    Ok(())
+}
+
+/// Reads the password from the environment or stdin, so it stays out of the
+/// shell history and the process list
+fn read_password() -> io::Result<String> {
+    if let Ok(password) = env::var(PASSWORD_VAR) {
+        return Ok(password);
+    }
+
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+        io::stderr().flush().ok();
+    }
+
+    let mut password = String::new();
+    stdin.lock().read_line(&mut password)?;
+
+    Ok(password.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Returns the value of a required argument
+fn value(args: &ArgMatches, name: &str) -> String {
+    args.value_of(name).unwrap_or_default().to_string()
+}
+
+/// Describes the error, including the codes of validation errors
+fn describe(error: &ApiError) -> String {
+    match error {
+        ApiError::ValidationError(_, message, errors) => {
+            let fields: Vec<String> = errors
+                .iter()
+                .map(|error| format!("{}: {}", error.field, error.code))
+                .collect();
+            format!("{} ({})", message, fields.join(", "))
+        }
+        ApiError::InternalServerError(_, message) => message.clone(),
+        _ => error.to_string(),
+    }
+}
+
+/// Reports the error and exits unsuccessfully
+fn exit(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
@@ -1,2 +1,9 @@
+pub mod admin;
+pub mod docs;
+pub mod health;
+pub mod invite;
+pub mod jwks;
 pub mod key;
+pub mod metrics;
 pub mod user;
+pub mod waitlist;
@@ -0,0 +1,431 @@
+use actix_web::{web, HttpRequest};
+use serde::Serialize;
+use tracing::instrument;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::db::Database;
+use crate::models::key::{self, GenerateKeysForm, Key};
+use crate::models::user::ManagedUser;
+use crate::models::waitlist::{ApproveWaitlistForm, WaitlistEntry};
+use crate::utils::errors::ApiError;
+use crate::utils::json::Json;
+use crate::utils::mailer::{self, SharedMailer};
+use crate::utils::pagination::ListQuery;
+
+/// Summary of a waitlist approval
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ApprovalSummary {
+    pub approved: Vec<WaitlistEntry>,
+    pub emailed: usize,
+}
+
+/// Summary of a bulk key import
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+///  Returns a filtered, sorted and paginated list of users
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    tag = "admin",
+    params(ListQuery<ManagedUser>),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Page of users, counted in X-Total-Count and linked to its neighbours in Link", body = [ManagedUser]),
+        (status = 400, description = "VALIDATION_ERROR for invalid list parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn list_users(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    query: ListQuery<ManagedUser>,
+) -> Result<web::HttpResponse, ApiError> {
+    let params = query.into_inner();
+
+    let page = db.run(move |store| store.search_users(&params)).await?;
+
+    Ok(page.respond(&req))
+}
+
+///  Disables a user, preventing them from logging in or using their token
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/disable",
+    tag = "admin",
+    params(("id" = i32, Path, description = "Id of the user")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The disabled user", body = ManagedUser),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "NOT_FOUND", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn disable_user(
+    db: web::Data<Database>,
+    user_id: web::Path<i32>,
+) -> Result<web::HttpResponse, ApiError> {
+    let user = db
+        .run(move |store| store.set_disabled(*user_id, true))
+        .await?;
+
+    Ok(web::HttpResponse::Ok().json(user))
+}
+
+///  Re-enables a previously disabled user
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/enable",
+    tag = "admin",
+    params(("id" = i32, Path, description = "Id of the user")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The enabled user", body = ManagedUser),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "NOT_FOUND", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn enable_user(
+    db: web::Data<Database>,
+    user_id: web::Path<i32>,
+) -> Result<web::HttpResponse, ApiError> {
+    let user = db
+        .run(move |store| store.set_disabled(*user_id, false))
+        .await?;
+
+    Ok(web::HttpResponse::Ok().json(user))
+}
+
+///  Soft deletes a user
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{id}",
+    tag = "admin",
+    params(("id" = i32, Path, description = "Id of the user")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The deleted user", body = ManagedUser),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "NOT_FOUND", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn delete_user(
+    db: web::Data<Database>,
+    user_id: web::Path<i32>,
+) -> Result<web::HttpResponse, ApiError> {
+    let user = db
+        .run(move |store| store.soft_delete_user(*user_id))
+        .await?;
+
+    Ok(web::HttpResponse::Ok().json(user))
+}
+
+///  Restores a soft deleted user
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/restore",
+    tag = "admin",
+    params(("id" = i32, Path, description = "Id of the user")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The restored user", body = ManagedUser),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "NOT_FOUND", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn restore_user(
+    db: web::Data<Database>,
+    user_id: web::Path<i32>,
+) -> Result<web::HttpResponse, ApiError> {
+    let user = db.run(move |store| store.restore_user(*user_id)).await?;
+
+    Ok(web::HttpResponse::Ok().json(user))
+}
+
+///  Generates the requested number of beta keys
+#[utoipa::path(
+    post,
+    path = "/admin/keys",
+    tag = "admin",
+    request_body = GenerateKeysForm,
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "The generated keys", body = [Key]),
+        (status = 400, description = "VALIDATION_ERROR", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn generate_keys(
+    db: web::Data<Database>,
+    Json(form): Json<GenerateKeysForm>,
+) -> Result<web::HttpResponse, ApiError> {
+    form.validate()?;
+
+    let keys = db.run(move |store| store.generate_keys(&form)).await?;
+
+    Ok(web::HttpResponse::Created().json(keys))
+}
+
+///  Returns a filtered, sorted and paginated list of beta keys with their redemption counts
+#[utoipa::path(
+    get,
+    path = "/admin/keys",
+    tag = "admin",
+    params(ListQuery<Key>),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Page of keys, counted in X-Total-Count and linked to its neighbours in Link", body = [Key]),
+        (status = 400, description = "VALIDATION_ERROR for invalid list parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn list_keys(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    query: ListQuery<Key>,
+) -> Result<web::HttpResponse, ApiError> {
+    let params = query.into_inner();
+
+    let page = db.run(move |store| store.search_keys(&params)).await?;
+
+    Ok(page.respond(&req))
+}
+
+///  Revokes a beta key so it can't be redeemed any further
+#[utoipa::path(
+    delete,
+    path = "/admin/keys/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "The beta key")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The revoked key", body = Key),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "NOT_FOUND", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn revoke_key(
+    db: web::Data<Database>,
+    key_id: web::Path<uuid::Uuid>,
+) -> Result<web::HttpResponse, ApiError> {
+    let key = db.run(move |store| store.revoke_key(*key_id)).await?;
+
+    Ok(web::HttpResponse::Ok().json(key))
+}
+
+///  Returns the users who redeemed a beta key
+#[utoipa::path(
+    get,
+    path = "/admin/keys/{id}/redemptions",
+    tag = "admin",
+    params(("id" = String, Path, description = "The beta key")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Users who redeemed the key", body = [RedeemedBy]),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "NOT_FOUND", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn key_redemptions(
+    db: web::Data<Database>,
+    key_id: web::Path<uuid::Uuid>,
+) -> Result<web::HttpResponse, ApiError> {
+    let redeemed_by = db.run(move |store| store.key_redemptions(*key_id)).await?;
+
+    Ok(web::HttpResponse::Ok().json(redeemed_by))
+}
+
+///  Returns the tree of users referred by the user
+#[utoipa::path(
+    get,
+    path = "/admin/users/{id}/referrals",
+    tag = "admin",
+    params(("id" = i32, Path, description = "Id of the user")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Users referred by the user, directly or not", body = [Referral]),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "NOT_FOUND", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn user_referrals(
+    db: web::Data<Database>,
+    user_id: web::Path<i32>,
+) -> Result<web::HttpResponse, ApiError> {
+    let referrals = db
+        .run(move |store| {
+            // make sure the user exists so unknown ids are reported as such
+            store.find_user(*user_id)?;
+            store.referral_tree(*user_id)
+        })
+        .await?;
+
+    Ok(web::HttpResponse::Ok().json(referrals))
+}
+
+///  Imports beta keys from a CSV document with a key in the first column
+#[utoipa::path(
+    post,
+    path = "/admin/keys/import",
+    tag = "admin",
+    request_body(content = String, content_type = "text/csv", description = "Keys in the first column, with an optional id header"),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Number of keys imported and skipped as duplicates", body = ImportSummary),
+        (status = 400, description = "VALIDATION_ERROR listing INVALID_CSV", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn import_keys(
+    db: web::Data<Database>,
+    body: String,
+) -> Result<web::HttpResponse, ApiError> {
+    let ids = key::parse_csv(&body)?;
+    let total = ids.len();
+
+    let imported = db.run(move |store| store.import_keys(&ids)).await?;
+
+    Ok(web::HttpResponse::Ok().json(ImportSummary {
+        imported,
+        skipped: total - imported,
+    }))
+}
+
+///  Exports every beta key matching the filters as a CSV document
+#[utoipa::path(
+    get,
+    path = "/admin/keys/export",
+    tag = "admin",
+    params(ListQuery<Key>),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Keys matching the filters", body = String, content_type = "text/csv"),
+        (status = 400, description = "VALIDATION_ERROR for invalid list parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn export_keys(
+    db: web::Data<Database>,
+    query: ListQuery<Key>,
+) -> Result<web::HttpResponse, ApiError> {
+    let params = query.into_inner();
+
+    let keys = db.run(move |store| store.export_keys(&params)).await?;
+
+    Ok(web::HttpResponse::Ok()
+        .content_type("text/csv")
+        .header("Content-Disposition", "attachment; filename=\"keys.csv\"")
+        .body(key::to_csv(&keys)))
+}
+
+///  Returns a filtered, sorted and paginated list of waitlist entries
+#[utoipa::path(
+    get,
+    path = "/admin/waitlist",
+    tag = "admin",
+    params(ListQuery<WaitlistEntry>),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Page of waitlist entries, counted in X-Total-Count and linked to its neighbours in Link", body = [WaitlistEntry]),
+        (status = 400, description = "VALIDATION_ERROR for invalid list parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn list_waitlist(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    query: ListQuery<WaitlistEntry>,
+) -> Result<web::HttpResponse, ApiError> {
+    let params = query.into_inner();
+
+    let page = db.run(move |store| store.search_waitlist(&params)).await?;
+
+    Ok(page.respond(&req))
+}
+
+///  Issues keys to the next entries on the waitlist and emails them out
+#[utoipa::path(
+    post,
+    path = "/admin/waitlist/approve",
+    tag = "admin",
+    request_body = ApproveWaitlistForm,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The approved entries and how many were emailed their key", body = ApprovalSummary),
+        (status = 400, description = "VALIDATION_ERROR", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "FORBIDDEN unless the token belongs to an admin", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn approve_waitlist(
+    db: web::Data<Database>,
+    mailer: web::Data<SharedMailer>,
+    Json(approve_form): Json<ApproveWaitlistForm>,
+) -> Result<web::HttpResponse, ApiError> {
+    approve_form.validate()?;
+
+    let count = approve_form.count;
+    let approved = db.run(move |store| store.approve_waitlist(count)).await?;
+
+    let emails: Vec<_> = approved
+        .iter()
+        .filter_map(|entry| {
+            let key_id = entry.key_id?;
+            Some((entry.id, mailer::waitlist_approval(&entry.email, &key_id)))
+        })
+        .collect();
+
+    let mailer = mailer.get_ref().clone();
+    let emailed = web::block(move || -> Result<usize, ApiError> {
+        // the keys are issued either way, so a failed delivery is only reported
+        let delivered = emails
+            .iter()
+            .filter(|(entry_id, email)| match mailer.send(email) {
+                Ok(()) => true,
+                Err(error) => {
+                    warn!(
+                        "failed to email the key of waitlist entry {}: {}",
+                        entry_id, error
+                    );
+                    false
+                }
+            })
+            .count();
+
+        Ok(delivered)
+    })
+    .await?;
+
+    Ok(web::HttpResponse::Ok().json(ApprovalSummary { approved, emailed }))
+}
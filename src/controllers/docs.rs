@@ -0,0 +1,216 @@
+use actix_web::{web, HttpRequest};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::Server;
+use utoipa::{Modify, OpenApi};
+
+use crate::controllers::{admin, health, invite, jwks, key, metrics, user, waitlist};
+use crate::models::invite::{Invitation, NewInviteForm, Referral};
+use crate::models::key::{CheckKeyForm, GenerateKeysForm, Key, RedeemedBy};
+use crate::models::user::{LoginUserForm, ManagedUser, NewUserForm, ViewableUser};
+use crate::models::waitlist::{
+    ApproveWaitlistForm, JoinWaitlistForm, WaitlistEntry, WaitlistStatus,
+};
+use crate::utils::errors::{FieldError, Problem};
+
+/// Stylesheet of the vendored Swagger UI release, see `assets/swagger-ui/LICENSE`
+const SWAGGER_UI_CSS: &str = include_str!("../../assets/swagger-ui/swagger-ui.css");
+
+/// Script of the vendored Swagger UI release
+const SWAGGER_UI_BUNDLE: &str = include_str!("../../assets/swagger-ui/swagger-ui-bundle.js");
+
+/// Page rendering the OpenAPI document with the vendored Swagger UI
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <title>API documentation</title>
+    <link rel="stylesheet" href="docs/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="docs"></div>
+    <script src="docs/swagger-ui-bundle.js"></script>
+    <script>
+      SwaggerUIBundle({ url: "openapi.json", dom_id: "#docs" });
+    </script>
+  </body>
+</html>
+"##;
+
+/// OpenAPI document of the routes mounted by `configure`, generated from the
+/// handlers and the models they read and respond with
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        user::create,
+        user::login,
+        user::get,
+        key::check_key,
+        invite::get,
+        invite::create,
+        waitlist::join,
+        waitlist::status,
+        admin::list_users,
+        admin::delete_user,
+        admin::disable_user,
+        admin::enable_user,
+        admin::restore_user,
+        admin::user_referrals,
+        admin::list_keys,
+        admin::generate_keys,
+        admin::import_keys,
+        admin::export_keys,
+        admin::revoke_key,
+        admin::key_redemptions,
+        admin::list_waitlist,
+        admin::approve_waitlist,
+        health::live,
+        health::ready,
+        metrics::get,
+        jwks::get,
+    ),
+    components(schemas(
+        NewUserForm,
+        LoginUserForm,
+        ViewableUser,
+        ManagedUser,
+        CheckKeyForm,
+        GenerateKeysForm,
+        Key,
+        RedeemedBy,
+        NewInviteForm,
+        Invitation,
+        Referral,
+        JoinWaitlistForm,
+        ApproveWaitlistForm,
+        WaitlistEntry,
+        WaitlistStatus,
+        admin::ApprovalSummary,
+        admin::ImportSummary,
+        Problem,
+        FieldError,
+    )),
+    modifiers(&BearerToken),
+    tags(
+        (name = "users", description = "Signing up, logging in and listing users"),
+        (name = "keys", description = "Checking beta keys"),
+        (name = "invites", description = "Invites minted by users for the people they refer"),
+        (name = "waitlist", description = "Waiting for a beta key"),
+        (name = "admin", description = "Managing users, beta keys and the waitlist"),
+        (name = "operations", description = "Probes, metrics and the keys tokens are verified with"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Declares the bearer tokens issued at signup and login
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let scheme = HttpBuilder::new()
+            .scheme(HttpAuthScheme::Bearer)
+            .bearer_format("JWT")
+            .build();
+
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme("bearer", SecurityScheme::Http(scheme));
+        }
+    }
+}
+
+/// Returns the OpenAPI document, relative to the scope the routes are mounted in
+pub async fn openapi(req: HttpRequest) -> web::HttpResponse {
+    let mut document = ApiDoc::openapi();
+
+    let base = req.path().trim_end_matches("/openapi.json");
+    if !base.is_empty() {
+        document.servers = Some(vec![Server::new(base)]);
+    }
+
+    web::HttpResponse::Ok().json(document)
+}
+
+/// Renders the OpenAPI document for people to browse and try out
+pub async fn ui() -> web::HttpResponse {
+    web::HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(DOCS_PAGE)
+}
+
+/// Serves the stylesheet the docs page is rendered with
+pub async fn stylesheet() -> web::HttpResponse {
+    web::HttpResponse::Ok()
+        .content_type("text/css; charset=utf-8")
+        .body(SWAGGER_UI_CSS)
+}
+
+/// Serves the script the docs page is rendered with
+pub async fn script() -> web::HttpResponse {
+    web::HttpResponse::Ok()
+        .content_type("application/javascript; charset=utf-8")
+        .body(SWAGGER_UI_BUNDLE)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::app::{self, Auth};
+    use crate::utils::errors::{error_codes, ValidationCode};
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+    use std::collections::HashSet;
+
+    #[actix_rt::test]
+    async fn it_serves_the_openapi_document() {
+        let auth = Auth::start(app::tests::settings()).await.unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .configure(|cfg| auth.data(cfg))
+                .service(web::scope("/auth").configure(crate::configure)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/auth/openapi.json")
+            .to_request();
+        let document: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        assert_eq!(document["servers"][0]["url"], "/auth");
+        assert_eq!(
+            document["paths"]["/login"]["post"]["requestBody"]["content"]["application/json"]
+                ["schema"]["$ref"],
+            "#/components/schemas/LoginUserForm"
+        );
+
+        // every operation can be told apart by code generators
+        let operations: Vec<_> = document["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .flat_map(|path| path.as_object().unwrap().values())
+            .map(|operation| operation["operationId"].as_str().unwrap())
+            .collect();
+        let unique: HashSet<_> = operations.iter().collect();
+        assert_eq!(unique.len(), operations.len());
+
+        let schemas = &document["components"]["schemas"];
+        let codes = &schemas["Problem"]["properties"]["code"];
+        assert_eq!(codes["enum"].as_array().unwrap().len(), error_codes().len());
+        let codes = &schemas["FieldError"]["properties"]["code"];
+        assert_eq!(
+            codes["enum"].as_array().unwrap().len(),
+            ValidationCode::ALL.len()
+        );
+
+        let req = test::TestRequest::get().uri("/auth/docs").to_request();
+        let page = test::read_response(&mut app, req).await;
+        let page = std::str::from_utf8(&page).unwrap();
+        assert!(page.contains("openapi.json"));
+        assert!(!page.contains("https://"));
+
+        for asset in &["swagger-ui.css", "swagger-ui-bundle.js"] {
+            let uri = format!("/auth/docs/{}", asset);
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+    }
+}
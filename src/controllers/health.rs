@@ -0,0 +1,133 @@
+use actix_web::web;
+use serde::Serialize;
+use std::time::Instant;
+
+use crate::db::Database;
+use crate::settings::Settings;
+use crate::utils::errors::ApiError;
+use crate::utils::keyring::{Keyring, KeyringStatus};
+
+/// Refreshes of the signing keys that may fail in a row before the service
+/// is taken out of rotation, as it would miss keys rotated in the meantime
+const MISSED_REFRESHES: u64 = 3;
+
+/// Outcome of one of the readiness checks, with details for dashboards
+#[derive(Serialize)]
+pub struct Check<T: Serialize> {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(flatten)]
+    pub detail: Option<T>,
+}
+
+impl<T: Serialize> Check<T> {
+    fn new(ok: bool, detail: T) -> Self {
+        Check {
+            ok,
+            error: None,
+            detail: Some(detail),
+        }
+    }
+
+    fn failed(error: ApiError) -> Self {
+        Check {
+            ok: false,
+            error: Some(error.to_string()),
+            detail: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Latency {
+    pub duration_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct Migrations {
+    pub pending: Vec<String>,
+}
+
+/// Checks run by the readiness probe
+#[derive(Serialize)]
+pub struct Checks {
+    pub database: Check<Latency>,
+    pub migrations: Check<Migrations>,
+    pub signing_keys: Check<KeyringStatus>,
+}
+
+/// Body of the readiness probe
+#[derive(Serialize)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub checks: Checks,
+}
+
+/// Liveness probe, answering as long as the server is running
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    responses((status = 200, description = "The server is running", body = Object))
+)]
+pub async fn live() -> web::HttpResponse {
+    web::HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Readiness probe, checking that a database connection can be used, that
+/// the migrations are up to date and that the signing keys were recently loaded
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Every check passed", body = Object),
+        (status = 503, description = "A check failed, the body tells which", body = Object),
+    )
+)]
+pub async fn ready(
+    db: web::Data<Database>,
+    keyring: web::Data<Keyring>,
+    settings: web::Data<Settings>,
+) -> web::HttpResponse {
+    let started = Instant::now();
+    let database = match db.run(|store| store.ping()).await {
+        Ok(()) => Check::new(
+            true,
+            Latency {
+                duration_ms: started.elapsed().as_millis() as u64,
+            },
+        ),
+        Err(error) => Check::failed(error),
+    };
+
+    let migrations = match db.run(|store| store.pending_migrations()).await {
+        Ok(pending) => Check::new(pending.is_empty(), Migrations { pending }),
+        Err(error) => Check::failed(error),
+    };
+
+    let signing_keys = match keyring.status() {
+        Ok(status) => {
+            let stale_after = settings.token.refresh_secs * MISSED_REFRESHES;
+            Check::new(status.loaded_within(stale_after), status)
+        }
+        Err(error) => Check::failed(error),
+    };
+
+    let ready = database.ok && migrations.ok && signing_keys.ok;
+    let readiness = Readiness {
+        status: if ready { "ready" } else { "unavailable" },
+        checks: Checks {
+            database,
+            migrations,
+            signing_keys,
+        },
+    };
+
+    if ready {
+        web::HttpResponse::Ok().json(readiness)
+    } else {
+        web::HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
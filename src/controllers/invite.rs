@@ -0,0 +1,82 @@
+use actix_web::web;
+use tracing::instrument;
+use validator::Validate;
+
+use crate::db::Database;
+use crate::models::invite::{Invitation, NewInviteForm};
+use crate::settings::Settings;
+use crate::utils::errors::ApiError;
+use crate::utils::json::Json;
+use crate::utils::mailer::{self, SharedMailer};
+use crate::utils::token::Token;
+
+///  Mints an invite for the current user, emailing it when an address is provided
+#[utoipa::path(
+    post,
+    path = "/invites",
+    operation_id = "create_invite",
+    tag = "invites",
+    request_body = NewInviteForm,
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "The minted invite and who it was emailed to", body = Invitation),
+        (status = 400, description = "VALIDATION_ERROR", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "INVITE_LIMIT_REACHED", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn create(
+    db: web::Data<Database>,
+    mailer: web::Data<SharedMailer>,
+    settings: web::Data<Settings>,
+    token: Token,
+    Json(invite_form): Json<NewInviteForm>,
+) -> Result<web::HttpResponse, ApiError> {
+    invite_form.validate()?;
+
+    let inviter = token.sub;
+    let limit = settings.invites.per_user;
+    let key = db
+        .run(move |store| store.mint_invite(inviter, limit))
+        .await?;
+
+    let emailed_to = match invite_form.email {
+        Some(to) => {
+            let email = mailer::invitation(&to, &token.email, &key.id);
+            let mailer = mailer.get_ref().clone();
+
+            // the invite is minted either way, so only report whether it was delivered
+            match web::block(move || mailer.send(&email)).await {
+                Ok(()) => Some(to),
+                Err(error) => {
+                    warn!("failed to email an invite of user {}: {}", inviter, error);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    Ok(web::HttpResponse::Created().json(Invitation { key, emailed_to }))
+}
+
+///  Returns the invites minted by the current user
+#[utoipa::path(
+    get,
+    path = "/invites",
+    operation_id = "get_invites",
+    tag = "invites",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Invites minted by the user", body = [Key]),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn get(db: web::Data<Database>, token: Token) -> Result<web::HttpResponse, ApiError> {
+    let inviter = token.sub;
+    let invites = db.run(move |store| store.invites_sent_by(inviter)).await?;
+
+    Ok(web::HttpResponse::Ok().json(invites))
+}
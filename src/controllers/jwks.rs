@@ -0,0 +1,29 @@
+use actix_web::web;
+
+use crate::settings::Settings;
+use crate::utils::errors::ApiError;
+use crate::utils::keyring::Keyring;
+
+/// Publishes the public keys tokens are verified with, so resource servers
+/// can verify them without sharing a secret
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    operation_id = "get_jwks",
+    tag = "operations",
+    responses((status = 200, description = "JSON Web Key Set of the verifying keys", body = Object))
+)]
+pub async fn get(
+    keyring: web::Data<Keyring>,
+    settings: web::Data<Settings>,
+) -> Result<web::HttpResponse, ApiError> {
+    let keys = keyring.jwks()?;
+
+    // keys are reloaded at this interval, so clients needn't fetch them more often
+    Ok(web::HttpResponse::Ok()
+        .header(
+            "cache-control",
+            format!("public, max-age={}", settings.token.refresh_secs),
+        )
+        .json(keys))
+}
@@ -1,22 +1,67 @@
 use actix_web::web;
+use tracing::instrument;
 
-use crate::db::DbPool;
-use crate::models::key::{CheckKeyForm, Key};
+use crate::db::Database;
+use crate::models::key::CheckKeyForm;
 use crate::utils::errors::ApiError;
+use crate::utils::json::Json;
 
-///  Creates a user in the database
+///  Checks whether a beta key can still be redeemed
+#[utoipa::path(
+    post,
+    path = "/keys",
+    tag = "keys",
+    request_body = CheckKeyForm,
+    responses(
+        (status = 200, description = "The key can be redeemed"),
+        (status = 400, description = "INVALID_BETA_KEY when the key is taken, revoked or unknown", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
 pub async fn check_key(
-    pool: web::Data<DbPool>,
-    web::Json(key_form): web::Json<CheckKeyForm>,
+    db: web::Data<Database>,
+    Json(key_form): Json<CheckKeyForm>,
 ) -> Result<web::HttpResponse, ApiError> {
-    let conn = pool.get()?;
+    // check if the provided key can still be redeemed
+    let is_available = db
+        .run(move |store| store.is_key_available(&key_form.key))
+        .await?;
 
-    // check if the provided key is available
-    let is_taken = web::block(move || Key::is_available(&key_form.key, &conn)).await?;
-
-    if is_taken {
-        Err(ApiError::InvalidBetaKey)
-    } else {
+    if is_available {
         Ok(web::HttpResponse::Ok().finish())
+    } else {
+        Err(ApiError::InvalidBetaKey)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::app::tests::Harness;
+    use crate::store::KeyStore;
+    use actix_web::{http::StatusCode, test, App};
+
+    #[actix_rt::test]
+    async fn it_checks_whether_a_key_can_be_redeemed() {
+        let harness = Harness::start().await;
+        let mut app =
+            test::init_service(App::new().configure(|cfg| harness.auth.configure(cfg))).await;
+        let key = harness.key(1);
+
+        let check = |key: uuid::Uuid| {
+            test::TestRequest::post()
+                .uri("/keys")
+                .set_json(&serde_json::json!({ "key": key }))
+                .to_request()
+        };
+
+        let response = test::call_service(&mut app, check(key.id)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let unknown = test::call_service(&mut app, check(uuid::Uuid::new_v4())).await;
+        assert_eq!(unknown.status(), StatusCode::BAD_REQUEST);
+
+        harness.store.revoke_key(key.id).unwrap();
+        let revoked = test::call_service(&mut app, check(key.id)).await;
+        assert_eq!(revoked.status(), StatusCode::BAD_REQUEST);
     }
 }
@@ -0,0 +1,27 @@
+use actix_web::web;
+
+use crate::db::Database;
+use crate::utils::errors::ApiError;
+use crate::utils::metrics::Metrics;
+
+/// Content type of the Prometheus text format
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+/// Returns the metrics of the service in the Prometheus text format
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    operation_id = "get_metrics",
+    tag = "operations",
+    responses((status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain"))
+)]
+pub async fn get(
+    db: web::Data<Database>,
+    metrics: web::Data<Metrics>,
+) -> Result<web::HttpResponse, ApiError> {
+    let rendered = metrics.render(db.pool_state())?;
+
+    Ok(web::HttpResponse::Ok()
+        .content_type(TEXT_FORMAT)
+        .body(rendered))
+}
@@ -1,53 +1,218 @@
-use actix_web::web;
+use actix_web::{web, HttpRequest};
+use tracing::instrument;
 
-use crate::db::DbPool;
-use crate::models::user::{LoginUserForm, NewUserForm, User};
-use crate::utils::{token::Token, errors::ApiError};
+use crate::db::Database;
+use crate::models::user::{LoginUserForm, NewUserForm, ViewableUser, USER_ROLE};
+use crate::settings::Settings;
+use crate::utils::json::Json;
+use crate::utils::keyring::Keyring;
+use crate::utils::metrics::Metrics;
+use crate::utils::pagination::ListQuery;
+use crate::utils::{errors::ApiError, token};
 
-///  Returns all users
-pub async fn get(pool: web::Data<DbPool>) -> Result<web::HttpResponse, ApiError> {
-    let conn = pool.get()?;
-    info!("asdfasdfasdf");
-    // get all users
-    let users = web::block(move || User::find_all_users(&conn)).await?;
+///  Returns a page of users
+#[utoipa::path(
+    get,
+    path = "/users",
+    operation_id = "get_users",
+    tag = "users",
+    params(ListQuery<ViewableUser>),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Page of users, counted in X-Total-Count and linked to its neighbours in Link", body = [ViewableUser]),
+        (status = 400, description = "VALIDATION_ERROR for invalid list parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "UNAUTHORIZED without a valid token", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn get(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    query: ListQuery<ViewableUser>,
+) -> Result<web::HttpResponse, ApiError> {
+    let params = query.into_inner();
+
+    // get a page of users
+    let page = db.run(move |store| store.list_users(&params)).await?;
 
-    // respond with all users
-    Ok(web::HttpResponse::Ok().json(users))
+    // respond with the page of users
+    Ok(page.respond(&req))
 }
 
 ///  Creates a user in the database
+#[utoipa::path(
+    post,
+    path = "/signup",
+    operation_id = "signup",
+    tag = "users",
+    request_body = NewUserForm,
+    responses(
+        (status = 200, description = "Token issued to the new user", body = String),
+        (status = 400, description = "VALIDATION_ERROR, INVALID_BETA_KEY when the key is unknown, revoked or expired, BETA_KEY_REQUIRED or SIGNUP_FAILED", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "EMAIL_DOMAIN_NOT_ALLOWED or REGISTRATION_CLOSED", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "EMAIL_TAKEN, or KEY_ALREADY_REDEEMED when the key is used up", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
 pub async fn create(
-    pool: web::Data<DbPool>,
-    web::Json(new_user): web::Json<NewUserForm>,
+    db: web::Data<Database>,
+    keyring: web::Data<Keyring>,
+    metrics: web::Data<Metrics>,
+    settings: web::Data<Settings>,
+    Json(new_user): Json<NewUserForm>,
 ) -> Result<web::HttpResponse, ApiError> {
-    let conn = pool.get()?;
-
-    // create user in database
-    let user = web::block(move || new_user.create(&conn)).await?;
+    // hash the password off the database threads, then create the user in
+    // the database, as far as the registration policy allows
+    let policy = settings.registration.clone();
+    let prepared = {
+        let policy = policy.clone();
+        let span = tracing::Span::current();
+        web::block(move || span.in_scope(|| new_user.prepare(USER_ROLE, &policy))).await?
+    };
+    let user = db.run(move |store| prepared.insert(&policy, store)).await?;
+    metrics.record_signup();
 
     // create token for the user
-    let token = Token::from_user(&user).encode();
+    let token = keyring.encode(&token::issue(&user, &settings.token))?;
 
     // respond with the token instead of the user
     Ok(web::HttpResponse::Ok().json(token))
 }
 
 /// Creates a jwt token for the user to use for requests
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "users",
+    request_body = LoginUserForm,
+    responses(
+        (status = 200, description = "Token issued to the user", body = String),
+        (status = 400, description = "INVALID_LOGIN when the email and password don't match", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "ACCOUNT_DISABLED", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
 pub async fn login(
-    pool: web::Data<DbPool>,
-    web::Json(creds): web::Json<LoginUserForm>,
+    db: web::Data<Database>,
+    keyring: web::Data<Keyring>,
+    metrics: web::Data<Metrics>,
+    settings: web::Data<Settings>,
+    Json(creds): Json<LoginUserForm>,
 ) -> Result<web::HttpResponse, ApiError> {
-    let conn = pool.get()?;
-
-    // Verifies the users login information
-    let valid_user = web::block(move || creds.verify_user(&conn)).await?;
+    // Verifies the users login information, checking the password off the
+    // database threads
+    let email = creds.email.clone();
+    let valid_user = async {
+        let user = db
+            .run(move |store| store.find_user_by_email(&email))
+            .await?;
+        let span = tracing::Span::current();
+        Ok(web::block(move || span.in_scope(|| creds.check_password(user))).await?)
+    }
+    .await;
+    metrics.record_login(&valid_user);
+    let valid_user = valid_user?;
 
     match valid_user {
         Some(u) => {
-            let token = Token::from_user(&u).encode();
+            let token = keyring.encode(&token::issue(&u, &settings.token))?;
 
             return Ok(web::HttpResponse::Ok().json(token));
         }
         None => Err(ApiError::InvalidLogin),
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use crate::app::tests::{settings, Harness};
+    use crate::models::registration::RegistrationPolicy;
+    use crate::settings::Settings;
+    use actix_web::dev::Service;
+    use actix_web::{http::StatusCode, test, App};
+    use serde_json::json;
+
+    #[actix_rt::test]
+    async fn it_signs_up_with_a_key_and_logs_in() {
+        let harness = Harness::start_with(Settings {
+            registration: RegistrationPolicy::default(),
+            ..settings()
+        })
+        .await;
+        let mut app =
+            test::init_service(App::new().configure(|cfg| harness.auth.configure(cfg))).await;
+        let key = harness.key(1);
+
+        let signup = |email: &str, key: Option<uuid::Uuid>| {
+            test::TestRequest::post()
+                .uri("/signup")
+                .set_json(&json!({"email": email, "password": "password", "key_id": key}))
+                .to_request()
+        };
+
+        let keyless = signup("new@app.com", None);
+        let body: serde_json::Value = test::read_response_json(&mut app, keyless).await;
+        assert_eq!(body["code"], "BETA_KEY_REQUIRED");
+
+        let redeeming = signup("new@app.com", Some(key.id));
+        let response = test::call_service(&mut app, redeeming).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let used_up = signup("other@app.com", Some(key.id));
+        let response = test::call_service(&mut app, used_up).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(response).await).unwrap();
+        assert_eq!(body["code"], "KEY_ALREADY_REDEEMED");
+
+        let unknown = signup("other@app.com", Some(uuid::Uuid::new_v4()));
+        let body: serde_json::Value = test::read_response_json(&mut app, unknown).await;
+        assert_eq!(body["code"], "INVALID_BETA_KEY");
+
+        let taken = signup("new@app.com", Some(harness.key(1).id));
+        let response = test::call_service(&mut app, taken).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let login = |password: &str| {
+            test::TestRequest::post()
+                .uri("/login")
+                .set_json(&json!({"email": "new@app.com", "password": password}))
+                .to_request()
+        };
+
+        let body: serde_json::Value = test::read_response_json(&mut app, login("wrong")).await;
+        assert_eq!(body["code"], "INVALID_LOGIN");
+
+        let token: String = test::read_response_json(&mut app, login("password")).await;
+        assert!(!token.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn it_lists_users_to_holders_of_a_token() {
+        let harness = Harness::start().await;
+        let mut app =
+            test::init_service(App::new().configure(|cfg| harness.auth.configure(cfg))).await;
+        let first = harness.user("first@app.com");
+        harness.user("second@app.com");
+
+        let users = test::TestRequest::get()
+            .uri("/users?sort=email")
+            .header("authorization", format!("Bearer {}", harness.token(&first)))
+            .to_request();
+        let response = test::call_service(&mut app, users).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("x-total-count").unwrap(), "2");
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(response).await).unwrap();
+        assert_eq!(body[0]["email"], "first@app.com");
+        assert_eq!(body[1]["email"], "second@app.com");
+
+        // the validator's error escapes the service, to be rendered by the server
+        let anonymous = test::TestRequest::get().uri("/users").to_request();
+        let error = app.call(anonymous).await.err().unwrap();
+        assert_eq!(
+            error.as_response_error().error_response().status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
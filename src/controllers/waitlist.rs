@@ -0,0 +1,55 @@
+use actix_web::web;
+use tracing::instrument;
+
+use crate::db::Database;
+use crate::models::waitlist::JoinWaitlistForm;
+use crate::settings::Settings;
+use crate::utils::errors::ApiError;
+use crate::utils::json::Json;
+
+///  Adds an email to the waitlist and returns its place in the queue
+#[utoipa::path(
+    post,
+    path = "/waitlist",
+    operation_id = "join_waitlist",
+    tag = "waitlist",
+    request_body = JoinWaitlistForm,
+    responses(
+        (status = 201, description = "Place of the email in the queue", body = WaitlistStatus),
+        (status = 400, description = "VALIDATION_ERROR", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "ALREADY_ON_WAITLIST, unless anti-enumeration answers it like a new join", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn join(
+    db: web::Data<Database>,
+    settings: web::Data<Settings>,
+    Json(join_form): Json<JoinWaitlistForm>,
+) -> Result<web::HttpResponse, ApiError> {
+    let policy = settings.registration.clone();
+    let status = db.run(move |store| join_form.join(&policy, store)).await?;
+
+    Ok(web::HttpResponse::Created().json(status))
+}
+
+///  Returns the place in the queue of the entry with the provided token
+#[utoipa::path(
+    get,
+    path = "/waitlist/{token}",
+    operation_id = "waitlist_status",
+    tag = "waitlist",
+    params(("token" = String, Path, description = "Token returned when joining the waitlist")),
+    responses(
+        (status = 200, description = "Place of the entry in the queue", body = WaitlistStatus),
+        (status = 404, description = "NOT_FOUND", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn status(
+    db: web::Data<Database>,
+    token: web::Path<uuid::Uuid>,
+) -> Result<web::HttpResponse, ApiError> {
+    let status = db.run(move |store| store.waitlist_status(&token)).await?;
+
+    Ok(web::HttpResponse::Ok().json(status))
+}
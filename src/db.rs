@@ -1,15 +1,492 @@
+use diesel::backend::UsesAnsiSavepointSyntax;
+use diesel::connection::{
+    AnsiTransactionManager, Connection, SimpleConnection, TransactionManager,
+};
+use diesel::deserialize::{Queryable, QueryableByName};
 use diesel::pg::PgConnection;
-use diesel::r2d2::ConnectionManager;
+use diesel::query_builder::{AsQuery, QueryFragment, QueryId};
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, ManageConnection, PooledConnection};
+use diesel::result::{ConnectionError, ConnectionResult, QueryResult};
+use diesel::sql_types::HasSqlType;
+use futures::channel::oneshot;
+use std::ops::Deref;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use std::env;
+use crate::settings::DatabaseSettings;
+use crate::store::{PoolState, SharedStore, Store};
+use crate::utils::errors::{ApiError, InternalCode};
 
 pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
-/// Creates a database pool
-pub fn create_pool() -> DbPool {
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
+/// Call queued for one of the database threads
+type Job = Box<dyn FnOnce(&dyn Store) + Send>;
+
+/// Per connection settings applied to every Postgres connection the pool opens
+#[derive(Debug)]
+struct Session {
+    statement_timeout_ms: u64,
+}
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for Session {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute(&format!(
+            "set statement_timeout = {}",
+            self.statement_timeout_ms
+        ))
+        .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Returns a pool builder sized and timed out as configured
+pub fn pool_builder<M: ManageConnection>(settings: &DatabaseSettings) -> r2d2::Builder<M> {
+    let secs = |secs: u64| Some(secs).filter(|secs| *secs > 0).map(Duration::from_secs);
+
     r2d2::Pool::builder()
+        .max_size(settings.max_connections)
+        .min_idle(settings.min_idle)
+        .connection_timeout(Duration::from_secs(settings.connect_timeout_secs))
+        .idle_timeout(secs(settings.idle_timeout_secs))
+        .max_lifetime(secs(settings.max_lifetime_secs))
+}
+
+/// Creates a database pool
+pub fn create_pool(settings: &DatabaseSettings) -> Result<DbPool, r2d2::Error> {
+    let manager = ConnectionManager::<PgConnection>::new(settings.url.as_str());
+    pool_builder(settings)
+        .connection_customizer(Box::new(Session {
+            statement_timeout_ms: settings.statement_timeout_ms,
+        }))
         .build(manager)
-        .expect("Failed to create pool.")
+}
+
+/// Connections a diesel backed store runs its calls on, checked out of the
+/// pool for every call unless a unit of work pinned one for its steps
+pub struct Connections<C: Connection + 'static> {
+    pool: r2d2::Pool<ConnectionManager<C>>,
+    pinned: Option<Arc<Mutex<Option<Pooled<C>>>>>,
+}
+
+/// Connection checked out of a pool
+type Pooled<C> = PooledConnection<ConnectionManager<C>>;
+
+impl<C: Connection + 'static> Clone for Connections<C> {
+    fn clone(&self) -> Self {
+        Connections {
+            pool: self.pool.clone(),
+            pinned: self.pinned.clone(),
+        }
+    }
+}
+
+/// Connection a single store call runs on, either checked out of the pool for
+/// the call or borrowed from the unit of work the call is part of, which gets
+/// it back once the call is done
+pub struct StoreConnection<'a, C: Connection + 'static> {
+    conn: Option<Pooled<C>>,
+    pinned: Option<&'a Mutex<Option<Pooled<C>>>>,
+}
+
+impl<C: Connection + 'static> Deref for StoreConnection<'_, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        self.conn
+            .as_ref()
+            .expect("store connections hold their connection until dropped")
+    }
+}
+
+impl<C: Connection + 'static> Drop for StoreConnection<'_, C> {
+    fn drop(&mut self) {
+        if let (Some(pinned), Some(conn)) = (self.pinned, self.conn.take()) {
+            *pinned.lock().unwrap_or_else(PoisonError::into_inner) = Some(conn);
+        }
+    }
+}
+
+impl<C> SimpleConnection for StoreConnection<'_, C>
+where
+    C: Connection + 'static,
+{
+    fn batch_execute(&self, query: &str) -> QueryResult<()> {
+        (**self).batch_execute(query)
+    }
+}
+
+/// Lets queries run on the connection directly, like they do on pooled ones
+impl<C> Connection for StoreConnection<'_, C>
+where
+    C: Connection<TransactionManager = AnsiTransactionManager> + 'static,
+    C::Backend: UsesAnsiSavepointSyntax,
+{
+    type Backend = C::Backend;
+    type TransactionManager = C::TransactionManager;
+
+    fn establish(_: &str) -> ConnectionResult<Self> {
+        Err(ConnectionError::BadConnection(String::from(
+            "Store connections are checked out of a pool",
+        )))
+    }
+
+    fn execute(&self, query: &str) -> QueryResult<usize> {
+        (**self).execute(query)
+    }
+
+    fn query_by_index<T, U>(&self, source: T) -> QueryResult<Vec<U>>
+    where
+        T: AsQuery,
+        T::Query: QueryFragment<Self::Backend> + QueryId,
+        Self::Backend: HasSqlType<T::SqlType>,
+        U: Queryable<T::SqlType, Self::Backend>,
+    {
+        (**self).query_by_index(source)
+    }
+
+    fn query_by_name<T, U>(&self, source: &T) -> QueryResult<Vec<U>>
+    where
+        T: QueryFragment<Self::Backend> + QueryId,
+        U: QueryableByName<Self::Backend>,
+    {
+        (**self).query_by_name(source)
+    }
+
+    fn execute_returning_count<T>(&self, source: &T) -> QueryResult<usize>
+    where
+        T: QueryFragment<Self::Backend> + QueryId,
+    {
+        (**self).execute_returning_count(source)
+    }
+
+    fn transaction_manager(&self) -> &Self::TransactionManager {
+        (**self).transaction_manager()
+    }
+}
+
+impl<C> Connections<C>
+where
+    C: Connection<TransactionManager = AnsiTransactionManager> + 'static,
+    C::Backend: UsesAnsiSavepointSyntax,
+{
+    /// Creates connections checked out of the provided pool
+    pub fn new(pool: r2d2::Pool<ConnectionManager<C>>) -> Self {
+        Connections { pool, pinned: None }
+    }
+
+    /// Returns the pool connections are checked out from
+    pub fn pool(&self) -> &r2d2::Pool<ConnectionManager<C>> {
+        &self.pool
+    }
+
+    /// Returns the connection the next call runs on
+    pub fn get(&self) -> Result<StoreConnection<'_, C>, ApiError> {
+        let pinned = match &self.pinned {
+            Some(pinned) => pinned,
+            None => {
+                return Ok(StoreConnection {
+                    conn: Some(self.pool.get()?),
+                    pinned: None,
+                })
+            }
+        };
+
+        let conn = pinned
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .ok_or_else(|| {
+                ApiError::InternalServerError(
+                    InternalCode::Store,
+                    String::from("The connection of the unit of work is already in use"),
+                )
+            })?;
+
+        Ok(StoreConnection {
+            conn: Some(conn),
+            pinned: Some(pinned),
+        })
+    }
+
+    /// Pins a connection and runs the steps with connections handing out only
+    /// that one, in a transaction begun with the provided statement, or in a
+    /// savepoint when the steps are part of an outer unit of work. The
+    /// transaction commits once the steps succeed and rolls back when they fail
+    pub fn unit_of_work<F>(&self, begin: &str, steps: F) -> Result<(), ApiError>
+    where
+        F: FnOnce(&Self) -> Result<(), ApiError>,
+    {
+        let connections = match &self.pinned {
+            Some(_) => self.clone(),
+            None => Connections {
+                pool: self.pool.clone(),
+                pinned: Some(Arc::new(Mutex::new(Some(self.pool.get()?)))),
+            },
+        };
+
+        {
+            let conn = connections.get()?;
+            let manager = conn.transaction_manager();
+            if TransactionManager::<C>::get_transaction_depth(manager) == 0 {
+                manager.begin_transaction_sql(&*conn, begin)?;
+            } else {
+                manager.begin_transaction(&*conn)?;
+            }
+        }
+
+        // the connection is only borrowed to begin and end the transaction,
+        // so the calls of the steps can borrow it in turn
+        let result = steps(&connections);
+
+        let conn = connections.get()?;
+        let manager = conn.transaction_manager();
+        match result {
+            Ok(()) => Ok(manager.commit_transaction(&*conn)?),
+            Err(error) => {
+                manager.rollback_transaction(&*conn)?;
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Runs store calls on threads of its own, one per pooled connection, so
+/// requests await database work without tying up the server's threads
+#[derive(Clone)]
+pub struct Database {
+    jobs: Sender<Job>,
+    queue_timeout: Duration,
+    store: SharedStore,
+}
+
+impl Database {
+    /// Starts the threads running calls against the store
+    pub fn start(store: SharedStore, settings: &DatabaseSettings) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for index in 0..settings.max_connections {
+            let store = store.clone();
+            let queue = queue.clone();
+
+            thread::Builder::new()
+                .name(format!("db-{}", index))
+                .spawn(move || work(store, queue))
+                .expect("failed to spawn database thread");
+        }
+
+        Database {
+            jobs,
+            queue_timeout: Duration::from_millis(settings.queue_timeout_ms),
+            store,
+        }
+    }
+
+    /// Returns the state of the connection pool without queueing behind
+    /// other calls, so it can be reported while the database is saturated
+    pub fn pool_state(&self) -> Option<PoolState> {
+        self.store.pool_state()
+    }
+
+    /// Runs the call on a database thread and resolves with its result, or
+    /// with a busy error when it waited in the queue for too long
+    pub async fn run<F, T>(&self, call: F) -> Result<T, ApiError>
+    where
+        F: FnOnce(&dyn Store) -> Result<T, ApiError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let queued_at = Instant::now();
+        let queue_timeout = self.queue_timeout;
+
+        // the call runs within the span of the request that queued it
+        let span = tracing::info_span!("database", queued_ms = tracing::field::Empty);
+
+        let job: Job = Box::new(move |store| {
+            let result = span.in_scope(|| {
+                span.record("queued_ms", queued_at.elapsed().as_millis() as u64);
+
+                if queued_at.elapsed() > queue_timeout {
+                    Err(ApiError::DatabaseBusy)
+                } else {
+                    call(store)
+                }
+            });
+
+            // close the span before the request can finish without it
+            drop(span);
+
+            // the request may have been dropped in the meantime
+            let _ = sender.send(result);
+        });
+
+        self.jobs.send(job).map_err(|_| stopped())?;
+
+        receiver.await.map_err(|_| stopped())?
+    }
+}
+
+/// Runs queued calls until every handle to the database is dropped
+fn work(store: SharedStore, queue: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        let job = match queue.lock() {
+            Ok(queue) => queue.recv(),
+            Err(_) => return,
+        };
+
+        match job {
+            Ok(job) => job(store.as_ref()),
+            Err(_) => return,
+        }
+    }
+}
+
+/// Error returned when the database threads are gone
+fn stopped() -> ApiError {
+    ApiError::InternalServerError(
+        InternalCode::DatabaseStopped,
+        String::from("The database is no longer running"),
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::store::memory::MemoryStore;
+    use crate::store::postgres::PgStore;
+    use crate::store::SchemaStore;
+    use futures::executor::block_on;
+    use std::sync::Once;
+
+    /// Gives the connections of a test pool a Postgres schema of their own,
+    /// dropped with everything written to it once the pool is dropped
+    #[derive(Debug)]
+    struct TestSchema {
+        url: String,
+        name: String,
+    }
+
+    impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for TestSchema {
+        fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+            // extensions stay in the public schema, shared by every test
+            conn.batch_execute(&format!("set search_path = {}, public", self.name))
+                .map_err(diesel::r2d2::Error::QueryError)
+        }
+    }
+
+    impl Drop for TestSchema {
+        fn drop(&mut self) {
+            if let Ok(conn) = PgConnection::establish(&self.url) {
+                let _ = conn.batch_execute(&format!("drop schema if exists {} cascade", self.name));
+            }
+        }
+    }
+
+    /// Creates a pool for a throwaway schema, migrated to the latest version,
+    /// in the database configured through the environment
+    pub fn create_pool() -> DbPool {
+        static EXTENSIONS: Once = Once::new();
+
+        let settings = Settings::from_env().expect("invalid settings");
+        let url = settings.database.url;
+        let name = format!("test_{}", uuid::Uuid::new_v4().simple());
+
+        let conn = PgConnection::establish(&url).expect("Failed to connect.");
+        EXTENSIONS.call_once(|| {
+            conn.batch_execute("create extension if not exists \"uuid-ossp\" schema public")
+                .expect("Failed to create extensions.");
+        });
+        conn.batch_execute(&format!("create schema {}", name))
+            .expect("Failed to create schema.");
+
+        let pool_settings = DatabaseSettings {
+            max_connections: 5,
+            min_idle: Some(1),
+            ..Default::default()
+        };
+        let pool = pool_builder(&pool_settings)
+            .connection_customizer(Box::new(TestSchema {
+                url: url.clone(),
+                name,
+            }))
+            .build(ConnectionManager::new(url))
+            .expect("Failed to create pool.");
+
+        PgStore::new(pool.clone())
+            .migrate()
+            .expect("Failed to migrate schema.");
+
+        pool
+    }
+
+    /// Creates a Postgres store for a throwaway schema in the database
+    /// configured through the environment
+    pub fn create_store() -> PgStore {
+        PgStore::new(create_pool())
+    }
+
+    #[test]
+    fn it_sets_up_every_pooled_connection() {
+        let settings = DatabaseSettings {
+            url: Settings::from_env().expect("invalid settings").database.url,
+            max_connections: 1,
+            statement_timeout_ms: 2_500,
+            ..Default::default()
+        };
+        let conn = super::create_pool(&settings).unwrap().get().unwrap();
+
+        let setting = |name: &str| {
+            use diesel::RunQueryDsl;
+
+            diesel::select(diesel::dsl::sql::<diesel::sql_types::Text>(&format!(
+                "current_setting('{}')",
+                name
+            )))
+            .get_result::<String>(&conn)
+            .unwrap()
+        };
+        assert_eq!(setting("statement_timeout"), "2500ms");
+    }
+
+    #[test]
+    fn it_runs_calls_on_database_threads() {
+        let settings = DatabaseSettings {
+            max_connections: 2,
+            ..Default::default()
+        };
+        let db = Database::start(Arc::new(MemoryStore::new()), &settings);
+
+        let thread_name = block_on(db.run(|_| Ok(thread::current().name().map(String::from))));
+        assert!(thread_name.unwrap().unwrap().starts_with("db-"));
+
+        match block_on(db.run(|store| store.find_user(1))) {
+            Err(ApiError::NotFound) => {}
+            other => panic!("expected not found error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_sheds_calls_that_waited_too_long() {
+        let settings = DatabaseSettings {
+            max_connections: 1,
+            queue_timeout_ms: 10,
+            ..Default::default()
+        };
+        let db = Database::start(Arc::new(MemoryStore::new()), &settings);
+
+        let slow = db.run(|_| {
+            thread::sleep(Duration::from_millis(50));
+            Ok(())
+        });
+        let queued = db.run(|_| Ok(()));
+
+        let (slow, queued) = block_on(futures::future::join(slow, queued));
+        assert!(slow.is_ok());
+        match queued {
+            Err(ApiError::DatabaseBusy) => {}
+            other => panic!("expected busy error, got {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,63 @@
+//! Users, beta keys and tokens for actix-web services.
+//!
+//! `Auth::start` opens the configured store, `Auth::data` registers what the
+//! routes need and `Auth::routes` mounts them, while `validator` and `Token`
+//! let other routes require the tokens issued here:
+//!
+//! ```no_run
+//! use actix_web::{web, App, HttpServer};
+//! use actix_web_httpauth::middleware::HttpAuthentication;
+//! use auth::{Auth, Settings, Token};
+//!
+//! #[actix_rt::main]
+//! async fn main() -> std::io::Result<()> {
+//!     let settings = Settings::load().expect("invalid settings");
+//!     let auth = Auth::start(settings).await.expect("failed to start");
+//!
+//!     HttpServer::new(move || {
+//!         App::new()
+//!             .configure(|cfg| auth.data(cfg))
+//!             .service(web::scope("/auth").configure(|cfg| auth.routes(cfg)))
+//!             .service(
+//!                 web::resource("/me")
+//!                     .wrap(HttpAuthentication::bearer(auth::validator))
+//!                     .to(|token: Token| async move { token.email }),
+//!             )
+//!     })
+//!     .bind("0.0.0.0:8080")?
+//!     .run()
+//!     .await
+//! }
+//! ```
+
+// needed for the musl docker build
+extern crate openssl;
+
+#[macro_use]
+extern crate diesel;
+
+#[macro_use]
+extern crate diesel_migrations;
+
+#[macro_use]
+extern crate validator_derive;
+
+#[macro_use]
+extern crate log;
+
+pub mod app;
+pub mod controllers;
+pub mod db;
+pub mod models;
+pub mod routes;
+pub mod schema;
+pub mod settings;
+pub mod store;
+pub mod utils;
+
+pub use crate::app::{Auth, StartError};
+pub use crate::routes::{admin_validator, configure, validator};
+pub use crate::settings::Settings;
+pub use crate::utils::errors::ApiError;
+pub use crate::utils::keyring::Keyring;
+pub use crate::utils::token::Token;
@@ -1,54 +1,67 @@
-// needed for the musl docker build
-extern crate openssl;
-
-#[macro_use]
-extern crate diesel;
-
-#[macro_use]
-extern crate validator_derive;
-
 #[macro_use]
 extern crate log;
 
-use actix_cors::Cors;
-use actix_web::{App, HttpServer, http::header};
-use env_logger::{Env, Target};
+use actix_web::{App, HttpServer};
+use std::process;
 
-mod controllers;
-mod db;
-mod models;
-mod routes;
-mod schema;
-mod utils;
-
-use crate::routes::define_routes;
+use auth::settings::{LogSettings, TelemetrySettings};
+use auth::utils::cors::Cors;
+use auth::utils::i18n::Localization;
+use auth::utils::logging;
+use auth::utils::request_id::RequestTracing;
+use auth::{Auth, Settings};
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    // configure database connection
-    let pool = db::create_pool();
+    // load settings, refusing to start when they are missing or invalid
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(error) => {
+            let _ = logging::init(&LogSettings::default(), &TelemetrySettings::default());
+            error!("{}", error);
+            process::exit(1);
+        }
+    };
+
+    // configure logging and trace export, flushing spans once the server stops
+    let _telemetry = match logging::init(&settings.log, &settings.telemetry) {
+        Ok(telemetry) => telemetry,
+        Err(error) => {
+            eprintln!("Invalid logging configuration: {}", error);
+            process::exit(1);
+        }
+    };
+
+    // configure cross origin requests
+    let cors = match Cors::new(&settings.cors.default, &settings.cors.scopes) {
+        Ok(cors) => cors,
+        Err(error) => {
+            error!("Invalid CORS configuration: {}", error);
+            process::exit(1);
+        }
+    };
+
+    let bind = settings.server.bind.clone();
 
-    // configure logging
-    env_logger::from_env(Env::default().default_filter_or("info"))
-        .target(Target::Stdout)
-        .init();
+    // configure storage, signing keys and email delivery
+    let auth = match Auth::start(settings).await {
+        Ok(auth) => auth,
+        Err(error) => {
+            error!("{}", error);
+            process::exit(1);
+        }
+    };
 
     // configure server
     HttpServer::new(move || {
         App::new()
-            .wrap(
-                Cors::new()
-                    .allowed_origin("http://localhost:3000")
-                    .allowed_methods(vec!["GET", "POST"])
-                    .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
-                    .allowed_header(header::CONTENT_TYPE)
-                    .max_age(3600)
-                    .finish(),
-            )
-            .data(pool.clone())
-            .configure(define_routes)
+            .wrap(cors.clone())
+            .wrap(auth.metrics.clone())
+            .wrap(Localization)
+            .wrap(RequestTracing)
+            .configure(|cfg| auth.configure(cfg))
     })
-    .bind("0.0.0.0:8080")?
+    .bind(bind)?
     .run()
     .await
 }
@@ -1,2 +1,6 @@
+pub mod invite;
 pub mod key;
+pub mod registration;
+pub mod signing_key;
 pub mod user;
+pub mod waitlist;
@@ -0,0 +1,226 @@
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{Int4, Nullable, Timestamp, Varchar};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::models::key::{Key, NewKey};
+use crate::models::user::User;
+use crate::utils::errors::ApiError;
+
+/// Label given to keys minted as personal invitations
+pub const INVITE_LABEL: &str = "invite";
+
+/// Form used by users to invite someone, optionally emailing them the key
+#[derive(Deserialize, Validate, ToSchema, Debug, Default)]
+pub struct NewInviteForm {
+    #[validate(email(code = "INVALID_EMAIL"))]
+    pub email: Option<String>,
+}
+
+/// Representation of a minted invite and who it was emailed to
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Invitation {
+    pub key: Key,
+    pub emailed_to: Option<String>,
+}
+
+/// Representation of a user and everyone they referred, directly or not
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct Referral {
+    pub id: i32,
+    pub email: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub referrals: Vec<Referral>,
+}
+
+/// Row returned by the recursive referral query
+#[derive(QueryableByName, Debug)]
+pub(crate) struct ReferralRow {
+    #[sql_type = "Int4"]
+    pub id: i32,
+    #[sql_type = "Varchar"]
+    pub email: String,
+    #[sql_type = "Nullable<Int4>"]
+    pub invited_by: Option<i32>,
+    #[sql_type = "Timestamp"]
+    pub created_at: chrono::NaiveDateTime,
+}
+
+pub struct Invite;
+
+impl Invite {
+    /// Mints a single use key on behalf of the inviter, as long as they have
+    /// not reached the provided limit
+    pub fn mint(inviter: i32, limit: i64, conn: &PgConnection) -> Result<Key, ApiError> {
+        use crate::schema::keys::dsl::*;
+        use crate::schema::users::dsl::users;
+
+        conn.transaction(|| {
+            // lock the inviter so concurrent requests can't exceed the limit
+            users.find(inviter).for_update().first::<User>(conn)?;
+
+            let minted = keys
+                .filter(created_by.eq(inviter))
+                .count()
+                .get_result::<i64>(conn)?;
+
+            if minted >= limit {
+                return Err(ApiError::InviteLimitReached);
+            }
+
+            let new_key = NewKey {
+                id: uuid::Uuid::new_v4(),
+                label: Some(INVITE_LABEL.to_string()),
+                max_redemptions: Some(1),
+                created_by: Some(inviter),
+                ..Default::default()
+            };
+
+            let key = diesel::insert_into(keys)
+                .values(&new_key)
+                .get_result::<Key>(conn)?;
+
+            Ok(key)
+        })
+    }
+
+    /// Returns every invite minted by the user, newest first
+    pub fn sent_by(inviter: i32, conn: &PgConnection) -> Result<Vec<Key>, ApiError> {
+        use crate::schema::keys::dsl::*;
+
+        let invites = keys
+            .filter(created_by.eq(inviter))
+            .order(created_at.desc())
+            .load::<Key>(conn)?;
+
+        Ok(invites)
+    }
+
+    /// Returns the tree of users referred by the user, directly or through
+    /// the people they invited
+    pub fn referral_tree(user_id: i32, conn: &PgConnection) -> Result<Vec<Referral>, ApiError> {
+        let rows = diesel::sql_query(
+            "with recursive tree as ( \
+                select id, email, invited_by, created_at from users where invited_by = $1 \
+                union all \
+                select u.id, u.email, u.invited_by, u.created_at \
+                from users u join tree t on u.invited_by = t.id \
+            ) select id, email, invited_by, created_at from tree",
+        )
+        .bind::<Int4, _>(user_id)
+        .load::<ReferralRow>(conn)?;
+
+        Ok(build_tree(user_id, rows))
+    }
+}
+
+/// Nests the referral rows under the users who invited them
+pub(crate) fn build_tree(root: i32, rows: Vec<ReferralRow>) -> Vec<Referral> {
+    let mut children: HashMap<i32, Vec<ReferralRow>> = HashMap::new();
+
+    for row in rows {
+        if let Some(parent) = row.invited_by {
+            children.entry(parent).or_default().push(row);
+        }
+    }
+
+    fn nest(parent: i32, children: &mut HashMap<i32, Vec<ReferralRow>>) -> Vec<Referral> {
+        let mut rows = children.remove(&parent).unwrap_or_default();
+        rows.sort_by_key(|row| row.id);
+
+        rows.into_iter()
+            .map(|row| Referral {
+                id: row.id,
+                email: row.email,
+                created_at: row.created_at,
+                referrals: nest(row.id, children),
+            })
+            .collect()
+    }
+
+    nest(root, &mut children)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::tests::create_store;
+    use crate::models::registration::RegistrationPolicy;
+    use crate::models::user::NewUserForm;
+    use crate::store::postgres::PgStore;
+
+    /// Creates a user with a unique email redeeming the provided key
+    fn sign_up(key: &uuid::Uuid, store: &PgStore) -> User {
+        let new_user = NewUserForm {
+            email: format!("{}@bar.com", &uuid::Uuid::new_v4().to_string()[..8]),
+            password: "password".to_string(),
+            key_id: Some(*key),
+            locale: None,
+        };
+
+        new_user
+            .create(&RegistrationPolicy::default(), store)
+            .expect("failed to create user")
+    }
+
+    #[test]
+    fn it_caps_invites_per_user() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let root_key = Key::generate(
+            &crate::models::key::GenerateKeysForm {
+                count: 1,
+                ..Default::default()
+            },
+            &conn,
+        )
+        .unwrap()
+        .remove(0);
+        let inviter = sign_up(&root_key.id, &store);
+
+        assert!(Invite::mint(inviter.id, 2, &conn).is_ok());
+        assert!(Invite::mint(inviter.id, 2, &conn).is_ok());
+
+        match Invite::mint(inviter.id, 2, &conn) {
+            Err(ApiError::InviteLimitReached) => {}
+            other => panic!("expected invite limit error, got {:?}", other),
+        }
+
+        assert_eq!(Invite::sent_by(inviter.id, &conn).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn it_tracks_referral_tree() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let root_key = Key::generate(
+            &crate::models::key::GenerateKeysForm {
+                count: 1,
+                ..Default::default()
+            },
+            &conn,
+        )
+        .unwrap()
+        .remove(0);
+        let root = sign_up(&root_key.id, &store);
+
+        let invite = Invite::mint(root.id, 5, &conn).unwrap();
+        let child = sign_up(&invite.id, &store);
+        assert_eq!(child.invited_by, Some(root.id));
+
+        let invite = Invite::mint(child.id, 5, &conn).unwrap();
+        let grandchild = sign_up(&invite.id, &store);
+
+        let tree = Invite::referral_tree(root.id, &conn).unwrap();
+
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].id, child.id);
+        assert_eq!(tree[0].referrals.len(), 1);
+        assert_eq!(tree[0].referrals[0].id, grandchild.id);
+    }
+}
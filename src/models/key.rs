@@ -1,66 +1,424 @@
-use diesel::pg::PgConnection;
+use diesel::pg::{Pg, PgConnection};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
 
-use crate::utils::errors::ApiError;
-use crate::models::user::User;
-use crate::schema::keys;
+use crate::schema::{keys, redemptions, users};
+use crate::utils::errors::{ApiError, FieldError, ValidationCode};
+use crate::utils::pagination::{sort_by, Cursor, ListParams, Listable, Page};
+
+/// Format timestamps are written in when used as a keyset cursor or exported
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
 
 /// Database representation of a Beta Key
-#[derive(Identifiable, Insertable, Queryable, Serialize, Debug)]
+#[derive(Identifiable, Queryable, Serialize, ToSchema, Debug, Clone)]
 #[table_name = "keys"]
 pub struct Key {
+    #[schema(value_type = String, format = "uuid")]
+    pub id: uuid::Uuid,
+    pub created_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    pub label: Option<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub max_redemptions: i32,
+    pub redemption_count: i32,
+    pub created_by: Option<i32>,
+}
+
+/// Database representation of a Beta Key that can be inserted
+#[derive(Insertable, Debug, Default)]
+#[table_name = "keys"]
+pub struct NewKey {
     pub id: uuid::Uuid,
+    pub label: Option<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub max_redemptions: Option<i32>,
+    pub created_by: Option<i32>,
+}
+
+/// Database representation of a user redeeming a Beta Key
+#[derive(Identifiable, Queryable, Associations, Serialize, Debug, Clone)]
+#[table_name = "redemptions"]
+#[primary_key(key_id, user_id)]
+#[belongs_to(Key)]
+pub struct Redemption {
+    pub key_id: uuid::Uuid,
+    pub user_id: i32,
+    pub redeemed_at: chrono::NaiveDateTime,
+}
+
+/// Representation of a user who redeemed a Beta Key
+#[derive(Queryable, Serialize, ToSchema, Debug, Clone)]
+pub struct RedeemedBy {
+    pub user_id: i32,
+    pub email: String,
+    pub redeemed_at: chrono::NaiveDateTime,
 }
 
+impl Listable for Key {
+    const SORT_FIELDS: &'static [&'static str] = &["created_at", "id"];
+    const FILTERS: &'static [&'static str] = &["redeemed", "revoked", "expired", "label"];
+
+    fn cursor(&self, sort: &str) -> Cursor {
+        match sort {
+            "id" => Cursor::new(self.id, self.id),
+            _ => Cursor::new(self.created_at.format(TIMESTAMP_FORMAT), self.id),
+        }
+    }
+}
+
+type KeyQuery = diesel::dsl::IntoBoxed<'static, keys::table, Pg>;
+
 impl Key {
-    /// Checks if the provided key is vaild
+    /// Checks if the provided key exists and has not been revoked
     pub fn has_key(key: &uuid::Uuid, conn: &PgConnection) -> Result<bool, ApiError> {
         use crate::schema::keys::dsl::*;
 
-        let beta_key = keys.find(key).first::<Self>(conn).optional()?;
+        let beta_key = keys
+            .find(key)
+            .filter(revoked_at.is_null())
+            .first::<Self>(conn)
+            .optional()?;
+
+        Ok(beta_key.is_some())
+    }
+
+    /// Checks if the key is valid, unexpired and has redemptions left
+    pub fn is_available(key: &uuid::Uuid, conn: &PgConnection) -> Result<bool, ApiError> {
+        use crate::schema::keys::dsl::*;
+        use diesel::dsl::now;
+
+        let beta_key = keys
+            .find(key)
+            .filter(revoked_at.is_null())
+            .filter(expires_at.is_null().or(expires_at.gt(now.nullable())))
+            .filter(redemption_count.lt(max_redemptions))
+            .first::<Self>(conn)
+            .optional()?;
+
+        Ok(beta_key.is_some())
+    }
+
+    /// Claims one redemption of the key, failing with KEY_ALREADY_REDEEMED if
+    /// it is used up and INVALID_BETA_KEY if it is unknown, revoked or expired.
+    /// The check and the increment happen in a single statement so concurrent
+    /// signups can't redeem the key more often than allowed, and the row stays
+    /// locked until the signup's transaction ends.
+    pub fn redeem(key: &uuid::Uuid, conn: &PgConnection) -> Result<Key, ApiError> {
+        use crate::schema::keys::dsl::*;
+        use diesel::dsl::now;
+
+        let claimed = diesel::update(
+            keys.find(key)
+                .filter(revoked_at.is_null())
+                .filter(expires_at.is_null().or(expires_at.gt(now.nullable())))
+                .filter(redemption_count.lt(max_redemptions)),
+        )
+        .set(redemption_count.eq(redemption_count + 1))
+        .get_result::<Key>(conn)
+        .optional()?;
+
+        match claimed {
+            Some(claimed) => Ok(claimed),
+            None => Err(Key::unredeemable(
+                keys.find(key).first::<Key>(conn).optional()?.as_ref(),
+            )),
+        }
+    }
 
-        if beta_key.is_some() {
-            return Ok(true);
+    /// Returns why a key that could not be claimed can't be redeemed, telling
+    /// keys that were used up apart from unknown, revoked or expired ones
+    pub fn unredeemable(key: Option<&Key>) -> ApiError {
+        match key {
+            Some(key) if key.redemption_count >= key.max_redemptions => {
+                ApiError::KeyAlreadyRedeemed
+            }
+            _ => ApiError::InvalidBetaKey,
         }
+    }
+
+    /// Records that the user redeemed the key
+    pub fn record_redemption(
+        key: &uuid::Uuid,
+        user: i32,
+        conn: &PgConnection,
+    ) -> Result<Redemption, ApiError> {
+        use crate::schema::redemptions::dsl::*;
 
-        return Ok(false);
+        let redemption = diesel::insert_into(redemptions)
+            .values((key_id.eq(key), user_id.eq(user)))
+            .get_result::<Redemption>(conn)?;
+
+        Ok(redemption)
     }
 
-    /// Checks if the key is both valid and available
-    pub fn is_available(key: &uuid::Uuid, conn: &PgConnection) -> Result<bool, ApiError> {
+    /// Generates new keys as described by the form
+    pub fn generate(form: &GenerateKeysForm, conn: &PgConnection) -> Result<Vec<Key>, ApiError> {
         use crate::schema::keys::dsl::*;
-        use crate::schema::users::dsl::*;
 
-        let is_taken = users
-            .filter(key_id.eq(key))
-            .first::<User>(conn)
-            .optional()?;
+        let new_keys: Vec<NewKey> = (0..form.count)
+            .map(|_| NewKey {
+                id: uuid::Uuid::new_v4(),
+                label: form.label.clone(),
+                expires_at: form.expires_at,
+                max_redemptions: form.max_redemptions,
+                created_by: None,
+            })
+            .collect();
+
+        let created = diesel::insert_into(keys)
+            .values(&new_keys)
+            .get_results::<Key>(conn)?;
+
+        Ok(created)
+    }
+
+    /// Imports the provided keys, skipping any that already exist, and returns
+    /// the number of keys that were inserted
+    pub fn import(ids: &[uuid::Uuid], conn: &PgConnection) -> Result<usize, ApiError> {
+        use crate::schema::keys::dsl::*;
 
-        let is_valid = keys.find(key).first::<Self>(conn).optional()?;
+        let new_keys: Vec<NewKey> = ids
+            .iter()
+            .map(|key| NewKey {
+                id: *key,
+                ..Default::default()
+            })
+            .collect();
 
-        if is_taken.is_some() & is_valid.is_some() {
-            return Ok(true);
+        let inserted = diesel::insert_into(keys)
+            .values(&new_keys)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        Ok(inserted)
+    }
+
+    /// Revokes a key so it can't be redeemed any further
+    pub fn revoke(key: uuid::Uuid, conn: &PgConnection) -> Result<Key, ApiError> {
+        use crate::schema::keys::dsl::*;
+        use diesel::dsl::now;
+
+        let beta_key = keys.find(key).first::<Key>(conn)?;
+
+        if beta_key.redemption_count >= beta_key.max_redemptions {
+            return Err(ApiError::KeyAlreadyRedeemed);
+        }
+
+        if beta_key.revoked_at.is_some() {
+            return Ok(beta_key);
         }
 
-        return Ok(false);
+        let revoked = diesel::update(
+            keys.find(key)
+                .filter(revoked_at.is_null())
+                .filter(redemption_count.lt(max_redemptions)),
+        )
+        .set(revoked_at.eq(now.nullable()))
+        .get_result::<Key>(conn)
+        .optional()?;
+
+        // the last redemption was claimed between the lookup and the update
+        revoked.ok_or(ApiError::KeyAlreadyRedeemed)
+    }
+
+    /// Returns the users who redeemed the key
+    pub fn redeemed_by(key: uuid::Uuid, conn: &PgConnection) -> Result<Vec<RedeemedBy>, ApiError> {
+        let redeemed = redemptions::table
+            .inner_join(users::table)
+            .filter(redemptions::key_id.eq(key))
+            .select((users::id, users::email, redemptions::redeemed_at))
+            .order(redemptions::redeemed_at.asc())
+            .load::<RedeemedBy>(conn)?;
+
+        Ok(redeemed)
+    }
+
+    /// Returns a filtered, sorted and paginated list of keys
+    pub fn search(params: &ListParams, conn: &PgConnection) -> Result<Page<Key>, ApiError> {
+        use diesel::dsl::count_star;
+
+        let total = Self::filtered(params)?
+            .select(count_star())
+            .first::<i64>(conn)?;
+
+        let rows = Self::load_sorted(params, true, conn)?;
+
+        Ok(Page::from_rows(rows, total, params))
+    }
+
+    /// Returns every key matching the filters, ignoring pagination
+    pub fn export(params: &ListParams, conn: &PgConnection) -> Result<Vec<Key>, ApiError> {
+        Self::load_sorted(params, false, conn)
+    }
+
+    /// Builds the keys query with the list filters applied
+    fn filtered(params: &ListParams) -> Result<KeyQuery, ApiError> {
+        use crate::schema::keys::dsl::*;
+        use diesel::dsl::now;
+
+        let mut query = keys.into_boxed::<Pg>();
+
+        match params.filter_bool("redeemed")? {
+            Some(true) => query = query.filter(redemption_count.gt(0)),
+            Some(false) => query = query.filter(redemption_count.eq(0)),
+            None => {}
+        }
+
+        match params.filter_bool("revoked")? {
+            Some(true) => query = query.filter(revoked_at.is_not_null()),
+            Some(false) => query = query.filter(revoked_at.is_null()),
+            None => {}
+        }
+
+        match params.filter_bool("expired")? {
+            Some(true) => query = query.filter(expires_at.le(now.nullable())),
+            Some(false) => {
+                query = query.filter(expires_at.is_null().or(expires_at.gt(now.nullable())))
+            }
+            None => {}
+        }
+
+        if let Some(name) = params.filter("label") {
+            query = query.filter(label.eq(name.to_string()));
+        }
+
+        Ok(query)
+    }
+
+    /// Loads the filtered keys in the requested order, optionally limited to the requested page
+    fn load_sorted(
+        params: &ListParams,
+        paginate: bool,
+        conn: &PgConnection,
+    ) -> Result<Vec<Key>, ApiError> {
+        let query = Self::filtered(params)?;
+
+        let mut query = match params.sort.as_str() {
+            "id" => sort_by!(query, params, keys::id, keys::id, uuid::Uuid, uuid::Uuid),
+            _ => sort_by!(
+                query,
+                params,
+                keys::created_at,
+                keys::id,
+                chrono::NaiveDateTime,
+                uuid::Uuid
+            ),
+        };
+
+        if paginate {
+            query = query
+                .limit(params.fetch_limit())
+                .offset(params.fetch_offset());
+        }
+
+        let rows = query.load::<Key>(conn)?;
+
+        Ok(rows)
     }
 }
 
 /// Check Key form  used to check if a key is valid
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, ToSchema, Debug)]
 pub struct CheckKeyForm {
+    #[schema(value_type = String, format = "uuid")]
     pub key: uuid::Uuid,
 }
 
+/// Form used by administrators to generate new keys
+#[derive(Deserialize, Validate, ToSchema, Debug, Default)]
+pub struct GenerateKeysForm {
+    #[validate(range(min = 1, max = 1000, code = "INVALID_COUNT"))]
+    pub count: u32,
+    #[validate(length(min = 1, max = 100, code = "INVALID_LABEL"))]
+    pub label: Option<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    #[validate(range(min = 1, max = 1000000, code = "INVALID_MAX_REDEMPTIONS"))]
+    pub max_redemptions: Option<i32>,
+}
+
+/// Parses a CSV document with a key in the first column of every row, an
+/// optional `id` header row and blank lines being skipped
+pub fn parse_csv(body: &str) -> Result<Vec<uuid::Uuid>, ApiError> {
+    let mut ids = Vec::new();
+
+    for (index, line) in body.lines().enumerate() {
+        let field = line
+            .split(',')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .trim_matches('"');
+
+        if field.is_empty() || (index == 0 && field == "id") {
+            continue;
+        }
+
+        match field.parse::<uuid::Uuid>() {
+            Ok(id) => ids.push(id),
+            Err(_) => {
+                return Err(ApiError::ValidationError(
+                    String::from("VALIDATION_ERROR"),
+                    format!("Line {} does not contain a valid key", index + 1),
+                    vec![FieldError::new("body", ValidationCode::InvalidCsv)
+                        .with_param("line", index + 1)],
+                ))
+            }
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Writes keys and their redemption status as a CSV document
+pub fn to_csv(beta_keys: &[Key]) -> String {
+    let timestamp = |value: &Option<chrono::NaiveDateTime>| {
+        value
+            .map(|v| v.format(TIMESTAMP_FORMAT).to_string())
+            .unwrap_or_default()
+    };
+
+    let mut csv = String::from(
+        "id,label,created_at,expires_at,revoked_at,max_redemptions,redemption_count\n",
+    );
+
+    for key in beta_keys {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            key.id,
+            escape_csv(key.label.as_deref().unwrap_or("")),
+            key.created_at.format(TIMESTAMP_FORMAT),
+            timestamp(&key.expires_at),
+            timestamp(&key.revoked_at),
+            key.max_redemptions,
+            key.redemption_count,
+        ));
+    }
+
+    csv
+}
+
+/// Quotes a CSV field if it contains a delimiter, quote or line break
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::db::create_pool;
+    use crate::db::tests::create_store;
+    use crate::models::registration::RegistrationPolicy;
+    use crate::store::postgres::PgStore;
 
     #[test]
     fn it_returns_false_for_missing_key() {
-        let conn = create_pool().get().unwrap();
+        let store = create_store();
+        let conn = store.conn().unwrap();
 
         let random_uuid = uuid::Uuid::new_v4();
 
@@ -73,11 +431,15 @@ pub mod tests {
     fn it_returns_true_for_existing_key() {
         use crate::schema::keys::dsl::*;
 
-        let conn = create_pool().get().unwrap();
+        let store = create_store();
+        let conn = store.conn().unwrap();
 
         let random_uuid = uuid::Uuid::new_v4();
 
-        let new_key = Key { id: random_uuid };
+        let new_key = NewKey {
+            id: random_uuid,
+            ..Default::default()
+        };
 
         diesel::insert_into(keys)
             .values(&new_key)
@@ -94,11 +456,15 @@ pub mod tests {
         use crate::models::user::NewUserForm;
         use crate::schema::keys::dsl::*;
 
-        let conn = create_pool().get().unwrap();
+        let store = create_store();
+        let conn = store.conn().unwrap();
 
         let random_uuid = uuid::Uuid::new_v4();
 
-        let new_key = Key { id: random_uuid };
+        let new_key = NewKey {
+            id: random_uuid,
+            ..Default::default()
+        };
 
         diesel::insert_into(keys)
             .values(&new_key)
@@ -108,13 +474,168 @@ pub mod tests {
         let new_user = NewUserForm {
             email: "foo1@bar.com".to_string(),
             password: "password".to_string(),
-            key_id: random_uuid,
+            key_id: Some(random_uuid),
+            locale: None,
         };
 
-        new_user.create(&conn).expect("failed to create user");
+        new_user
+            .create(&RegistrationPolicy::default(), &store)
+            .expect("failed to create user");
 
         let is_available = Key::is_available(&random_uuid, &conn).unwrap();
 
-        assert!(is_available);
+        assert!(!is_available);
+    }
+
+    /// Creates a user with a unique email redeeming the provided key
+    fn redeem_key(
+        key: &uuid::Uuid,
+        store: &PgStore,
+    ) -> Result<crate::models::user::User, ApiError> {
+        use crate::models::user::NewUserForm;
+
+        let new_user = NewUserForm {
+            email: format!("{}@bar.com", &uuid::Uuid::new_v4().to_string()[..8]),
+            password: "password".to_string(),
+            key_id: Some(*key),
+            locale: None,
+        };
+
+        new_user.create(&RegistrationPolicy::default(), store)
+    }
+
+    #[test]
+    fn it_generates_and_revokes_keys() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let form = GenerateKeysForm {
+            count: 2,
+            ..Default::default()
+        };
+        let generated = Key::generate(&form, &conn).expect("failed to generate keys");
+        assert_eq!(generated.len(), 2);
+
+        let revoked = Key::revoke(generated[0].id, &conn).expect("failed to revoke key");
+        assert!(revoked.revoked_at.is_some());
+        assert!(!Key::has_key(&generated[0].id, &conn).unwrap());
+        assert!(Key::has_key(&generated[1].id, &conn).unwrap());
+        assert!(redeem_key(&generated[0].id, &store).is_err());
+    }
+
+    #[test]
+    fn it_refuses_to_revoke_redeemed_key() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let form = GenerateKeysForm {
+            count: 1,
+            ..Default::default()
+        };
+        let key = Key::generate(&form, &conn).unwrap().remove(0);
+
+        let user = redeem_key(&key.id, &store).expect("failed to create user");
+
+        match Key::revoke(key.id, &conn) {
+            Err(ApiError::KeyAlreadyRedeemed) => {}
+            other => panic!("expected redeemed key error, got {:?}", other),
+        }
+
+        let redeemed_by = Key::redeemed_by(key.id, &conn).unwrap();
+        assert_eq!(redeemed_by.len(), 1);
+        assert_eq!(redeemed_by[0].user_id, user.id);
+    }
+
+    #[test]
+    fn it_redeems_multi_use_key_up_to_its_limit() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let form = GenerateKeysForm {
+            count: 1,
+            label: Some("launch".to_string()),
+            max_redemptions: Some(2),
+            ..Default::default()
+        };
+        let key = Key::generate(&form, &conn).unwrap().remove(0);
+
+        assert!(redeem_key(&key.id, &store).is_ok());
+        assert!(redeem_key(&key.id, &store).is_ok());
+        assert!(!Key::is_available(&key.id, &conn).unwrap());
+
+        match redeem_key(&key.id, &store) {
+            Err(ApiError::KeyAlreadyRedeemed) => {}
+            other => panic!("expected redeemed key error, got {:?}", other),
+        }
+
+        assert_eq!(Key::redeemed_by(key.id, &conn).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn it_redeems_single_use_key_once_under_concurrent_signups() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let form = GenerateKeysForm {
+            count: 1,
+            max_redemptions: Some(1),
+            ..Default::default()
+        };
+        let key = Key::generate(&form, &conn).unwrap().remove(0).id;
+
+        let signups: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || redeem_key(&key, &store).is_ok())
+            })
+            .collect();
+        let redeemed = signups
+            .into_iter()
+            .filter_map(|signup| signup.join().ok())
+            .filter(|redeemed| *redeemed)
+            .count();
+
+        assert_eq!(redeemed, 1);
+        assert_eq!(Key::redeemed_by(key, &conn).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn it_refuses_expired_key() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let form = GenerateKeysForm {
+            count: 1,
+            expires_at: Some(chrono::Utc::now().naive_utc() - chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        let key = Key::generate(&form, &conn).unwrap().remove(0);
+
+        assert!(!Key::is_available(&key.id, &conn).unwrap());
+        assert!(redeem_key(&key.id, &store).is_err());
+    }
+
+    #[test]
+    fn it_imports_keys_skipping_duplicates() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let form = GenerateKeysForm {
+            count: 1,
+            ..Default::default()
+        };
+        let existing = Key::generate(&form, &conn).unwrap().remove(0);
+        let csv = format!("id\n{}\n\n{}\n", existing.id, uuid::Uuid::new_v4());
+
+        let ids = parse_csv(&csv).expect("failed to parse csv");
+        let imported = Key::import(&ids, &conn).expect("failed to import keys");
+
+        assert_eq!(ids.len(), 2);
+        assert_eq!(imported, 1);
+    }
+
+    #[test]
+    fn it_rejects_invalid_csv() {
+        assert!(parse_csv("id\nnot-a-key\n").is_err());
     }
 }
@@ -0,0 +1,151 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::str::FromStr;
+
+use crate::settings::deserialize_list;
+use crate::utils::errors::ApiError;
+
+/// How new users are allowed to sign up
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationMode {
+    /// Anyone can sign up, a beta key is optional
+    Open,
+    /// A valid beta key is required to sign up
+    #[default]
+    InviteOnly,
+    /// Only emails on one of the allowed domains can sign up
+    DomainAllowlist,
+    /// Nobody can sign up
+    Closed,
+}
+
+impl FromStr for RegistrationMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "open" => Ok(RegistrationMode::Open),
+            "invite_only" => Ok(RegistrationMode::InviteOnly),
+            "domain_allowlist" => Ok(RegistrationMode::DomainAllowlist),
+            "closed" => Ok(RegistrationMode::Closed),
+            _ => Err(format!("unknown registration mode {:?}", mode)),
+        }
+    }
+}
+
+/// Rules deciding who is allowed to sign up
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RegistrationPolicy {
+    pub mode: RegistrationMode,
+    /// Email domains allowed to sign up in domain allowlist mode
+    #[serde(deserialize_with = "deserialize_domains")]
+    pub allowed_domains: Vec<String>,
+    /// Answers signups with a taken email or an unusable key with SIGNUP_FAILED
+    /// instead of EMAIL_TAKEN, INVALID_BETA_KEY or KEY_ALREADY_REDEEMED, and
+    /// waitlist joins of a queued email the way new joins are answered, so
+    /// neither tells whether an email or a key is already in use
+    pub anti_enumeration: bool,
+}
+
+/// Deserializes the allowed domains, lowercasing them and dropping any leading `@`
+fn deserialize_domains<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let domains = deserialize_list(deserializer)?;
+
+    Ok(domains
+        .iter()
+        .filter_map(|domain| normalize_domain(domain))
+        .collect())
+}
+
+/// Lowercases the domain and strips any leading `@`, skipping blank entries
+fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_start_matches('@').to_lowercase();
+
+    if domain.is_empty() {
+        None
+    } else {
+        Some(domain)
+    }
+}
+
+impl RegistrationPolicy {
+    /// Checks if the email may sign up and returns whether a beta key is required
+    pub fn check(&self, email: &str) -> Result<bool, ApiError> {
+        match self.mode {
+            RegistrationMode::Open => Ok(false),
+            RegistrationMode::InviteOnly => Ok(true),
+            RegistrationMode::DomainAllowlist => {
+                if self.allows_domain(email) {
+                    Ok(false)
+                } else {
+                    Err(ApiError::EmailDomainNotAllowed)
+                }
+            }
+            RegistrationMode::Closed => Err(ApiError::RegistrationClosed),
+        }
+    }
+
+    /// Replaces the errors of a signup telling whether its email is taken or
+    /// its key exists or was used with SIGNUP_FAILED when anti-enumeration is enabled
+    pub fn conceal(&self, error: ApiError) -> ApiError {
+        match error {
+            ApiError::EmailTaken | ApiError::InvalidBetaKey | ApiError::KeyAlreadyRedeemed
+                if self.anti_enumeration =>
+            {
+                ApiError::SignupFailed
+            }
+            error => error,
+        }
+    }
+
+    /// Checks if the domain of the email is on the allowlist
+    fn allows_domain(&self, email: &str) -> bool {
+        let domain = match email.rsplit_once('@') {
+            Some((_, domain)) => domain.to_lowercase(),
+            None => return false,
+        };
+
+        self.allowed_domains.contains(&domain)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn it_applies_registration_modes() {
+        let policy = |mode| RegistrationPolicy {
+            mode,
+            allowed_domains: vec!["ourcorp.com".to_string()],
+            anti_enumeration: false,
+        };
+
+        assert!(!policy(RegistrationMode::Open).check("a@b.com").unwrap());
+        assert!(policy(RegistrationMode::InviteOnly)
+            .check("a@b.com")
+            .unwrap());
+
+        let allowlist = policy(RegistrationMode::DomainAllowlist);
+        assert!(!allowlist.check("a@OurCorp.com").unwrap());
+        assert!(allowlist.check("a@ourcorp.com.evil.com").is_err());
+        assert!(allowlist.check("a@b.com").is_err());
+
+        assert!(policy(RegistrationMode::Closed)
+            .check("a@ourcorp.com")
+            .is_err());
+    }
+
+    #[test]
+    fn it_parses_registration_modes() {
+        assert_eq!(
+            "domain_allowlist".parse::<RegistrationMode>().unwrap(),
+            RegistrationMode::DomainAllowlist
+        );
+        assert!("sometimes".parse::<RegistrationMode>().is_err());
+    }
+}
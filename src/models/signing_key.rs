@@ -0,0 +1,84 @@
+use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use openssl::rsa::Rsa;
+
+use crate::schema::signing_keys;
+use crate::utils::errors::{ApiError, InternalCode};
+
+/// Algorithm tokens are signed with once signing keys have been generated
+pub const SIGNING_ALGORITHM: &str = "RS256";
+
+/// Size in bits of generated RSA keys
+const KEY_BITS: u32 = 2048;
+
+/// Database representation of a key pair used to sign tokens, identified in
+/// token headers by its kid
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[table_name = "signing_keys"]
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: String,
+    pub private_key: String,
+    pub public_key: String,
+    pub created_at: NaiveDateTime,
+    pub retired_at: Option<NaiveDateTime>,
+}
+
+impl SigningKey {
+    /// Generates a new RSA key pair, encoded as PEM
+    pub fn generate() -> Result<SigningKey, ApiError> {
+        let rsa = Rsa::generate(KEY_BITS).map_err(key_error)?;
+        let pem = |bytes: Vec<u8>| String::from_utf8(bytes).map_err(key_error);
+
+        Ok(SigningKey {
+            kid: uuid::Uuid::new_v4().to_string(),
+            algorithm: SIGNING_ALGORITHM.to_string(),
+            private_key: pem(rsa.private_key_to_pem().map_err(key_error)?)?,
+            public_key: pem(rsa.public_key_to_pem().map_err(key_error)?)?,
+            created_at: chrono::Utc::now().naive_utc(),
+            retired_at: None,
+        })
+    }
+
+    /// Returns the active keys and those retired after the provided time,
+    /// newest first
+    pub fn find_usable(
+        retired_after: NaiveDateTime,
+        conn: &PgConnection,
+    ) -> Result<Vec<SigningKey>, ApiError> {
+        use crate::schema::signing_keys::dsl::*;
+
+        let keys = signing_keys
+            .filter(retired_at.is_null().or(retired_at.gt(retired_after)))
+            .order(created_at.desc())
+            .load::<SigningKey>(conn)?;
+
+        Ok(keys)
+    }
+
+    /// Retires the active keys and makes the provided key the one signing tokens
+    pub fn rotate(key: &SigningKey, conn: &PgConnection) -> Result<(), ApiError> {
+        use crate::schema::signing_keys::dsl::*;
+
+        conn.transaction(|| {
+            diesel::update(signing_keys.filter(retired_at.is_null()))
+                .set(retired_at.eq(key.created_at))
+                .execute(conn)?;
+
+            diesel::insert_into(signing_keys)
+                .values(key)
+                .execute(conn)?;
+
+            Ok(())
+        })
+    }
+}
+
+/// Converts a failure to generate or encode a key to an ApiError
+fn key_error<E: std::fmt::Display>(error: E) -> ApiError {
+    ApiError::InternalServerError(
+        InternalCode::SigningKey,
+        format!("Could not generate a signing key: {}", error),
+    )
+}
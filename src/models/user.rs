@@ -1,37 +1,124 @@
-use diesel::pg::PgConnection;
+use diesel::pg::{Pg, PgConnection};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
-use validator::Validate;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
 
-use crate::utils::errors::ApiError;
+use crate::models::key::Key;
+use crate::models::registration::RegistrationPolicy;
 use crate::schema::users;
+use crate::store::{unit_of_work, Store, UserStore};
+use crate::utils::errors::{ApiError, FieldError, ValidationCode};
+use crate::utils::i18n::{Locale, LOCALES};
+use crate::utils::pagination::{sort_by, Cursor, ListParams, Listable, Page};
 
 /// Database representation of a User
-#[derive(Identifiable, Queryable, PartialEq, Associations, Serialize, Debug)]
+#[derive(Identifiable, Queryable, PartialEq, Associations, Serialize, Debug, Clone)]
 #[table_name = "users"]
 pub struct User {
     pub id: i32,
     pub email: String,
     pub password: String,
-    pub key_id: uuid::Uuid,
+    pub key_id: Option<uuid::Uuid>,
     pub created_at: std::time::SystemTime,
+    pub role: String,
+    pub disabled: bool,
+    pub deleted_at: Option<std::time::SystemTime>,
+    pub invited_by: Option<i32>,
+    /// Language tag errors are written in for the user, such as `fr`
+    pub locale: Option<String>,
 }
 
-#[derive(Identifiable, Queryable, Serialize)]
+#[derive(Identifiable, Queryable, Serialize, ToSchema, Clone)]
 #[table_name = "users"]
 pub struct ViewableUser {
     pub id: i32,
     pub email: String,
 }
 
+/// Representation of a User as seen by administrators
+#[derive(Identifiable, Queryable, Serialize, ToSchema, Debug, Clone)]
+#[table_name = "users"]
+pub struct ManagedUser {
+    pub id: i32,
+    pub email: String,
+    pub role: String,
+    pub disabled: bool,
+    pub created_at: chrono::NaiveDateTime,
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Role granted to administrators
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Role given to users when they sign up
+pub const USER_ROLE: &str = "user";
+
+/// Roles that can be assigned to users
+pub const ROLES: &[&str] = &[USER_ROLE, ADMIN_ROLE];
+
+/// Format timestamps are written in when used as a keyset cursor
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+impl Listable for ViewableUser {
+    const SORT_FIELDS: &'static [&'static str] = &["id", "email"];
+    const FILTERS: &'static [&'static str] = &["email"];
+
+    fn cursor(&self, sort: &str) -> Cursor {
+        match sort {
+            "email" => Cursor::new(&self.email, self.id),
+            _ => Cursor::new(self.id, self.id),
+        }
+    }
+}
+
+impl Listable for ManagedUser {
+    const SORT_FIELDS: &'static [&'static str] = &["id", "email", "created_at"];
+    const FILTERS: &'static [&'static str] = &["email", "disabled", "deleted"];
+
+    fn cursor(&self, sort: &str) -> Cursor {
+        match sort {
+            "email" => Cursor::new(&self.email, self.id),
+            "created_at" => Cursor::new(self.created_at.format(TIMESTAMP_FORMAT), self.id),
+            _ => Cursor::new(self.id, self.id),
+        }
+    }
+}
+
 impl User {
-    pub fn find_all_users(conn: &PgConnection) -> Result<Vec<ViewableUser>, ApiError> {
-        use crate::schema::users::dsl::users;
-        use crate::schema::users::{email, id};
+    /// Returns a filtered, sorted and paginated list of users that have not been deleted
+    pub fn find_all_users(
+        params: &ListParams,
+        conn: &PgConnection,
+    ) -> Result<Page<ViewableUser>, ApiError> {
+        use crate::schema::users::dsl::*;
+        use diesel::dsl::count_star;
 
-        let all_users = users.select((id, email)).load::<ViewableUser>(conn)?;
+        let filtered = || {
+            let mut query = users.filter(deleted_at.is_null()).into_boxed::<Pg>();
 
-        Ok(all_users)
+            if let Some(pattern) = params.filter_prefix("email") {
+                query = query.filter(email.ilike(pattern));
+            }
+
+            query
+        };
+
+        let total = filtered().select(count_star()).first::<i64>(conn)?;
+
+        let query = filtered().select((id, email));
+
+        let query = match params.sort.as_str() {
+            "email" => sort_by!(query, params, email, id, String, i32),
+            _ => sort_by!(query, params, id, id, i32, i32),
+        };
+
+        let rows = query
+            .limit(params.fetch_limit())
+            .offset(params.fetch_offset())
+            .load::<ViewableUser>(conn)?;
+
+        Ok(Page::from_rows(rows, total, params))
     }
 
     pub fn find_one(user_id: i32, conn: &PgConnection) -> Result<ViewableUser, ApiError> {
@@ -45,10 +132,201 @@ impl User {
 
         Ok(user)
     }
+
+    /// Finds a user by id, including disabled and deleted users
+    pub fn find_by_id(user_id: i32, conn: &PgConnection) -> Result<User, ApiError> {
+        use crate::schema::users::dsl::users;
+
+        let user = users.find(user_id).first::<User>(conn)?;
+
+        Ok(user)
+    }
+
+    /// Finds a user that has not been deleted by email
+    pub fn find_by_email(user_email: &str, conn: &PgConnection) -> Result<Option<User>, ApiError> {
+        use crate::schema::users::dsl::*;
+
+        let user = users
+            .filter(email.eq(user_email))
+            .filter(deleted_at.is_null())
+            .first::<User>(conn)
+            .optional()?;
+
+        Ok(user)
+    }
+
+    /// Inserts a user with the role and a password that has already been
+    /// hashed, claiming a redemption of their key in the same transaction
+    pub fn insert(
+        new_user: &NewUserForm,
+        granted: &str,
+        conn: &PgConnection,
+    ) -> Result<User, ApiError> {
+        use crate::schema::users::dsl::{invited_by, role, users as query_users};
+        use diesel::insert_into;
+
+        conn.transaction(|| {
+            // claim a redemption of the key, which fails once it is used up
+            let key = match &new_user.key_id {
+                Some(key) => Some(Key::redeem(key, conn)?),
+                None => None,
+            };
+
+            // insert new user in the database, crediting whoever minted the key
+            let inviter = key.as_ref().and_then(|k| k.created_by);
+            let user: User = insert_into(query_users)
+                .values((new_user, invited_by.eq(inviter), role.eq(granted)))
+                .returning(users::all_columns)
+                .get_result(conn)?;
+
+            if let Some(key) = &key {
+                Key::record_redemption(&key.id, user.id, conn)?;
+            }
+
+            Ok(user)
+        })
+    }
+
+    /// Returns true if the user has not been disabled or deleted
+    pub fn is_active(&self) -> bool {
+        !self.disabled && self.deleted_at.is_none()
+    }
+
+    /// Returns true if the user has been granted the admin role
+    pub fn is_admin(&self) -> bool {
+        self.role == ADMIN_ROLE
+    }
+
+    /// Returns a filtered, sorted and paginated list of users for administrators
+    pub fn search(params: &ListParams, conn: &PgConnection) -> Result<Page<ManagedUser>, ApiError> {
+        use crate::schema::users::dsl::*;
+        use diesel::dsl::count_star;
+
+        let show_disabled = params.filter_bool("disabled")?;
+        let show_deleted = params.filter_bool("deleted")?.unwrap_or(false);
+
+        let filtered = || {
+            let mut query = users.into_boxed::<Pg>();
+
+            if let Some(pattern) = params.filter_prefix("email") {
+                query = query.filter(email.ilike(pattern));
+            }
+
+            if let Some(is_disabled) = show_disabled {
+                query = query.filter(disabled.eq(is_disabled));
+            }
+
+            if show_deleted {
+                query = query.filter(deleted_at.is_not_null());
+            } else {
+                query = query.filter(deleted_at.is_null());
+            }
+
+            query
+        };
+
+        let total = filtered().select(count_star()).first::<i64>(conn)?;
+
+        let query = filtered().select((id, email, role, disabled, created_at, deleted_at));
+
+        let query = match params.sort.as_str() {
+            "email" => sort_by!(query, params, email, id, String, i32),
+            "created_at" => sort_by!(query, params, created_at, id, chrono::NaiveDateTime, i32),
+            _ => sort_by!(query, params, id, id, i32, i32),
+        };
+
+        let rows = query
+            .limit(params.fetch_limit())
+            .offset(params.fetch_offset())
+            .load::<ManagedUser>(conn)?;
+
+        Ok(Page::from_rows(rows, total, params))
+    }
+
+    /// Disables or enables the user with the provided id
+    pub fn set_disabled(
+        user_id: i32,
+        is_disabled: bool,
+        conn: &PgConnection,
+    ) -> Result<ManagedUser, ApiError> {
+        use crate::schema::users::dsl::*;
+
+        let user = diesel::update(users.find(user_id))
+            .set(disabled.eq(is_disabled))
+            .returning((id, email, role, disabled, created_at, deleted_at))
+            .get_result::<ManagedUser>(conn)?;
+
+        Ok(user)
+    }
+
+    /// Grants the role to the user with the provided id
+    pub fn set_role(
+        user_id: i32,
+        new_role: &str,
+        conn: &PgConnection,
+    ) -> Result<ManagedUser, ApiError> {
+        use crate::schema::users::dsl::*;
+
+        let user = diesel::update(users.find(user_id))
+            .set(role.eq(new_role))
+            .returning((id, email, role, disabled, created_at, deleted_at))
+            .get_result::<ManagedUser>(conn)?;
+
+        Ok(user)
+    }
+
+    /// Soft deletes the user with the provided id
+    pub fn soft_delete(user_id: i32, conn: &PgConnection) -> Result<ManagedUser, ApiError> {
+        use crate::schema::users::dsl::*;
+        use diesel::dsl::now;
+
+        let user = diesel::update(users.find(user_id).filter(deleted_at.is_null()))
+            .set(deleted_at.eq(now.nullable()))
+            .returning((id, email, role, disabled, created_at, deleted_at))
+            .get_result::<ManagedUser>(conn)?;
+
+        Ok(user)
+    }
+
+    /// Restores a previously soft deleted user
+    pub fn restore(user_id: i32, conn: &PgConnection) -> Result<ManagedUser, ApiError> {
+        use crate::schema::users::dsl::*;
+
+        let user = diesel::update(users.find(user_id).filter(deleted_at.is_not_null()))
+            .set(deleted_at.eq(None::<chrono::NaiveDateTime>))
+            .returning((id, email, role, disabled, created_at, deleted_at))
+            .get_result::<ManagedUser>(conn)?;
+
+        Ok(user)
+    }
+}
+
+/// Checks that the role is one users can be granted
+pub fn validate_role(role: &str) -> Result<(), ApiError> {
+    if ROLES.contains(&role) {
+        return Ok(());
+    }
+
+    Err(ApiError::ValidationError(
+        String::from("VALIDATION_ERROR"),
+        format!("Role must be one of {}", ROLES.join(", ")),
+        vec![FieldError::new("role", ValidationCode::InvalidRole).with_param("allowed", ROLES)],
+    ))
+}
+
+/// Checks that errors can be written in the language of the tag
+fn validate_locale(tag: &str) -> Result<(), ValidationError> {
+    if Locale::parse(tag).is_some() {
+        return Ok(());
+    }
+
+    let mut error = ValidationError::new("INVALID_LOCALE");
+    error.add_param("allowed".into(), &LOCALES);
+    Err(error)
 }
 
 /// Representation of a User Login model
-#[derive(Validate, Debug, Deserialize)]
+#[derive(Validate, Debug, Deserialize, ToSchema)]
 pub struct LoginUserForm {
     #[validate(email(code = "INVALID_EMAIL"))]
     pub email: String,
@@ -57,22 +335,30 @@ pub struct LoginUserForm {
 
 impl LoginUserForm {
     // Checks if the provided credentials are valid
-    pub fn verify_user(self, conn: &PgConnection) -> Result<Option<User>, ApiError> {
-        use crate::schema::users::dsl::*;
-        use bcrypt::verify;
+    pub fn verify_user(self, store: &dyn UserStore) -> Result<Option<User>, ApiError> {
+        let user = store.find_user_by_email(&self.email)?;
+        self.check_password(user)
+    }
 
-        let user = users
-            .filter(email.eq(self.email))
-            .first::<User>(conn)
-            .optional()?;
+    /// Checks the password against the user found by email. Verifying takes
+    /// long enough to run on a blocking thread rather than a database one
+    pub fn check_password(self, user: Option<User>) -> Result<Option<User>, ApiError> {
+        use bcrypt::verify;
 
         match user {
             Some(u) => {
-                if !verify(self.password, &u.password).unwrap() {
+                let span = tracing::info_span!("bcrypt", operation = "verify");
+                let valid = span.in_scope(|| verify(self.password, &u.password));
+                if !valid.unwrap() {
                     return Ok(None);
                 }
 
-                return Ok(Some(u));
+                // only reveal that the account is disabled once the password checks out
+                if u.disabled {
+                    return Err(ApiError::AccountDisabled);
+                }
+
+                Ok(Some(u))
             }
             None => Ok(None),
         }
@@ -80,74 +366,131 @@ impl LoginUserForm {
 }
 
 /// Database representation of a User that can be inserted
-#[derive(Insertable, Validate, Debug, Deserialize, Default)]
+#[derive(Insertable, Validate, Debug, Deserialize, ToSchema, Default)]
 #[table_name = "users"]
 pub struct NewUserForm {
     #[validate(email(code = "INVALID_EMAIL"))]
     pub email: String,
     pub password: String,
-    pub key_id: uuid::Uuid,
+    /// Beta key or invite redeemed by signing up
+    #[schema(value_type = Option<String>, format = "uuid")]
+    pub key_id: Option<uuid::Uuid>,
+    /// Language tag errors are written in for the user, one of `en`, `fr`, `de` or `es`
+    #[serde(default)]
+    #[validate(custom = "validate_locale")]
+    pub locale: Option<String>,
 }
 
 impl NewUserForm {
-    /// Creates a new user in the database
-    pub fn create(mut self, conn: &PgConnection) -> Result<User, ApiError> {
-        use crate::models::key::Key;
-        use crate::schema::users::dsl::users as query_users;
+    /// Creates a new user in the store if the registration policy allows it
+    pub fn create(self, policy: &RegistrationPolicy, store: &dyn Store) -> Result<User, ApiError> {
+        self.create_with_role(USER_ROLE, policy, store)
+    }
+
+    /// Creates a new user granted the role as they are inserted, if the
+    /// registration policy allows it
+    pub fn create_with_role(
+        self,
+        role: &str,
+        policy: &RegistrationPolicy,
+        store: &dyn Store,
+    ) -> Result<User, ApiError> {
+        self.prepare(role, policy)?.insert(policy, store)
+    }
+
+    /// Validates the fields and hashes the password of a user to be granted
+    /// the role. Hashing takes long enough to run on a blocking thread rather
+    /// than a database one
+    pub fn prepare(
+        mut self,
+        role: &str,
+        policy: &RegistrationPolicy,
+    ) -> Result<PreparedUser, ApiError> {
         use bcrypt::hash;
-        use diesel::insert_into;
 
-        // validate the fields
+        // validate the fields, storing the supported locale of the language tag
         self.validate()?;
+        validate_role(role)?;
+        self.locale = self
+            .locale
+            .as_deref()
+            .and_then(Locale::parse)
+            .map(|locale| locale.to_string());
+
+        // check if the email may sign up and whether it needs a key to do so
+        let key_required = policy.check(&self.email)?;
+
+        // hashing the password before finding out whether the key is valid
+        // or the email taken, so every signup takes about as long
+        let span = tracing::info_span!("bcrypt", operation = "hash");
+        let password = &self.password;
+        self.password = span.in_scope(|| hash(password, 4)).unwrap();
+
+        Ok(PreparedUser {
+            form: self,
+            role: role.to_string(),
+            key_required,
+        })
+    }
+}
 
-        // check if the key exists
-        let has_key = Key::has_key(&self.key_id, conn)?;
-
-        if has_key {
-            // hashing the password
-            self.password = hash(self.password, 4).unwrap();
+/// User whose fields were validated and password hashed, ready to be inserted
+#[derive(Debug)]
+pub struct PreparedUser {
+    form: NewUserForm,
+    role: String,
+    key_required: bool,
+}
 
-            // insert new user in the database
-            match insert_into(query_users)
-                .values(&self)
-                .returning(users::all_columns)
-                .get_result(conn)
-            {
-                Ok(u) => return Ok(u),
-                // TODO: handle case where user with email already exists
-                Err(_) => return Err(ApiError::InvalidBetaKey),
+impl PreparedUser {
+    /// Inserts the user, claiming a redemption of their key
+    pub fn insert(self, policy: &RegistrationPolicy, store: &dyn Store) -> Result<User, ApiError> {
+        // the checks and the insert commit together, so later steps of a
+        // signup can join the same unit of work
+        unit_of_work(store, |store| {
+            // the insert claims the key, failing when it can't be redeemed
+            if self.form.key_id.is_none() && self.key_required {
+                return Err(ApiError::BetaKeyRequired);
             }
-        }
 
-        // if the key doesnt match
-        Err(ApiError::InvalidBetaKey)
+            store
+                .insert_user(&self.form, &self.role)
+                .map_err(|error| policy.conceal(error))
+        })
     }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::db::create_pool;
+    use crate::db::tests::create_store;
+    use crate::store::postgres::PgStore;
 
     #[test]
     fn it_gets_all_users() {
-        let conn = create_pool().get().unwrap();
+        let store = create_store();
+        let conn = store.conn().unwrap();
 
-        let all_users = User::find_all_users(&conn);
+        let params = ListParams::parse::<ViewableUser>("").unwrap();
+        let all_users = User::find_all_users(&params, &conn);
 
         assert!(all_users.is_ok())
     }
 
     #[test]
     fn it_returns_err_for_invalid_email() {
-        use crate::models::key::Key;
+        use crate::models::key::NewKey;
         use crate::schema::keys::dsl::*;
 
-        let conn = create_pool().get().unwrap();
+        let store = create_store();
+        let conn = store.conn().unwrap();
 
         let random_uuid = uuid::Uuid::new_v4();
 
-        let new_key = Key { id: random_uuid };
+        let new_key = NewKey {
+            id: random_uuid,
+            ..Default::default()
+        };
 
         diesel::insert_into(keys)
             .values(&new_key)
@@ -157,24 +500,29 @@ pub mod tests {
         let new_user = NewUserForm {
             email: "foo".to_string(),
             password: "password".to_string(),
-            key_id: random_uuid,
+            key_id: Some(random_uuid),
+            locale: None,
         };
 
-        let result = new_user.create(&conn);
+        let result = new_user.create(&RegistrationPolicy::default(), &store);
 
         assert!(result.is_err());
     }
 
     #[test]
     fn it_creates_user() {
-        use crate::models::key::Key;
+        use crate::models::key::NewKey;
         use crate::schema::keys::dsl::*;
 
-        let conn = create_pool().get().unwrap();
+        let store = create_store();
+        let conn = store.conn().unwrap();
 
         let random_uuid = uuid::Uuid::new_v4();
 
-        let new_key = Key { id: random_uuid };
+        let new_key = NewKey {
+            id: random_uuid,
+            ..Default::default()
+        };
 
         diesel::insert_into(keys)
             .values(&new_key)
@@ -184,24 +532,29 @@ pub mod tests {
         let new_user = NewUserForm {
             email: "foo2@bar.com".to_string(),
             password: "password".to_string(),
-            key_id: random_uuid,
+            key_id: Some(random_uuid),
+            locale: None,
         };
 
-        let result = new_user.create(&conn);
+        let result = new_user.create(&RegistrationPolicy::default(), &store);
 
         assert!(result.is_ok());
     }
 
     #[test]
     fn it_verifies_user() {
-        use crate::models::key::Key;
+        use crate::models::key::NewKey;
         use crate::schema::keys::dsl::*;
 
-        let conn = create_pool().get().unwrap();
+        let store = create_store();
+        let conn = store.conn().unwrap();
 
         let random_uuid = uuid::Uuid::new_v4();
 
-        let new_key = Key { id: random_uuid };
+        let new_key = NewKey {
+            id: random_uuid,
+            ..Default::default()
+        };
 
         diesel::insert_into(keys)
             .values(&new_key)
@@ -211,18 +564,158 @@ pub mod tests {
         let new_user = NewUserForm {
             email: "foo3@bar.com".to_string(),
             password: "password".to_string(),
-            key_id: random_uuid,
+            key_id: Some(random_uuid),
+            locale: None,
         };
 
-        new_user.create(&conn).expect("Failed to create new user");
+        new_user
+            .create(&RegistrationPolicy::default(), &store)
+            .expect("Failed to create new user");
 
         let login = LoginUserForm {
             email: "foo3@bar.com".to_string(),
             password: "password".to_string(),
         };
 
-        let is_valid = login.verify_user(&conn);
+        let is_valid = login.verify_user(&store);
 
         assert!(is_valid.is_ok());
     }
+
+    #[test]
+    fn it_requires_key_only_in_invite_only_mode() {
+        use crate::models::registration::RegistrationMode;
+
+        let store = create_store();
+
+        let new_user = || NewUserForm {
+            email: format!("{}@bar.com", &uuid::Uuid::new_v4().to_string()[..8]),
+            password: "password".to_string(),
+            key_id: None,
+            locale: None,
+        };
+
+        match new_user().create(&RegistrationPolicy::default(), &store) {
+            Err(ApiError::BetaKeyRequired) => {}
+            other => panic!("expected missing key error, got {:?}", other),
+        }
+
+        let open = RegistrationPolicy {
+            mode: RegistrationMode::Open,
+            ..Default::default()
+        };
+        let user = new_user()
+            .create(&open, &store)
+            .expect("failed to create user");
+        assert_eq!(user.key_id, None);
+        assert_eq!(user.invited_by, None);
+    }
+
+    /// Creates a user with a unique email and returns it
+    fn create_random_user(store: &PgStore) -> User {
+        use crate::models::key::NewKey;
+        use crate::schema::keys::dsl::*;
+
+        let random_uuid = uuid::Uuid::new_v4();
+
+        let new_key = NewKey {
+            id: random_uuid,
+            ..Default::default()
+        };
+
+        diesel::insert_into(keys)
+            .values(&new_key)
+            .execute(&store.conn().unwrap())
+            .expect("failed to insert key");
+
+        let new_user = NewUserForm {
+            email: format!("{}@bar.com", &random_uuid.to_string()[..8]),
+            password: "password".to_string(),
+            key_id: Some(random_uuid),
+            locale: None,
+        };
+
+        new_user
+            .create(&RegistrationPolicy::default(), store)
+            .expect("failed to create user")
+    }
+
+    #[test]
+    fn it_refuses_login_for_disabled_user() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let user = create_random_user(&store);
+
+        User::set_disabled(user.id, true, &conn).expect("failed to disable user");
+
+        let login = LoginUserForm {
+            email: user.email.clone(),
+            password: "password".to_string(),
+        };
+
+        match login.verify_user(&store) {
+            Err(ApiError::AccountDisabled) => {}
+            other => panic!("expected disabled account, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_soft_deletes_and_restores_user() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let user = create_random_user(&store);
+
+        let deleted = User::soft_delete(user.id, &conn).expect("failed to delete user");
+        assert!(deleted.deleted_at.is_some());
+
+        let login = LoginUserForm {
+            email: user.email.clone(),
+            password: "password".to_string(),
+        };
+        assert!(login.verify_user(&store).unwrap().is_none());
+
+        let restored = User::restore(user.id, &conn).expect("failed to restore user");
+        assert!(restored.deleted_at.is_none());
+        assert!(User::find_by_id(user.id, &conn).unwrap().is_active());
+    }
+
+    #[test]
+    fn it_searches_users_by_email_prefix() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let user = create_random_user(&store);
+
+        let query = format!("email={}", &user.email[..8]);
+        let params = ListParams::parse::<ManagedUser>(&query).unwrap();
+
+        let page = User::search(&params, &conn).expect("failed to search users");
+
+        assert_eq!(page.total, 1);
+        assert_eq!(page.items[0].id, user.id);
+    }
+
+    #[test]
+    fn it_pages_users_with_cursor() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        for _ in 0..3 {
+            create_random_user(&store);
+        }
+
+        let params = ListParams::parse::<ManagedUser>("sort=-created_at&limit=2").unwrap();
+        let first = User::search(&params, &conn).expect("failed to search users");
+
+        let cursor = first.next_cursor.expect("expected a next page").encode();
+        let query = format!("sort=-created_at&limit=2&cursor={}", cursor);
+        let params = ListParams::parse::<ManagedUser>(&query).unwrap();
+        let second = User::search(&params, &conn).expect("failed to search users");
+
+        assert_eq!(first.items.len(), 2);
+        assert!(second.items[0].created_at <= first.items[1].created_at);
+        assert!(first.items.iter().all(|u| u.id != second.items[0].id));
+    }
 }
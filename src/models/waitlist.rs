@@ -0,0 +1,312 @@
+use chrono::Utc;
+use diesel::pg::{Pg, PgConnection};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::models::key::{GenerateKeysForm, Key};
+use crate::models::registration::RegistrationPolicy;
+use crate::schema::waitlist;
+use crate::store::WaitlistStore;
+use crate::utils::errors::ApiError;
+use crate::utils::pagination::{sort_by, Cursor, ListParams, Listable, Page};
+
+/// Label given to keys issued to approved waitlist entries
+pub const WAITLIST_LABEL: &str = "waitlist";
+
+/// Database representation of someone waiting for a Beta Key
+#[derive(Identifiable, Queryable, Serialize, ToSchema, Debug, Clone)]
+#[table_name = "waitlist"]
+pub struct WaitlistEntry {
+    pub id: i32,
+    pub email: String,
+    pub source: Option<String>,
+    #[serde(skip_serializing)]
+    #[schema(value_type = String, format = "uuid")]
+    pub token: uuid::Uuid,
+    pub created_at: chrono::NaiveDateTime,
+    pub approved_at: Option<chrono::NaiveDateTime>,
+    #[schema(value_type = Option<String>, format = "uuid")]
+    pub key_id: Option<uuid::Uuid>,
+}
+
+/// Form used by people without a key to join the waitlist
+#[derive(Insertable, Validate, Debug, Deserialize, ToSchema, Default)]
+#[table_name = "waitlist"]
+pub struct JoinWaitlistForm {
+    #[validate(email(code = "INVALID_EMAIL"))]
+    pub email: String,
+    #[validate(length(min = 1, max = 100, code = "INVALID_SOURCE"))]
+    pub source: Option<String>,
+}
+
+/// Form used by administrators to approve the next entries on the waitlist
+#[derive(Deserialize, Validate, ToSchema, Debug)]
+pub struct ApproveWaitlistForm {
+    #[validate(range(min = 1, max = 1000, code = "INVALID_COUNT"))]
+    pub count: u32,
+}
+
+/// Representation of a waitlist entry's place in the queue
+#[derive(Serialize, ToSchema, Debug)]
+pub struct WaitlistStatus {
+    #[schema(value_type = String, format = "uuid")]
+    pub token: uuid::Uuid,
+    pub position: Option<i64>,
+    pub approved: bool,
+    pub joined_at: chrono::NaiveDateTime,
+}
+
+impl Listable for WaitlistEntry {
+    const SORT_FIELDS: &'static [&'static str] = &["id", "email"];
+    const FILTERS: &'static [&'static str] = &["approved", "source"];
+
+    fn cursor(&self, sort: &str) -> Cursor {
+        match sort {
+            "email" => Cursor::new(&self.email, self.id),
+            _ => Cursor::new(self.id, self.id),
+        }
+    }
+}
+
+impl JoinWaitlistForm {
+    /// Adds the email to the end of the waitlist, concealing whether it was
+    /// already queued as far as the registration policy asks
+    pub fn join(
+        self,
+        policy: &RegistrationPolicy,
+        store: &dyn WaitlistStore,
+    ) -> Result<WaitlistStatus, ApiError> {
+        self.validate()?;
+
+        match store.join_waitlist(&self) {
+            Err(ApiError::AlreadyOnWaitlist) if policy.anti_enumeration => Self::decoy(store),
+            joined => joined,
+        }
+    }
+
+    /// Answers a join of an email that is already queued like a new join,
+    /// placing it at the end of the queue. The token is a fresh one so the
+    /// queued entry isn't given away, and status lookups don't know it
+    fn decoy(store: &dyn WaitlistStore) -> Result<WaitlistStatus, ApiError> {
+        let pending = ListParams::parse::<WaitlistEntry>("approved=false&limit=1")?;
+        let queued = store.search_waitlist(&pending)?.total;
+
+        Ok(WaitlistStatus {
+            token: uuid::Uuid::new_v4(),
+            position: Some(queued + 1),
+            approved: false,
+            joined_at: Utc::now().naive_utc(),
+        })
+    }
+}
+
+impl WaitlistEntry {
+    /// Inserts an entry for the email at the end of the waitlist
+    pub fn insert(
+        form: &JoinWaitlistForm,
+        conn: &PgConnection,
+    ) -> Result<WaitlistStatus, ApiError> {
+        use crate::schema::waitlist::dsl::*;
+
+        let entry = diesel::insert_into(waitlist)
+            .values((form, token.eq(uuid::Uuid::new_v4())))
+            .on_conflict_do_nothing()
+            .get_result::<WaitlistEntry>(conn)
+            .optional()?
+            .ok_or(ApiError::AlreadyOnWaitlist)?;
+
+        Self::status_of(&entry, conn)
+    }
+
+    /// Returns the place in the queue of the entry with the provided token
+    pub fn status(
+        status_token: &uuid::Uuid,
+        conn: &PgConnection,
+    ) -> Result<WaitlistStatus, ApiError> {
+        use crate::schema::waitlist::dsl::*;
+
+        let entry = waitlist
+            .filter(token.eq(status_token))
+            .first::<WaitlistEntry>(conn)?;
+
+        Self::status_of(&entry, conn)
+    }
+
+    /// Counts the pending entries ahead of and including the entry
+    fn status_of(entry: &WaitlistEntry, conn: &PgConnection) -> Result<WaitlistStatus, ApiError> {
+        use crate::schema::waitlist::dsl::*;
+
+        let position = match entry.approved_at {
+            Some(_) => None,
+            None => Some(
+                waitlist
+                    .filter(approved_at.is_null())
+                    .filter(id.le(entry.id))
+                    .count()
+                    .get_result::<i64>(conn)?,
+            ),
+        };
+
+        Ok(WaitlistStatus {
+            token: entry.token,
+            position,
+            approved: entry.approved_at.is_some(),
+            joined_at: entry.created_at,
+        })
+    }
+
+    /// Issues a single use key to each of the next pending entries, in the
+    /// order they joined, and returns the approved entries
+    pub fn approve_next(count: u32, conn: &PgConnection) -> Result<Vec<WaitlistEntry>, ApiError> {
+        use crate::schema::waitlist::dsl::*;
+        use diesel::dsl::now;
+
+        conn.transaction(|| {
+            // skip entries another approval is already handing keys to
+            let pending = waitlist
+                .filter(approved_at.is_null())
+                .order(id.asc())
+                .limit(i64::from(count))
+                .for_update()
+                .skip_locked()
+                .load::<WaitlistEntry>(conn)?;
+
+            if pending.is_empty() {
+                return Ok(pending);
+            }
+
+            let issued = Key::generate(
+                &GenerateKeysForm {
+                    count: pending.len() as u32,
+                    label: Some(WAITLIST_LABEL.to_string()),
+                    max_redemptions: Some(1),
+                    ..Default::default()
+                },
+                conn,
+            )?;
+
+            pending
+                .iter()
+                .zip(issued.iter())
+                .map(|(entry, key)| {
+                    diesel::update(waitlist.find(entry.id))
+                        .set((approved_at.eq(now.nullable()), key_id.eq(key.id)))
+                        .get_result::<WaitlistEntry>(conn)
+                        .map_err(ApiError::from)
+                })
+                .collect()
+        })
+    }
+
+    /// Returns a filtered, sorted and paginated list of waitlist entries
+    pub fn search(
+        params: &ListParams,
+        conn: &PgConnection,
+    ) -> Result<Page<WaitlistEntry>, ApiError> {
+        use crate::schema::waitlist::dsl::*;
+        use diesel::dsl::count_star;
+
+        let show_approved = params.filter_bool("approved")?;
+
+        let filtered = || {
+            let mut query = waitlist.into_boxed::<Pg>();
+
+            match show_approved {
+                Some(true) => query = query.filter(approved_at.is_not_null()),
+                Some(false) => query = query.filter(approved_at.is_null()),
+                None => {}
+            }
+
+            if let Some(name) = params.filter("source") {
+                query = query.filter(source.eq(name.to_string()));
+            }
+
+            query
+        };
+
+        let total = filtered().select(count_star()).first::<i64>(conn)?;
+
+        let query = match params.sort.as_str() {
+            "email" => sort_by!(filtered(), params, email, id, String, i32),
+            _ => sort_by!(filtered(), params, id, id, i32, i32),
+        };
+
+        let rows = query
+            .limit(params.fetch_limit())
+            .offset(params.fetch_offset())
+            .load::<WaitlistEntry>(conn)?;
+
+        Ok(Page::from_rows(rows, total, params))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::tests::create_store;
+    use crate::store::postgres::PgStore;
+
+    /// Adds a unique email to the waitlist
+    fn join(store: &PgStore) -> WaitlistStatus {
+        let form = JoinWaitlistForm {
+            email: format!("{}@wait.com", &uuid::Uuid::new_v4().to_string()[..8]),
+            source: Some("test".to_string()),
+        };
+
+        form.join(&RegistrationPolicy::default(), store)
+            .expect("failed to join waitlist")
+    }
+
+    #[test]
+    fn it_refuses_duplicate_emails() {
+        let store = create_store();
+
+        let form = || JoinWaitlistForm {
+            email: "twice@wait.com".to_string(),
+            source: None,
+        };
+
+        let policy = RegistrationPolicy::default();
+        assert!(form().join(&policy, &store).is_ok());
+
+        match form().join(&policy, &store) {
+            Err(ApiError::AlreadyOnWaitlist) => {}
+            other => panic!("expected duplicate error, got {:?}", other),
+        }
+
+        let policy = RegistrationPolicy {
+            anti_enumeration: true,
+            ..Default::default()
+        };
+        let concealed = form().join(&policy, &store).unwrap();
+        assert!(!concealed.approved);
+        assert!(concealed.position.is_some());
+        assert!(WaitlistEntry::status(&concealed.token, &store.conn().unwrap()).is_err());
+    }
+
+    #[test]
+    fn it_approves_entries_in_order() {
+        let store = create_store();
+        let conn = store.conn().unwrap();
+
+        let first = join(&store);
+        let second = join(&store);
+        assert!(first.position.unwrap() < second.position.unwrap());
+
+        // approve everything queued so far, including entries from other tests
+        let approved = WaitlistEntry::approve_next(1000, &conn).unwrap();
+        assert!(approved.iter().all(|entry| entry.key_id.is_some()));
+
+        let status = WaitlistEntry::status(&first.token, &conn).unwrap();
+        assert!(status.approved);
+        assert_eq!(status.position, None);
+
+        let entry = approved
+            .iter()
+            .find(|entry| entry.token == first.token)
+            .unwrap();
+        assert!(Key::is_available(&entry.key_id.unwrap(), &conn).unwrap());
+    }
+}
@@ -1,34 +1,188 @@
 use actix_web::web;
-use actix_web::Error;
+use actix_web::{Error, HttpMessage};
 
+use crate::controllers::{admin, docs, health, invite, jwks, key, metrics, user, waitlist};
+use crate::db::Database;
+use crate::models::user::User;
+use crate::settings::ServerSettings;
 use crate::utils::errors::ApiError;
-use crate::controllers::{key, user};
-use crate::utils::token::Token;
+use crate::utils::i18n::Locale;
+use crate::utils::keyring::Keyring;
+use crate::utils::metrics::{self as request_metrics, Metrics};
 
 use actix_web::dev::ServiceRequest;
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use actix_web_httpauth::middleware::HttpAuthentication;
 
-/// Middleware validator used to ensure the provided bearer token is valid
-async fn validator(req: ServiceRequest, credentials: BearerAuth) -> Result<ServiceRequest, Error> {
-    let token = credentials.token();
+/// Authenticates the request, counting accepted and rejected tokens
+async fn authenticate(req: &ServiceRequest, credentials: &BearerAuth) -> Result<User, ApiError> {
+    request_metrics::record_route(req);
 
-    match Token::decode(&token) {
-        Ok(_) => Ok(req),
-        Err(_) => Err(ApiError::Unauthorized.into()),
+    let user = verify(req, credentials).await;
+    if let Some(metrics) = req.app_data::<Metrics>() {
+        metrics.record_token_validation(user.is_ok());
     }
+
+    user
+}
+
+/// Decodes the bearer token and loads the user it was issued to, refusing
+/// disabled and deleted users
+async fn verify(req: &ServiceRequest, credentials: &BearerAuth) -> Result<User, ApiError> {
+    let keyring = req.app_data::<Keyring>().ok_or(ApiError::Unauthorized)?;
+    let token = keyring.decode(credentials.token())?;
+
+    let db = req.app_data::<Database>().ok_or(ApiError::Unauthorized)?;
+
+    // shed load rather than logging everyone out while the database is busy
+    let user_id = token.sub;
+    let user = db
+        .run(move |store| store.find_user(user_id))
+        .await
+        .map_err(|error| match error {
+            ApiError::DatabaseBusy => error,
+            _ => ApiError::Unauthorized,
+        })?;
+
+    if user.disabled {
+        return Err(ApiError::AccountDisabled);
+    }
+
+    if user.deleted_at.is_some() {
+        return Err(ApiError::Unauthorized);
+    }
+
+    // write errors in the language the user chose
+    Locale::prefer(req, user.locale.as_deref());
+
+    // make the verified claims available to handlers
+    req.extensions_mut().insert(token);
+
+    Ok(user)
+}
+
+/// Middleware validator used to ensure the provided bearer token is valid,
+/// for use with `HttpAuthentication::bearer`
+pub async fn validator(
+    req: ServiceRequest,
+    credentials: BearerAuth,
+) -> Result<ServiceRequest, Error> {
+    authenticate(&req, &credentials).await?;
+
+    Ok(req)
 }
 
-/// Defines all of the routes for the application
-pub fn define_routes(cfg: &mut web::ServiceConfig) {
+/// Middleware validator used to ensure the provided bearer token belongs to an admin
+pub async fn admin_validator(
+    req: ServiceRequest,
+    credentials: BearerAuth,
+) -> Result<ServiceRequest, Error> {
+    let user = authenticate(&req, &credentials).await?;
+
+    if !user.is_admin() {
+        return Err(ApiError::Forbidden.into());
+    }
+
+    Ok(req)
+}
+
+/// Configures the JSON extractor to answer malformed, oversized and non JSON
+/// bodies with an ApiError
+fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(|error, _| ApiError::from(error).into())
+}
+
+/// Defines all of the routes for the application with the default payload
+/// limits, expecting the state registered by `Auth::data`
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    mount(cfg, &ServerSettings::default());
+}
+
+/// Defines all of the routes for the application, accepting bodies up to
+/// the limits of the server settings
+pub fn mount(cfg: &mut web::ServiceConfig, server: &ServerSettings) {
+    let json = json_config(server.json_limit_bytes);
     let middleware = HttpAuthentication::bearer(validator);
     cfg.service(
         web::resource("/users")
             .wrap(middleware)
             .route(web::get().to(user::get)),
     )
+    .service(
+        web::resource("/invites")
+            .app_data(json.clone())
+            .wrap(HttpAuthentication::bearer(validator))
+            .route(web::get().to(invite::get))
+            .route(web::post().to(invite::create)),
+    )
+    // admin routes
+    .service(
+        web::scope("/admin")
+            .app_data(json.clone())
+            .wrap(HttpAuthentication::bearer(admin_validator))
+            .service(web::resource("/users").route(web::get().to(admin::list_users)))
+            .service(web::resource("/users/{id}").route(web::delete().to(admin::delete_user)))
+            .service(
+                web::resource("/users/{id}/disable").route(web::post().to(admin::disable_user)),
+            )
+            .service(web::resource("/users/{id}/enable").route(web::post().to(admin::enable_user)))
+            .service(
+                web::resource("/users/{id}/restore").route(web::post().to(admin::restore_user)),
+            )
+            .service(
+                web::resource("/users/{id}/referrals").route(web::get().to(admin::user_referrals)),
+            )
+            .service(
+                web::resource("/keys")
+                    .route(web::get().to(admin::list_keys))
+                    .route(web::post().to(admin::generate_keys)),
+            )
+            .service(
+                web::resource("/keys/import")
+                    .app_data(web::PayloadConfig::new(server.import_limit_bytes))
+                    .route(web::post().to(admin::import_keys)),
+            )
+            .service(web::resource("/keys/export").route(web::get().to(admin::export_keys)))
+            .service(web::resource("/keys/{id}").route(web::delete().to(admin::revoke_key)))
+            .service(
+                web::resource("/keys/{id}/redemptions")
+                    .route(web::get().to(admin::key_redemptions)),
+            )
+            .service(web::resource("/waitlist").route(web::get().to(admin::list_waitlist)))
+            .service(
+                web::resource("/waitlist/approve").route(web::post().to(admin::approve_waitlist)),
+            ),
+    )
     // public routes
-    .service(web::resource("/keys").route(web::post().to(key::check_key)))
-    .service(web::resource("/signup").route(web::post().to(user::create)))
-    .service(web::resource("/login").route(web::post().to(user::login)));
+    .service(web::resource("/healthz").route(web::get().to(health::live)))
+    .service(web::resource("/readyz").route(web::get().to(health::ready)))
+    .service(web::resource("/metrics").route(web::get().to(metrics::get)))
+    .service(web::resource("/.well-known/jwks.json").route(web::get().to(jwks::get)))
+    .service(web::resource("/openapi.json").route(web::get().to(docs::openapi)))
+    .service(web::resource("/docs").route(web::get().to(docs::ui)))
+    .service(web::resource("/docs/swagger-ui.css").route(web::get().to(docs::stylesheet)))
+    .service(web::resource("/docs/swagger-ui-bundle.js").route(web::get().to(docs::script)))
+    .service(
+        web::resource("/keys")
+            .app_data(json.clone())
+            .route(web::post().to(key::check_key)),
+    )
+    .service(
+        web::resource("/waitlist")
+            .app_data(json.clone())
+            .route(web::post().to(waitlist::join)),
+    )
+    .service(web::resource("/waitlist/{token}").route(web::get().to(waitlist::status)))
+    .service(
+        web::resource("/signup")
+            .app_data(json.clone())
+            .route(web::post().to(user::create)),
+    )
+    .service(
+        web::resource("/login")
+            .app_data(json)
+            .route(web::post().to(user::login)),
+    );
 }
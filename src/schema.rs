@@ -1,6 +1,32 @@
 table! {
     keys (id) {
         id -> Uuid,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        label -> Nullable<Varchar>,
+        expires_at -> Nullable<Timestamp>,
+        max_redemptions -> Int4,
+        redemption_count -> Int4,
+        created_by -> Nullable<Int4>,
+    }
+}
+
+table! {
+    redemptions (key_id, user_id) {
+        key_id -> Uuid,
+        user_id -> Int4,
+        redeemed_at -> Timestamp,
+    }
+}
+
+table! {
+    signing_keys (kid) {
+        kid -> Varchar,
+        algorithm -> Varchar,
+        private_key -> Text,
+        public_key -> Text,
+        created_at -> Timestamp,
+        retired_at -> Nullable<Timestamp>,
     }
 }
 
@@ -9,14 +35,37 @@ table! {
         id -> Int4,
         email -> Varchar,
         password -> Varchar,
-        key_id -> Uuid,
+        key_id -> Nullable<Uuid>,
+        created_at -> Timestamp,
+        role -> Varchar,
+        disabled -> Bool,
+        deleted_at -> Nullable<Timestamp>,
+        invited_by -> Nullable<Int4>,
+        locale -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    waitlist (id) {
+        id -> Int4,
+        email -> Varchar,
+        source -> Nullable<Varchar>,
+        token -> Uuid,
         created_at -> Timestamp,
+        approved_at -> Nullable<Timestamp>,
+        key_id -> Nullable<Uuid>,
     }
 }
 
+joinable!(redemptions -> keys (key_id));
+joinable!(redemptions -> users (user_id));
 joinable!(users -> keys (key_id));
+joinable!(waitlist -> keys (key_id));
 
 allow_tables_to_appear_in_same_query!(
     keys,
+    redemptions,
+    signing_keys,
     users,
+    waitlist,
 );
@@ -0,0 +1,653 @@
+use chrono::NaiveDateTime;
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::db;
+use crate::models::invite::Referral;
+use crate::models::key::{GenerateKeysForm, Key, RedeemedBy};
+use crate::models::signing_key::SigningKey;
+use crate::models::user::{ManagedUser, NewUserForm, User, ViewableUser};
+use crate::models::waitlist::{JoinWaitlistForm, WaitlistEntry, WaitlistStatus};
+use crate::settings::DatabaseSettings;
+use crate::utils::errors::{ApiError, InternalCode};
+use crate::utils::pagination::{ListParams, Page};
+
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+/// Storage of users
+pub trait UserStore {
+    /// Returns a filtered, sorted and paginated list of users that have not been deleted
+    fn list_users(&self, params: &ListParams) -> Result<Page<ViewableUser>, ApiError>;
+
+    /// Finds a user by id, including disabled and deleted users
+    fn find_user(&self, user_id: i32) -> Result<User, ApiError>;
+
+    /// Finds a user that has not been deleted by email
+    fn find_user_by_email(&self, email: &str) -> Result<Option<User>, ApiError>;
+
+    /// Returns a filtered, sorted and paginated list of users for administrators
+    fn search_users(&self, params: &ListParams) -> Result<Page<ManagedUser>, ApiError>;
+
+    /// Disables or enables the user with the provided id
+    fn set_disabled(&self, user_id: i32, disabled: bool) -> Result<ManagedUser, ApiError>;
+
+    /// Grants the already validated role to the user with the provided id
+    fn set_role(&self, user_id: i32, role: &str) -> Result<ManagedUser, ApiError>;
+
+    /// Soft deletes the user with the provided id
+    fn soft_delete_user(&self, user_id: i32) -> Result<ManagedUser, ApiError>;
+
+    /// Restores a previously soft deleted user
+    fn restore_user(&self, user_id: i32) -> Result<ManagedUser, ApiError>;
+
+    /// Inserts a user with the role and a password that has already been hashed,
+    /// atomically claiming a redemption of their key and crediting whoever minted it
+    fn insert_user(&self, new_user: &NewUserForm, role: &str) -> Result<User, ApiError>;
+
+    /// Returns the tree of users referred by the user, directly or not
+    fn referral_tree(&self, user_id: i32) -> Result<Vec<Referral>, ApiError>;
+}
+
+/// Storage of beta keys and their redemptions
+pub trait KeyStore {
+    /// Checks if the provided key exists and has not been revoked
+    fn has_key(&self, key: &uuid::Uuid) -> Result<bool, ApiError>;
+
+    /// Checks if the key is valid, unexpired and has redemptions left
+    fn is_key_available(&self, key: &uuid::Uuid) -> Result<bool, ApiError>;
+
+    /// Generates new keys as described by the form
+    fn generate_keys(&self, form: &GenerateKeysForm) -> Result<Vec<Key>, ApiError>;
+
+    /// Imports the provided keys, skipping any that already exist, and returns
+    /// the number of keys that were inserted
+    fn import_keys(&self, ids: &[uuid::Uuid]) -> Result<usize, ApiError>;
+
+    /// Revokes a key so it can't be redeemed any further
+    fn revoke_key(&self, key: uuid::Uuid) -> Result<Key, ApiError>;
+
+    /// Returns the users who redeemed the key
+    fn key_redemptions(&self, key: uuid::Uuid) -> Result<Vec<RedeemedBy>, ApiError>;
+
+    /// Returns a filtered, sorted and paginated list of keys
+    fn search_keys(&self, params: &ListParams) -> Result<Page<Key>, ApiError>;
+
+    /// Returns every key matching the filters, ignoring pagination
+    fn export_keys(&self, params: &ListParams) -> Result<Vec<Key>, ApiError>;
+
+    /// Mints a single use key on behalf of the inviter, as long as they have
+    /// not reached the provided limit
+    fn mint_invite(&self, inviter: i32, limit: i64) -> Result<Key, ApiError>;
+
+    /// Returns every invite minted by the user, newest first
+    fn invites_sent_by(&self, inviter: i32) -> Result<Vec<Key>, ApiError>;
+}
+
+/// Storage of people waiting for a beta key
+pub trait WaitlistStore {
+    /// Adds an already validated email to the end of the waitlist
+    fn join_waitlist(&self, form: &JoinWaitlistForm) -> Result<WaitlistStatus, ApiError>;
+
+    /// Returns the place in the queue of the entry with the provided token
+    fn waitlist_status(&self, token: &uuid::Uuid) -> Result<WaitlistStatus, ApiError>;
+
+    /// Issues a single use key to each of the next pending entries, in the
+    /// order they joined, and returns the approved entries
+    fn approve_waitlist(&self, count: u32) -> Result<Vec<WaitlistEntry>, ApiError>;
+
+    /// Returns a filtered, sorted and paginated list of waitlist entries
+    fn search_waitlist(&self, params: &ListParams) -> Result<Page<WaitlistEntry>, ApiError>;
+}
+
+/// Storage of the key pairs tokens are signed with
+pub trait SigningKeyStore {
+    /// Returns the active keys and those retired after the provided time,
+    /// newest first
+    fn signing_keys(&self, retired_after: NaiveDateTime) -> Result<Vec<SigningKey>, ApiError>;
+
+    /// Retires the active keys and makes the provided key the one signing tokens
+    fn rotate_signing_key(&self, key: &SigningKey) -> Result<(), ApiError>;
+}
+
+/// Management of the database schema and connections
+pub trait SchemaStore {
+    /// Applies the pending migrations embedded in the binary and returns
+    /// the versions that were applied
+    fn migrate(&self) -> Result<Vec<String>, ApiError>;
+
+    /// Returns the versions of the embedded migrations not applied yet
+    fn pending_migrations(&self) -> Result<Vec<String>, ApiError>;
+
+    /// Checks that a connection to the database can be used
+    fn ping(&self) -> Result<(), ApiError>;
+
+    /// Returns the state of the connection pool, for backends that have one
+    fn pool_state(&self) -> Option<PoolState>;
+}
+
+/// Running several calls as a single unit
+pub trait TransactionStore {
+    /// Runs the steps against a store whose calls all take part in a single
+    /// transaction, committing once the steps succeed and rolling back when
+    /// they fail. Nested units of work roll back on their own
+    fn transaction(
+        &self,
+        steps: &mut dyn FnMut(&dyn Store) -> Result<(), ApiError>,
+    ) -> Result<(), ApiError>;
+}
+
+/// Connections of the pool a store checks connections out from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolState {
+    pub connections: u32,
+    pub idle_connections: u32,
+    pub max_connections: u32,
+}
+
+impl PoolState {
+    /// Returns the state of an r2d2 pool
+    pub fn of<M: r2d2::ManageConnection>(pool: &r2d2::Pool<M>) -> Self {
+        let state = pool.state();
+
+        PoolState {
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+            max_connections: pool.max_size(),
+        }
+    }
+}
+
+/// Everything the application persists, implemented by every backend
+pub trait Store:
+    UserStore
+    + KeyStore
+    + WaitlistStore
+    + SigningKeyStore
+    + SchemaStore
+    + TransactionStore
+    + Send
+    + Sync
+{
+}
+
+impl<T> Store for T where
+    T: UserStore
+        + KeyStore
+        + WaitlistStore
+        + SigningKeyStore
+        + SchemaStore
+        + TransactionStore
+        + Send
+        + Sync
+{
+}
+
+/// Runs the steps of a multi step operation as one unit of work, which
+/// commits once they all succeed and rolls back as soon as one of them fails
+pub fn unit_of_work<T, F>(store: &dyn Store, steps: F) -> Result<T, ApiError>
+where
+    F: FnOnce(&dyn Store) -> Result<T, ApiError>,
+{
+    let span = tracing::info_span!("transaction", outcome = tracing::field::Empty);
+    let _entered = span.enter();
+
+    let mut steps = Some(steps);
+    let mut value = None;
+    let result = store.transaction(&mut |store| {
+        if let Some(steps) = steps.take() {
+            value = Some(steps(store)?);
+        }
+        Ok(())
+    });
+
+    let outcome = if result.is_ok() { "commit" } else { "rollback" };
+    span.record("outcome", outcome);
+
+    result?;
+    value.ok_or_else(|| {
+        ApiError::InternalServerError(
+            InternalCode::Store,
+            String::from("The unit of work did not run its steps"),
+        )
+    })
+}
+
+/// Store shared between the workers of the server
+pub type SharedStore = Arc<dyn Store>;
+
+/// Backend data is stored in
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// A Postgres database, the url being a connection url
+    #[default]
+    Postgres,
+    /// An embedded SQLite database, the url being a file path or `:memory:`
+    Sqlite,
+    /// Process memory, losing everything on restart
+    Memory,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(backend: &str) -> Result<Self, Self::Err> {
+        match backend {
+            "postgres" => Ok(Backend::Postgres),
+            "sqlite" => Ok(Backend::Sqlite),
+            "memory" => Ok(Backend::Memory),
+            _ => Err(format!("unknown database backend {:?}", backend)),
+        }
+    }
+}
+
+/// Representation of an error preventing the store from being opened
+#[derive(Fail, Debug)]
+pub enum StoreError {
+    #[fail(display = "Could not connect to the database: {}", _0)]
+    Connect(String),
+    #[fail(display = "Could not run the database migrations: {}", _0)]
+    Migrate(String),
+}
+
+/// Prefix diesel writes before the version of every migration it runs
+const MIGRATION_PREFIX: &str = "Running migration ";
+
+/// Returns the versions of the migrations diesel wrote it ran to the output
+pub(crate) fn applied_migrations(output: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .filter_map(|line| line.strip_prefix(MIGRATION_PREFIX))
+        .map(String::from)
+        .collect()
+}
+
+/// Opens the store of the configured backend, applying pending migrations
+/// when configured to
+pub fn open(settings: &DatabaseSettings) -> Result<SharedStore, StoreError> {
+    let store: SharedStore = match settings.backend {
+        Backend::Postgres => {
+            let pool = db::create_pool(settings)
+                .map_err(|error| StoreError::Connect(error.to_string()))?;
+            Arc::new(postgres::PgStore::new(pool))
+        }
+        Backend::Sqlite => Arc::new(sqlite::SqliteStore::open(settings)?),
+        Backend::Memory => Arc::new(memory::MemoryStore::new()),
+    };
+
+    if settings.run_migrations {
+        let applied = store
+            .migrate()
+            .map_err(|error| StoreError::Migrate(error.to_string()))?;
+
+        for version in applied {
+            info!("Applied migration {}", version);
+        }
+    }
+
+    Ok(store)
+}
+
+#[cfg(test)]
+pub mod tests {
+    //! Scenarios every backend has to pass, run against each of them through `store_tests!`
+
+    use super::*;
+    use crate::models::registration::{RegistrationMode, RegistrationPolicy};
+    use crate::models::user::{LoginUserForm, ADMIN_ROLE};
+
+    /// Returns a unique email on the provided domain
+    fn unique_email(domain: &str) -> String {
+        format!("{}@{}", &uuid::Uuid::new_v4().to_string()[..8], domain)
+    }
+
+    /// Generates a single key redeemable the provided number of times
+    fn generate_key(store: &dyn Store, max_redemptions: i32) -> Key {
+        let form = GenerateKeysForm {
+            count: 1,
+            max_redemptions: Some(max_redemptions),
+            ..Default::default()
+        };
+
+        store.generate_keys(&form).unwrap().remove(0)
+    }
+
+    /// Signs up a user with the provided email redeeming the key
+    fn sign_up(store: &dyn Store, email: &str, key: &uuid::Uuid) -> Result<User, ApiError> {
+        let new_user = NewUserForm {
+            email: email.to_string(),
+            password: "password".to_string(),
+            key_id: Some(*key),
+            locale: None,
+        };
+
+        new_user.create(&RegistrationPolicy::default(), store)
+    }
+
+    pub fn it_redeems_keys_up_to_their_limit(store: &dyn Store) {
+        let key = generate_key(store, 2);
+        assert!(store.is_key_available(&key.id).unwrap());
+
+        let first = sign_up(store, &unique_email("store.com"), &key.id).unwrap();
+        sign_up(store, &unique_email("store.com"), &key.id).unwrap();
+        assert!(!store.is_key_available(&key.id).unwrap());
+
+        match sign_up(store, &unique_email("store.com"), &key.id) {
+            Err(ApiError::KeyAlreadyRedeemed) => {}
+            other => panic!("expected redeemed key error, got {:?}", other),
+        }
+
+        match sign_up(store, &unique_email("store.com"), &uuid::Uuid::new_v4()) {
+            Err(ApiError::InvalidBetaKey) => {}
+            other => panic!("expected invalid key error, got {:?}", other),
+        }
+
+        let redeemed_by = store.key_redemptions(key.id).unwrap();
+        assert_eq!(redeemed_by.len(), 2);
+        assert_eq!(redeemed_by[0].user_id, first.id);
+
+        match store.revoke_key(key.id) {
+            Err(ApiError::KeyAlreadyRedeemed) => {}
+            other => panic!("expected redeemed key error, got {:?}", other),
+        }
+
+        let unused = generate_key(store, 1);
+        assert!(store.revoke_key(unused.id).unwrap().revoked_at.is_some());
+        assert!(!store.has_key(&unused.id).unwrap());
+        assert!(sign_up(store, &unique_email("store.com"), &unused.id).is_err());
+
+        let existing = generate_key(store, 1);
+        let imported = store.import_keys(&[existing.id, uuid::Uuid::new_v4()]);
+        assert_eq!(imported.unwrap(), 1);
+    }
+
+    pub fn it_reports_taken_emails(store: &dyn Store) {
+        let key = generate_key(store, 10);
+        let email = unique_email("store.com");
+        sign_up(store, &email, &key.id).unwrap();
+
+        match sign_up(store, &email, &key.id) {
+            Err(ApiError::EmailTaken) => {}
+            other => panic!("expected taken email error, got {:?}", other),
+        }
+
+        let policy = RegistrationPolicy {
+            anti_enumeration: true,
+            ..Default::default()
+        };
+        let new_user = NewUserForm {
+            email,
+            password: "password".to_string(),
+            key_id: Some(key.id),
+            locale: None,
+        };
+        match new_user.create(&policy, store) {
+            Err(ApiError::SignupFailed) => {}
+            other => panic!("expected concealed signup error, got {:?}", other),
+        }
+
+        let new_user = NewUserForm {
+            email: unique_email("store.com"),
+            password: "password".to_string(),
+            key_id: Some(uuid::Uuid::new_v4()),
+            locale: None,
+        };
+        match new_user.create(&policy, store) {
+            Err(ApiError::SignupFailed) => {}
+            other => panic!("expected concealed key error, got {:?}", other),
+        }
+
+        assert_eq!(store.key_redemptions(key.id).unwrap().len(), 1);
+    }
+
+    pub fn it_inserts_users_with_a_role(store: &dyn Store) {
+        let policy = RegistrationPolicy {
+            mode: RegistrationMode::Open,
+            ..Default::default()
+        };
+        let new_user = |email: &str| NewUserForm {
+            email: email.to_string(),
+            password: "password".to_string(),
+            key_id: None,
+            locale: None,
+        };
+
+        let email = unique_email("store.com");
+        let admin = new_user(&email).create_with_role(ADMIN_ROLE, &policy, store);
+        assert!(admin.unwrap().is_admin());
+
+        let email = unique_email("store.com");
+        let unknown_role = new_user(&email).create_with_role("root", &policy, store);
+        assert!(unknown_role.is_err());
+        assert!(store.find_user_by_email(&email).unwrap().is_none());
+    }
+
+    pub fn it_manages_and_lists_users(store: &dyn Store) {
+        let key = generate_key(store, 10);
+
+        let mut created = Vec::new();
+        for _ in 0..3 {
+            created.push(sign_up(store, &unique_email("store.com"), &key.id).unwrap());
+        }
+
+        let user = &created[0];
+        let login = || LoginUserForm {
+            email: user.email.clone(),
+            password: "password".to_string(),
+        };
+        assert_eq!(login().verify_user(store).unwrap().unwrap().id, user.id);
+
+        assert!(store.set_disabled(user.id, true).unwrap().disabled);
+        match login().verify_user(store) {
+            Err(ApiError::AccountDisabled) => {}
+            other => panic!("expected disabled account, got {:?}", other),
+        }
+        store.set_disabled(user.id, false).unwrap();
+
+        assert_eq!(
+            store.set_role(user.id, ADMIN_ROLE).unwrap().role,
+            ADMIN_ROLE
+        );
+        assert!(store.find_user(user.id).unwrap().is_admin());
+        assert!(store.set_role(-1, ADMIN_ROLE).is_err());
+
+        assert!(store
+            .soft_delete_user(user.id)
+            .unwrap()
+            .deleted_at
+            .is_some());
+        assert!(login().verify_user(store).unwrap().is_none());
+        assert!(store.soft_delete_user(user.id).is_err());
+        assert!(store.restore_user(user.id).unwrap().deleted_at.is_none());
+        assert!(store.find_user(user.id).unwrap().is_active());
+
+        let query = format!("email={}&sort=-created_at", user.email);
+        let params = ListParams::parse::<ManagedUser>(&query).unwrap();
+        assert_eq!(store.search_users(&params).unwrap().total, 1);
+
+        // page through the users two at a time, newest first
+        let query = "sort=-id&limit=2";
+        let params = ListParams::parse::<ManagedUser>(query).unwrap();
+        let first = store.search_users(&params).unwrap();
+        let cursor = first.next_cursor.clone().expect("expected a next page");
+
+        let query = format!("sort=-id&limit=2&cursor={}", cursor.encode());
+        let params = ListParams::parse::<ManagedUser>(&query).unwrap();
+        let second = store.search_users(&params).unwrap();
+
+        assert_eq!(first.items.len(), 2);
+        assert!(second.items[0].id < first.items[1].id);
+
+        let params = ListParams::parse::<ViewableUser>("sort=email").unwrap();
+        let listed = store.list_users(&params).unwrap();
+        assert!(listed.total >= 3);
+    }
+
+    pub fn it_caps_invites_and_tracks_referrals(store: &dyn Store) {
+        let key = generate_key(store, 1);
+        let root = sign_up(store, &unique_email("store.com"), &key.id).unwrap();
+
+        let invite = store.mint_invite(root.id, 2).unwrap();
+        let child = sign_up(store, &unique_email("store.com"), &invite.id).unwrap();
+        assert_eq!(child.invited_by, Some(root.id));
+
+        store.mint_invite(root.id, 2).unwrap();
+        match store.mint_invite(root.id, 2) {
+            Err(ApiError::InviteLimitReached) => {}
+            other => panic!("expected invite limit error, got {:?}", other),
+        }
+        assert_eq!(store.invites_sent_by(root.id).unwrap().len(), 2);
+
+        let invite = store.mint_invite(child.id, 2).unwrap();
+        let grandchild = sign_up(store, &unique_email("store.com"), &invite.id).unwrap();
+
+        let tree = store.referral_tree(root.id).unwrap();
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].id, child.id);
+        assert_eq!(tree[0].referrals[0].id, grandchild.id);
+        assert_eq!(store.invites_sent_by(child.id).unwrap().len(), 1);
+    }
+
+    pub fn it_approves_the_waitlist_in_order(store: &dyn Store) {
+        let join = |email: &str| {
+            JoinWaitlistForm {
+                email: email.to_string(),
+                source: Some("store".to_string()),
+            }
+            .join(&RegistrationPolicy::default(), store)
+        };
+
+        let email = unique_email("wait.com");
+        let first = join(&email).unwrap();
+        let second = join(&unique_email("wait.com")).unwrap();
+        assert!(first.position.unwrap() < second.position.unwrap());
+
+        match join(&email) {
+            Err(ApiError::AlreadyOnWaitlist) => {}
+            other => panic!("expected duplicate error, got {:?}", other),
+        }
+
+        // approve everything queued so far, including entries from other tests
+        let approved = store.approve_waitlist(1000).unwrap();
+        assert!(approved.iter().all(|entry| entry.key_id.is_some()));
+
+        let status = store.waitlist_status(&first.token).unwrap();
+        assert!(status.approved);
+        assert_eq!(status.position, None);
+
+        let entry = approved.iter().find(|e| e.token == first.token).unwrap();
+        assert!(store.is_key_available(&entry.key_id.unwrap()).unwrap());
+
+        let params = ListParams::parse::<WaitlistEntry>("source=store&approved=true").unwrap();
+        assert!(store.search_waitlist(&params).unwrap().total >= 2);
+    }
+
+    pub fn it_rotates_signing_keys(store: &dyn Store) {
+        let first = SigningKey::generate().unwrap();
+        store.rotate_signing_key(&first).unwrap();
+
+        let second = SigningKey {
+            created_at: first.created_at + chrono::Duration::seconds(1),
+            ..SigningKey::generate().unwrap()
+        };
+        store.rotate_signing_key(&second).unwrap();
+
+        // the first key was retired when the second one was added
+        let active = store.signing_keys(second.created_at).unwrap();
+        assert_eq!(active[0].kid, second.kid);
+        assert!(active.iter().all(|key| key.kid != first.kid));
+
+        let usable = store.signing_keys(first.created_at).unwrap();
+        let retired = usable.iter().find(|key| key.kid == first.kid).unwrap();
+        assert!(retired.retired_at.is_some());
+    }
+
+    pub fn it_rolls_back_failed_units_of_work(store: &dyn Store) {
+        let mut generated = None;
+        let failed: Result<(), ApiError> = unit_of_work(store, |store| {
+            generated = Some(generate_key(store, 1));
+            sign_up(store, &unique_email("store.com"), &uuid::Uuid::new_v4())?;
+            Ok(())
+        });
+
+        assert!(failed.is_err());
+        assert!(!store.has_key(&generated.unwrap().id).unwrap());
+
+        let key = unit_of_work(store, |store| {
+            let key = generate_key(store, 1);
+            let nested: Result<(), ApiError> = unit_of_work(store, |store| {
+                sign_up(store, &unique_email("store.com"), &key.id)?;
+                Err(ApiError::SignupFailed)
+            });
+            assert!(nested.is_err());
+
+            Ok(key)
+        })
+        .unwrap();
+
+        assert!(store.has_key(&key.id).unwrap());
+        assert!(store.key_redemptions(key.id).unwrap().is_empty());
+    }
+
+    pub fn it_reports_an_up_to_date_schema(store: &dyn Store) {
+        assert!(store.migrate().is_ok());
+        assert!(store.pending_migrations().unwrap().is_empty());
+        assert!(store.ping().is_ok());
+    }
+
+    /// Runs every store scenario against the store returned by the expression
+    macro_rules! store_tests {
+        ($store:expr) => {
+            #[test]
+            fn it_redeems_keys_up_to_their_limit() {
+                crate::store::tests::it_redeems_keys_up_to_their_limit(&$store);
+            }
+
+            #[test]
+            fn it_reports_taken_emails() {
+                crate::store::tests::it_reports_taken_emails(&$store);
+            }
+
+            #[test]
+            fn it_inserts_users_with_a_role() {
+                crate::store::tests::it_inserts_users_with_a_role(&$store);
+            }
+
+            #[test]
+            fn it_manages_and_lists_users() {
+                crate::store::tests::it_manages_and_lists_users(&$store);
+            }
+
+            #[test]
+            fn it_caps_invites_and_tracks_referrals() {
+                crate::store::tests::it_caps_invites_and_tracks_referrals(&$store);
+            }
+
+            #[test]
+            fn it_approves_the_waitlist_in_order() {
+                crate::store::tests::it_approves_the_waitlist_in_order(&$store);
+            }
+
+            #[test]
+            fn it_rotates_signing_keys() {
+                crate::store::tests::it_rotates_signing_keys(&$store);
+            }
+
+            #[test]
+            fn it_rolls_back_failed_units_of_work() {
+                crate::store::tests::it_rolls_back_failed_units_of_work(&$store);
+            }
+
+            #[test]
+            fn it_reports_an_up_to_date_schema() {
+                crate::store::tests::it_reports_an_up_to_date_schema(&$store);
+            }
+        };
+    }
+
+    pub(crate) use store_tests;
+}
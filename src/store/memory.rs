@@ -0,0 +1,794 @@
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use crate::models::invite::{build_tree, Referral, ReferralRow, INVITE_LABEL};
+use crate::models::key::{GenerateKeysForm, Key, NewKey, RedeemedBy, Redemption};
+use crate::models::signing_key::SigningKey;
+use crate::models::user::{ManagedUser, NewUserForm, User, ViewableUser};
+use crate::models::waitlist::{JoinWaitlistForm, WaitlistEntry, WaitlistStatus, WAITLIST_LABEL};
+use crate::store::{
+    KeyStore, PoolState, SchemaStore, SigningKeyStore, Store, TransactionStore, UserStore,
+    WaitlistStore,
+};
+use crate::utils::errors::{ApiError, InternalCode};
+use crate::utils::pagination::{Cursor, ListParams, Listable, Page, SortOrder};
+
+/// Store keeping everything in process memory, used to run embedded and in tests
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+/// Rows of every table, each kept in insertion order
+#[derive(Default, Clone)]
+struct Tables {
+    users: Vec<User>,
+    keys: Vec<Key>,
+    redemptions: Vec<Redemption>,
+    waitlist: Vec<WaitlistEntry>,
+    signing_keys: Vec<SigningKey>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Locks the tables for the duration of a call, making every call atomic
+    fn tables(&self) -> Result<MutexGuard<'_, Tables>, ApiError> {
+        self.tables.lock().map_err(|_| {
+            ApiError::InternalServerError(
+                InternalCode::Store,
+                String::from("The in-memory store is unavailable"),
+            )
+        })
+    }
+}
+
+/// Returns the current time the way the database would store it
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+/// Converts the timestamp of a user to the representation used everywhere else
+fn naive(time: SystemTime) -> NaiveDateTime {
+    chrono::DateTime::<Utc>::from(time).naive_utc()
+}
+
+/// Checks if the key is valid, unexpired and has redemptions left
+fn is_available(key: &Key, at: NaiveDateTime) -> bool {
+    key.revoked_at.is_none()
+        && key.expires_at.is_none_or(|expires_at| expires_at > at)
+        && key.redemption_count < key.max_redemptions
+}
+
+/// Checks if the value starts with the prefix, ignoring case like ILIKE does
+fn has_prefix(value: &str, prefix: &str) -> bool {
+    value.to_lowercase().starts_with(&prefix.to_lowercase())
+}
+
+impl From<&User> for ManagedUser {
+    fn from(user: &User) -> Self {
+        ManagedUser {
+            id: user.id,
+            email: user.email.clone(),
+            role: user.role.clone(),
+            disabled: user.disabled,
+            created_at: naive(user.created_at),
+            deleted_at: user.deleted_at.map(naive),
+        }
+    }
+}
+
+impl Tables {
+    /// Finds the user with the provided id
+    fn user_mut(&mut self, user_id: i32) -> Result<&mut User, ApiError> {
+        self.users
+            .iter_mut()
+            .find(|user| user.id == user_id)
+            .ok_or(ApiError::NotFound)
+    }
+
+    /// Inserts the key, filling in the defaults of the keys table
+    fn insert_key(&mut self, new_key: NewKey) -> Key {
+        let key = Key {
+            id: new_key.id,
+            created_at: now(),
+            revoked_at: None,
+            label: new_key.label,
+            expires_at: new_key.expires_at,
+            max_redemptions: new_key.max_redemptions.unwrap_or(1),
+            redemption_count: 0,
+            created_by: new_key.created_by,
+        };
+
+        self.keys.push(key.clone());
+
+        key
+    }
+
+    /// Counts the pending entries ahead of and including the entry
+    fn status_of(&self, entry: &WaitlistEntry) -> WaitlistStatus {
+        let position = match entry.approved_at {
+            Some(_) => None,
+            None => Some(
+                self.waitlist
+                    .iter()
+                    .filter(|other| other.approved_at.is_none() && other.id <= entry.id)
+                    .count() as i64,
+            ),
+        };
+
+        WaitlistStatus {
+            token: entry.token,
+            position,
+            approved: entry.approved_at.is_some(),
+            joined_at: entry.created_at,
+        }
+    }
+
+    /// Returns the keys matching the list filters
+    fn filter_keys(&self, params: &ListParams) -> Result<Vec<Key>, ApiError> {
+        let redeemed = params.filter_bool("redeemed")?;
+        let revoked = params.filter_bool("revoked")?;
+        let expired = params.filter_bool("expired")?;
+        let label = params.filter("label");
+        let at = now();
+
+        Ok(self
+            .keys
+            .iter()
+            .filter(|key| redeemed.is_none_or(|r| (key.redemption_count > 0) == r))
+            .filter(|key| revoked.is_none_or(|r| key.revoked_at.is_some() == r))
+            .filter(|key| match expired {
+                Some(true) => key.expires_at.is_some_and(|e| e <= at),
+                Some(false) => key.expires_at.is_none_or(|e| e > at),
+                None => true,
+            })
+            .filter(|key| label.is_none_or(|l| key.label.as_deref() == Some(l)))
+            .cloned()
+            .collect())
+    }
+}
+
+/// Value a row is sorted by, ordered the same way as the database column
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum SortValue {
+    Int(i32),
+    Text(String),
+    Time(NaiveDateTime),
+    Uuid(uuid::Uuid),
+}
+
+/// Describes how rows are sorted and how cursors pointing at them are parsed
+trait Sortable: Listable {
+    /// Returns the value of the sort field followed by the row id
+    fn sort_key(&self, sort: &str) -> (SortValue, SortValue);
+
+    /// Parses the cursor into the representation returned by `sort_key`
+    fn cursor_key(cursor: &Cursor, sort: &str) -> Result<(SortValue, SortValue), ApiError>;
+}
+
+impl Sortable for ViewableUser {
+    fn sort_key(&self, sort: &str) -> (SortValue, SortValue) {
+        match sort {
+            "email" => (SortValue::Text(self.email.clone()), SortValue::Int(self.id)),
+            _ => (SortValue::Int(self.id), SortValue::Int(self.id)),
+        }
+    }
+
+    fn cursor_key(cursor: &Cursor, sort: &str) -> Result<(SortValue, SortValue), ApiError> {
+        let value = match sort {
+            "email" => SortValue::Text(cursor.value()?),
+            _ => SortValue::Int(cursor.value()?),
+        };
+
+        Ok((value, SortValue::Int(cursor.id()?)))
+    }
+}
+
+impl Sortable for ManagedUser {
+    fn sort_key(&self, sort: &str) -> (SortValue, SortValue) {
+        let value = match sort {
+            "email" => SortValue::Text(self.email.clone()),
+            "created_at" => SortValue::Time(self.created_at),
+            _ => SortValue::Int(self.id),
+        };
+
+        (value, SortValue::Int(self.id))
+    }
+
+    fn cursor_key(cursor: &Cursor, sort: &str) -> Result<(SortValue, SortValue), ApiError> {
+        let value = match sort {
+            "email" => SortValue::Text(cursor.value()?),
+            "created_at" => SortValue::Time(cursor.value()?),
+            _ => SortValue::Int(cursor.value()?),
+        };
+
+        Ok((value, SortValue::Int(cursor.id()?)))
+    }
+}
+
+impl Sortable for Key {
+    fn sort_key(&self, sort: &str) -> (SortValue, SortValue) {
+        let value = match sort {
+            "id" => SortValue::Uuid(self.id),
+            _ => SortValue::Time(self.created_at),
+        };
+
+        (value, SortValue::Uuid(self.id))
+    }
+
+    fn cursor_key(cursor: &Cursor, sort: &str) -> Result<(SortValue, SortValue), ApiError> {
+        let value = match sort {
+            "id" => SortValue::Uuid(cursor.value()?),
+            _ => SortValue::Time(cursor.value()?),
+        };
+
+        Ok((value, SortValue::Uuid(cursor.id()?)))
+    }
+}
+
+impl Sortable for WaitlistEntry {
+    fn sort_key(&self, sort: &str) -> (SortValue, SortValue) {
+        let value = match sort {
+            "email" => SortValue::Text(self.email.clone()),
+            _ => SortValue::Int(self.id),
+        };
+
+        (value, SortValue::Int(self.id))
+    }
+
+    fn cursor_key(cursor: &Cursor, sort: &str) -> Result<(SortValue, SortValue), ApiError> {
+        let value = match sort {
+            "email" => SortValue::Text(cursor.value()?),
+            _ => SortValue::Int(cursor.value()?),
+        };
+
+        Ok((value, SortValue::Int(cursor.id()?)))
+    }
+}
+
+/// Sorts the rows as requested and drops every row up to and including the cursor
+fn sorted<T: Sortable>(mut rows: Vec<T>, params: &ListParams) -> Result<Vec<T>, ApiError> {
+    rows.sort_by_key(|row| row.sort_key(&params.sort));
+
+    if params.order == SortOrder::Desc {
+        rows.reverse();
+    }
+
+    if let Some(cursor) = params.cursor.as_ref() {
+        let after = T::cursor_key(cursor, &params.sort)?;
+
+        rows.retain(|row| match params.order {
+            SortOrder::Asc => row.sort_key(&params.sort) > after,
+            SortOrder::Desc => row.sort_key(&params.sort) < after,
+        });
+    }
+
+    Ok(rows)
+}
+
+/// Returns the requested page of the filtered rows
+fn paginate<T: Sortable + Serialize>(
+    rows: Vec<T>,
+    params: &ListParams,
+) -> Result<Page<T>, ApiError> {
+    let total = rows.len() as i64;
+
+    let rows = sorted(rows, params)?
+        .into_iter()
+        .skip(params.fetch_offset() as usize)
+        .take(params.fetch_limit() as usize)
+        .collect();
+
+    Ok(Page::from_rows(rows, total, params))
+}
+
+impl UserStore for MemoryStore {
+    fn list_users(&self, params: &ListParams) -> Result<Page<ViewableUser>, ApiError> {
+        let tables = self.tables()?;
+        let prefix = params.filter("email");
+
+        let rows = tables
+            .users
+            .iter()
+            .filter(|user| user.deleted_at.is_none())
+            .filter(|user| prefix.is_none_or(|p| has_prefix(&user.email, p)))
+            .map(|user| ViewableUser {
+                id: user.id,
+                email: user.email.clone(),
+            })
+            .collect();
+
+        paginate(rows, params)
+    }
+
+    fn find_user(&self, user_id: i32) -> Result<User, ApiError> {
+        Ok(self.tables()?.user_mut(user_id)?.clone())
+    }
+
+    fn find_user_by_email(&self, email: &str) -> Result<Option<User>, ApiError> {
+        let tables = self.tables()?;
+
+        Ok(tables
+            .users
+            .iter()
+            .find(|user| user.email == email && user.deleted_at.is_none())
+            .cloned())
+    }
+
+    fn search_users(&self, params: &ListParams) -> Result<Page<ManagedUser>, ApiError> {
+        let show_disabled = params.filter_bool("disabled")?;
+        let show_deleted = params.filter_bool("deleted")?.unwrap_or(false);
+        let prefix = params.filter("email");
+
+        let tables = self.tables()?;
+
+        let rows = tables
+            .users
+            .iter()
+            .filter(|user| prefix.is_none_or(|p| has_prefix(&user.email, p)))
+            .filter(|user| show_disabled.is_none_or(|d| user.disabled == d))
+            .filter(|user| user.deleted_at.is_some() == show_deleted)
+            .map(ManagedUser::from)
+            .collect();
+
+        paginate(rows, params)
+    }
+
+    fn set_disabled(&self, user_id: i32, disabled: bool) -> Result<ManagedUser, ApiError> {
+        let mut tables = self.tables()?;
+        let user = tables.user_mut(user_id)?;
+
+        user.disabled = disabled;
+
+        Ok(ManagedUser::from(&*user))
+    }
+
+    fn set_role(&self, user_id: i32, role: &str) -> Result<ManagedUser, ApiError> {
+        let mut tables = self.tables()?;
+        let user = tables.user_mut(user_id)?;
+
+        user.role = role.to_string();
+
+        Ok(ManagedUser::from(&*user))
+    }
+
+    fn soft_delete_user(&self, user_id: i32) -> Result<ManagedUser, ApiError> {
+        let mut tables = self.tables()?;
+        let user = tables.user_mut(user_id)?;
+
+        if user.deleted_at.is_some() {
+            return Err(ApiError::NotFound);
+        }
+
+        user.deleted_at = Some(SystemTime::now());
+
+        Ok(ManagedUser::from(&*user))
+    }
+
+    fn restore_user(&self, user_id: i32) -> Result<ManagedUser, ApiError> {
+        let mut tables = self.tables()?;
+        let user = tables.user_mut(user_id)?;
+
+        if user.deleted_at.is_none() {
+            return Err(ApiError::NotFound);
+        }
+
+        user.deleted_at = None;
+
+        Ok(ManagedUser::from(&*user))
+    }
+
+    fn insert_user(&self, new_user: &NewUserForm, role: &str) -> Result<User, ApiError> {
+        let mut tables = self.tables()?;
+
+        // emails are unique, failing the same way the Postgres insert does
+        if tables.users.iter().any(|user| user.email == new_user.email) {
+            return Err(ApiError::EmailTaken);
+        }
+
+        // claim a redemption of the key, which fails once it is used up
+        let at = now();
+        let key = match &new_user.key_id {
+            Some(key_id) => {
+                let key = match tables.keys.iter_mut().find(|key| key.id == *key_id) {
+                    Some(key) if is_available(key, at) => key,
+                    unclaimed => return Err(Key::unredeemable(unclaimed.as_deref())),
+                };
+
+                key.redemption_count += 1;
+                Some(key.clone())
+            }
+            None => None,
+        };
+
+        let user = User {
+            id: tables.users.last().map_or(1, |user| user.id + 1),
+            email: new_user.email.clone(),
+            password: new_user.password.clone(),
+            key_id: new_user.key_id,
+            created_at: SystemTime::now(),
+            role: role.to_string(),
+            disabled: false,
+            deleted_at: None,
+            invited_by: key.as_ref().and_then(|k| k.created_by),
+            locale: new_user.locale.clone(),
+        };
+
+        if let Some(key) = &key {
+            tables.redemptions.push(Redemption {
+                key_id: key.id,
+                user_id: user.id,
+                redeemed_at: at,
+            });
+        }
+
+        tables.users.push(user.clone());
+
+        Ok(user)
+    }
+
+    fn referral_tree(&self, user_id: i32) -> Result<Vec<Referral>, ApiError> {
+        let tables = self.tables()?;
+
+        let rows = tables
+            .users
+            .iter()
+            .filter(|user| user.invited_by.is_some())
+            .map(|user| ReferralRow {
+                id: user.id,
+                email: user.email.clone(),
+                invited_by: user.invited_by,
+                created_at: naive(user.created_at),
+            })
+            .collect();
+
+        Ok(build_tree(user_id, rows))
+    }
+}
+
+impl KeyStore for MemoryStore {
+    fn has_key(&self, key: &uuid::Uuid) -> Result<bool, ApiError> {
+        let tables = self.tables()?;
+
+        Ok(tables
+            .keys
+            .iter()
+            .any(|k| k.id == *key && k.revoked_at.is_none()))
+    }
+
+    fn is_key_available(&self, key: &uuid::Uuid) -> Result<bool, ApiError> {
+        let tables = self.tables()?;
+        let at = now();
+
+        Ok(tables
+            .keys
+            .iter()
+            .any(|k| k.id == *key && is_available(k, at)))
+    }
+
+    fn generate_keys(&self, form: &GenerateKeysForm) -> Result<Vec<Key>, ApiError> {
+        let mut tables = self.tables()?;
+
+        Ok((0..form.count)
+            .map(|_| {
+                tables.insert_key(NewKey {
+                    id: uuid::Uuid::new_v4(),
+                    label: form.label.clone(),
+                    expires_at: form.expires_at,
+                    max_redemptions: form.max_redemptions,
+                    created_by: None,
+                })
+            })
+            .collect())
+    }
+
+    fn import_keys(&self, ids: &[uuid::Uuid]) -> Result<usize, ApiError> {
+        let mut tables = self.tables()?;
+        let mut inserted = 0;
+
+        for id in ids {
+            if tables.keys.iter().any(|key| key.id == *id) {
+                continue;
+            }
+
+            tables.insert_key(NewKey {
+                id: *id,
+                ..Default::default()
+            });
+            inserted += 1;
+        }
+
+        Ok(inserted)
+    }
+
+    fn revoke_key(&self, key: uuid::Uuid) -> Result<Key, ApiError> {
+        let mut tables = self.tables()?;
+
+        let beta_key = tables
+            .keys
+            .iter_mut()
+            .find(|k| k.id == key)
+            .ok_or(ApiError::NotFound)?;
+
+        if beta_key.redemption_count >= beta_key.max_redemptions {
+            return Err(ApiError::KeyAlreadyRedeemed);
+        }
+
+        if beta_key.revoked_at.is_none() {
+            beta_key.revoked_at = Some(now());
+        }
+
+        Ok(beta_key.clone())
+    }
+
+    fn key_redemptions(&self, key: uuid::Uuid) -> Result<Vec<RedeemedBy>, ApiError> {
+        let tables = self.tables()?;
+
+        let mut redeemed: Vec<RedeemedBy> = tables
+            .redemptions
+            .iter()
+            .filter(|redemption| redemption.key_id == key)
+            .filter_map(|redemption| {
+                let user = tables.users.iter().find(|u| u.id == redemption.user_id)?;
+
+                Some(RedeemedBy {
+                    user_id: user.id,
+                    email: user.email.clone(),
+                    redeemed_at: redemption.redeemed_at,
+                })
+            })
+            .collect();
+
+        redeemed.sort_by_key(|r| r.redeemed_at);
+
+        Ok(redeemed)
+    }
+
+    fn search_keys(&self, params: &ListParams) -> Result<Page<Key>, ApiError> {
+        let rows = self.tables()?.filter_keys(params)?;
+
+        paginate(rows, params)
+    }
+
+    fn export_keys(&self, params: &ListParams) -> Result<Vec<Key>, ApiError> {
+        let rows = self.tables()?.filter_keys(params)?;
+
+        sorted(rows, params)
+    }
+
+    fn mint_invite(&self, inviter: i32, limit: i64) -> Result<Key, ApiError> {
+        let mut tables = self.tables()?;
+
+        tables.user_mut(inviter)?;
+
+        let minted = tables
+            .keys
+            .iter()
+            .filter(|key| key.created_by == Some(inviter))
+            .count() as i64;
+
+        if minted >= limit {
+            return Err(ApiError::InviteLimitReached);
+        }
+
+        Ok(tables.insert_key(NewKey {
+            id: uuid::Uuid::new_v4(),
+            label: Some(INVITE_LABEL.to_string()),
+            max_redemptions: Some(1),
+            created_by: Some(inviter),
+            ..Default::default()
+        }))
+    }
+
+    fn invites_sent_by(&self, inviter: i32) -> Result<Vec<Key>, ApiError> {
+        let tables = self.tables()?;
+
+        let mut invites: Vec<Key> = tables
+            .keys
+            .iter()
+            .filter(|key| key.created_by == Some(inviter))
+            .cloned()
+            .collect();
+
+        invites.reverse();
+
+        Ok(invites)
+    }
+}
+
+impl WaitlistStore for MemoryStore {
+    fn join_waitlist(&self, form: &JoinWaitlistForm) -> Result<WaitlistStatus, ApiError> {
+        let mut tables = self.tables()?;
+
+        if tables
+            .waitlist
+            .iter()
+            .any(|entry| entry.email == form.email)
+        {
+            return Err(ApiError::AlreadyOnWaitlist);
+        }
+
+        let entry = WaitlistEntry {
+            id: tables.waitlist.last().map_or(1, |entry| entry.id + 1),
+            email: form.email.clone(),
+            source: form.source.clone(),
+            token: uuid::Uuid::new_v4(),
+            created_at: now(),
+            approved_at: None,
+            key_id: None,
+        };
+
+        tables.waitlist.push(entry.clone());
+
+        Ok(tables.status_of(&entry))
+    }
+
+    fn waitlist_status(&self, token: &uuid::Uuid) -> Result<WaitlistStatus, ApiError> {
+        let tables = self.tables()?;
+
+        let entry = tables
+            .waitlist
+            .iter()
+            .find(|entry| entry.token == *token)
+            .ok_or(ApiError::NotFound)?;
+
+        Ok(tables.status_of(entry))
+    }
+
+    fn approve_waitlist(&self, count: u32) -> Result<Vec<WaitlistEntry>, ApiError> {
+        let mut tables = self.tables()?;
+        let at = now();
+
+        let pending: Vec<usize> = (0..tables.waitlist.len())
+            .filter(|index| tables.waitlist[*index].approved_at.is_none())
+            .take(count as usize)
+            .collect();
+
+        let mut approved = Vec::with_capacity(pending.len());
+
+        for index in pending {
+            let key = tables.insert_key(NewKey {
+                id: uuid::Uuid::new_v4(),
+                label: Some(WAITLIST_LABEL.to_string()),
+                max_redemptions: Some(1),
+                ..Default::default()
+            });
+
+            let entry = &mut tables.waitlist[index];
+            entry.approved_at = Some(at);
+            entry.key_id = Some(key.id);
+
+            approved.push(entry.clone());
+        }
+
+        Ok(approved)
+    }
+
+    fn search_waitlist(&self, params: &ListParams) -> Result<Page<WaitlistEntry>, ApiError> {
+        let show_approved = params.filter_bool("approved")?;
+        let source = params.filter("source");
+
+        let tables = self.tables()?;
+
+        let rows = tables
+            .waitlist
+            .iter()
+            .filter(|entry| show_approved.is_none_or(|a| entry.approved_at.is_some() == a))
+            .filter(|entry| source.is_none_or(|s| entry.source.as_deref() == Some(s)))
+            .cloned()
+            .collect();
+
+        paginate(rows, params)
+    }
+}
+
+impl SigningKeyStore for MemoryStore {
+    fn signing_keys(&self, retired_after: NaiveDateTime) -> Result<Vec<SigningKey>, ApiError> {
+        let tables = self.tables()?;
+
+        let mut keys: Vec<_> = tables
+            .signing_keys
+            .iter()
+            .filter(|key| key.retired_at.is_none_or(|retired| retired > retired_after))
+            .cloned()
+            .collect();
+        keys.sort_by_key(|key| Reverse(key.created_at));
+
+        Ok(keys)
+    }
+
+    fn rotate_signing_key(&self, key: &SigningKey) -> Result<(), ApiError> {
+        let mut tables = self.tables()?;
+
+        for active in tables
+            .signing_keys
+            .iter_mut()
+            .filter(|active| active.retired_at.is_none())
+        {
+            active.retired_at = Some(key.created_at);
+        }
+        tables.signing_keys.push(key.clone());
+
+        Ok(())
+    }
+}
+
+impl SchemaStore for MemoryStore {
+    /// Memory has no schema to migrate
+    fn migrate(&self) -> Result<Vec<String>, ApiError> {
+        Ok(Vec::new())
+    }
+
+    fn pending_migrations(&self) -> Result<Vec<String>, ApiError> {
+        Ok(Vec::new())
+    }
+
+    fn ping(&self) -> Result<(), ApiError> {
+        Ok(())
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        None
+    }
+}
+
+impl TransactionStore for MemoryStore {
+    fn transaction(
+        &self,
+        steps: &mut dyn FnMut(&dyn Store) -> Result<(), ApiError>,
+    ) -> Result<(), ApiError> {
+        // the steps change a copy of the tables, which replaces them once the
+        // steps succeed, while the lock keeps every other call waiting
+        let mut tables = self.tables()?;
+        let working = MemoryStore {
+            tables: Mutex::new(tables.clone()),
+        };
+
+        steps(&working)?;
+        *tables = working.tables()?.clone();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::store::tests::store_tests;
+
+    store_tests!(MemoryStore::new());
+
+    #[test]
+    fn it_pages_keys_by_cursor() {
+        let store = MemoryStore::new();
+
+        let form = GenerateKeysForm {
+            count: 5,
+            label: Some("batch".to_string()),
+            ..Default::default()
+        };
+        store.generate_keys(&form).unwrap();
+
+        let params = ListParams::parse::<Key>("sort=id&limit=2&label=batch").unwrap();
+        let first = store.search_keys(&params).unwrap();
+        assert_eq!(first.total, 5);
+
+        let cursor = first.next_cursor.unwrap().encode();
+        let query = format!("sort=id&limit=2&label=batch&cursor={}", cursor);
+        let params = ListParams::parse::<Key>(&query).unwrap();
+        let second = store.search_keys(&params).unwrap();
+
+        assert_eq!(second.items.len(), 2);
+        assert!(first.items[1].id < second.items[0].id);
+
+        let params = ListParams::parse::<Key>(&query).unwrap();
+        assert_eq!(store.export_keys(&params).unwrap().len(), 3);
+    }
+}
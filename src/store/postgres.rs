@@ -0,0 +1,206 @@
+use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel_migrations::MigrationConnection;
+
+use crate::db::{Connections, DbPool, StoreConnection};
+use crate::models::invite::{Invite, Referral};
+use crate::models::key::{GenerateKeysForm, Key, RedeemedBy};
+use crate::models::signing_key::SigningKey;
+use crate::models::user::{ManagedUser, NewUserForm, User, ViewableUser};
+use crate::models::waitlist::{JoinWaitlistForm, WaitlistEntry, WaitlistStatus};
+use crate::store::{
+    self, KeyStore, PoolState, SchemaStore, SigningKeyStore, Store, TransactionStore, UserStore,
+    WaitlistStore,
+};
+use crate::utils::errors::{ApiError, InternalCode};
+use crate::utils::pagination::{ListParams, Page};
+
+embed_migrations!();
+
+include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+/// Store backed by a Postgres database, checking out a pooled connection per call
+#[derive(Clone)]
+pub struct PgStore {
+    connections: Connections<PgConnection>,
+}
+
+impl PgStore {
+    /// Creates a store using connections from the provided pool
+    pub fn new(pool: DbPool) -> Self {
+        PgStore {
+            connections: Connections::new(pool),
+        }
+    }
+
+    /// Checks out a connection from the pool, or borrows the one of the unit
+    /// of work the store runs the steps of
+    pub fn conn(&self) -> Result<StoreConnection<'_, PgConnection>, ApiError> {
+        self.connections.get()
+    }
+}
+
+impl UserStore for PgStore {
+    fn list_users(&self, params: &ListParams) -> Result<Page<ViewableUser>, ApiError> {
+        User::find_all_users(params, &*self.conn()?)
+    }
+
+    fn find_user(&self, user_id: i32) -> Result<User, ApiError> {
+        User::find_by_id(user_id, &*self.conn()?)
+    }
+
+    fn find_user_by_email(&self, email: &str) -> Result<Option<User>, ApiError> {
+        User::find_by_email(email, &*self.conn()?)
+    }
+
+    fn search_users(&self, params: &ListParams) -> Result<Page<ManagedUser>, ApiError> {
+        User::search(params, &*self.conn()?)
+    }
+
+    fn set_disabled(&self, user_id: i32, disabled: bool) -> Result<ManagedUser, ApiError> {
+        User::set_disabled(user_id, disabled, &*self.conn()?)
+    }
+
+    fn set_role(&self, user_id: i32, role: &str) -> Result<ManagedUser, ApiError> {
+        User::set_role(user_id, role, &*self.conn()?)
+    }
+
+    fn soft_delete_user(&self, user_id: i32) -> Result<ManagedUser, ApiError> {
+        User::soft_delete(user_id, &*self.conn()?)
+    }
+
+    fn restore_user(&self, user_id: i32) -> Result<ManagedUser, ApiError> {
+        User::restore(user_id, &*self.conn()?)
+    }
+
+    fn insert_user(&self, new_user: &NewUserForm, role: &str) -> Result<User, ApiError> {
+        User::insert(new_user, role, &*self.conn()?)
+    }
+
+    fn referral_tree(&self, user_id: i32) -> Result<Vec<Referral>, ApiError> {
+        Invite::referral_tree(user_id, &*self.conn()?)
+    }
+}
+
+impl KeyStore for PgStore {
+    fn has_key(&self, key: &uuid::Uuid) -> Result<bool, ApiError> {
+        Key::has_key(key, &*self.conn()?)
+    }
+
+    fn is_key_available(&self, key: &uuid::Uuid) -> Result<bool, ApiError> {
+        Key::is_available(key, &*self.conn()?)
+    }
+
+    fn generate_keys(&self, form: &GenerateKeysForm) -> Result<Vec<Key>, ApiError> {
+        Key::generate(form, &*self.conn()?)
+    }
+
+    fn import_keys(&self, ids: &[uuid::Uuid]) -> Result<usize, ApiError> {
+        Key::import(ids, &*self.conn()?)
+    }
+
+    fn revoke_key(&self, key: uuid::Uuid) -> Result<Key, ApiError> {
+        Key::revoke(key, &*self.conn()?)
+    }
+
+    fn key_redemptions(&self, key: uuid::Uuid) -> Result<Vec<RedeemedBy>, ApiError> {
+        Key::redeemed_by(key, &*self.conn()?)
+    }
+
+    fn search_keys(&self, params: &ListParams) -> Result<Page<Key>, ApiError> {
+        Key::search(params, &*self.conn()?)
+    }
+
+    fn export_keys(&self, params: &ListParams) -> Result<Vec<Key>, ApiError> {
+        Key::export(params, &*self.conn()?)
+    }
+
+    fn mint_invite(&self, inviter: i32, limit: i64) -> Result<Key, ApiError> {
+        Invite::mint(inviter, limit, &*self.conn()?)
+    }
+
+    fn invites_sent_by(&self, inviter: i32) -> Result<Vec<Key>, ApiError> {
+        Invite::sent_by(inviter, &*self.conn()?)
+    }
+}
+
+impl WaitlistStore for PgStore {
+    fn join_waitlist(&self, form: &JoinWaitlistForm) -> Result<WaitlistStatus, ApiError> {
+        WaitlistEntry::insert(form, &*self.conn()?)
+    }
+
+    fn waitlist_status(&self, token: &uuid::Uuid) -> Result<WaitlistStatus, ApiError> {
+        WaitlistEntry::status(token, &*self.conn()?)
+    }
+
+    fn approve_waitlist(&self, count: u32) -> Result<Vec<WaitlistEntry>, ApiError> {
+        WaitlistEntry::approve_next(count, &*self.conn()?)
+    }
+
+    fn search_waitlist(&self, params: &ListParams) -> Result<Page<WaitlistEntry>, ApiError> {
+        WaitlistEntry::search(params, &*self.conn()?)
+    }
+}
+
+impl SigningKeyStore for PgStore {
+    fn signing_keys(&self, retired_after: NaiveDateTime) -> Result<Vec<SigningKey>, ApiError> {
+        SigningKey::find_usable(retired_after, &*self.conn()?)
+    }
+
+    fn rotate_signing_key(&self, key: &SigningKey) -> Result<(), ApiError> {
+        SigningKey::rotate(key, &*self.conn()?)
+    }
+}
+
+impl SchemaStore for PgStore {
+    fn migrate(&self) -> Result<Vec<String>, ApiError> {
+        let mut output = Vec::new();
+
+        embedded_migrations::run_with_output(&*self.conn()?, &mut output).map_err(|error| {
+            ApiError::InternalServerError(InternalCode::Migration, error.to_string())
+        })?;
+
+        Ok(store::applied_migrations(&output))
+    }
+
+    fn pending_migrations(&self) -> Result<Vec<String>, ApiError> {
+        let applied = self.conn()?.previously_run_migration_versions()?;
+
+        Ok(MIGRATION_VERSIONS
+            .iter()
+            .filter(|version| !applied.contains(**version))
+            .map(|version| version.to_string())
+            .collect())
+    }
+
+    fn ping(&self) -> Result<(), ApiError> {
+        diesel::sql_query("select 1").execute(&*self.conn()?)?;
+        Ok(())
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        Some(PoolState::of(self.connections.pool()))
+    }
+}
+
+impl TransactionStore for PgStore {
+    fn transaction(
+        &self,
+        steps: &mut dyn FnMut(&dyn Store) -> Result<(), ApiError>,
+    ) -> Result<(), ApiError> {
+        self.connections.unit_of_work("BEGIN", |connections| {
+            steps(&PgStore {
+                connections: connections.clone(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::db::tests::create_store;
+    use crate::store::tests::store_tests;
+
+    store_tests!(create_store());
+}
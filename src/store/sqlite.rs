@@ -0,0 +1,1114 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::connection::{SimpleConnection, TransactionManager};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection};
+use diesel::sql_types::Integer;
+use diesel::sqlite::{Sqlite, SqliteConnection};
+use diesel_migrations::MigrationConnection;
+use std::time::SystemTime;
+
+use crate::db::{self, Connections, StoreConnection};
+use crate::models::invite::{build_tree, Referral, ReferralRow, INVITE_LABEL};
+use crate::models::key::{GenerateKeysForm, Key, RedeemedBy};
+use crate::models::signing_key::SigningKey;
+use crate::models::user::{ManagedUser, NewUserForm, User, ViewableUser};
+use crate::models::waitlist::{JoinWaitlistForm, WaitlistEntry, WaitlistStatus, WAITLIST_LABEL};
+use crate::settings::DatabaseSettings;
+use crate::store::{
+    self, KeyStore, PoolState, SchemaStore, SigningKeyStore, Store, StoreError, TransactionStore,
+    UserStore, WaitlistStore,
+};
+use crate::utils::errors::{ApiError, InternalCode};
+use crate::utils::pagination::{sort_by, ListParams, Page};
+
+use self::schema::{keys, redemptions, signing_keys, users, waitlist};
+
+/// Tables of the SQLite database, storing uuids as text
+mod schema {
+    table! {
+        keys (id) {
+            id -> Text,
+            created_at -> Timestamp,
+            revoked_at -> Nullable<Timestamp>,
+            label -> Nullable<Text>,
+            expires_at -> Nullable<Timestamp>,
+            max_redemptions -> Integer,
+            redemption_count -> Integer,
+            created_by -> Nullable<Integer>,
+        }
+    }
+
+    table! {
+        redemptions (key_id, user_id) {
+            key_id -> Text,
+            user_id -> Integer,
+            redeemed_at -> Timestamp,
+        }
+    }
+
+    table! {
+        signing_keys (kid) {
+            kid -> Text,
+            algorithm -> Text,
+            private_key -> Text,
+            public_key -> Text,
+            created_at -> Timestamp,
+            retired_at -> Nullable<Timestamp>,
+        }
+    }
+
+    table! {
+        users (id) {
+            id -> Integer,
+            email -> Text,
+            password -> Text,
+            key_id -> Nullable<Text>,
+            created_at -> Timestamp,
+            role -> Text,
+            disabled -> Bool,
+            deleted_at -> Nullable<Timestamp>,
+            invited_by -> Nullable<Integer>,
+            locale -> Nullable<Text>,
+        }
+    }
+
+    table! {
+        waitlist (id) {
+            id -> Integer,
+            email -> Text,
+            source -> Nullable<Text>,
+            token -> Text,
+            created_at -> Timestamp,
+            approved_at -> Nullable<Timestamp>,
+            key_id -> Nullable<Text>,
+        }
+    }
+
+    joinable!(redemptions -> users (user_id));
+
+    allow_tables_to_appear_in_same_query!(keys, redemptions, signing_keys, users, waitlist);
+}
+
+embed_migrations!("migrations_sqlite");
+
+include!(concat!(env!("OUT_DIR"), "/sqlite_migrations.rs"));
+
+/// Recursive query returning everyone referred by a user, directly or not
+const REFERRAL_QUERY: &str = "with recursive tree as ( \
+        select id, email, invited_by, created_at from users where invited_by = ? \
+        union all \
+        select u.id, u.email, u.invited_by, u.created_at \
+        from users u join tree t on u.invited_by = t.id \
+    ) select id, email, invited_by, created_at from tree";
+
+/// Connection settings SQLite does not persist in the database file
+#[derive(Debug)]
+struct Pragmas {
+    busy_timeout_ms: u64,
+}
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for Pragmas {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute(&format!(
+            "pragma foreign_keys = on; pragma busy_timeout = {};",
+            self.busy_timeout_ms
+        ))
+        .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Store backed by an embedded SQLite database
+pub struct SqliteStore {
+    connections: Connections<SqliteConnection>,
+}
+
+impl SqliteStore {
+    /// Opens the database at the configured path. An in-memory database starts
+    /// out empty every time, so its migrations are applied right away
+    pub fn open(settings: &DatabaseSettings) -> Result<Self, StoreError> {
+        let manager = ConnectionManager::<SqliteConnection>::new(settings.url.as_str());
+        let builder = db::pool_builder(settings);
+
+        // every connection to an in-memory database would get a database of
+        // its own, and closing the only one would lose the data
+        let builder = if settings.url == ":memory:" {
+            builder
+                .max_size(1)
+                .min_idle(None)
+                .idle_timeout(None)
+                .max_lifetime(None)
+        } else {
+            builder
+        };
+
+        let pool = builder
+            .connection_customizer(Box::new(Pragmas {
+                busy_timeout_ms: settings.connect_timeout_secs * 1000,
+            }))
+            .build(manager)
+            .map_err(|error| StoreError::Connect(error.to_string()))?;
+
+        let store = SqliteStore {
+            connections: Connections::new(pool),
+        };
+
+        if settings.url == ":memory:" {
+            store
+                .migrate()
+                .map_err(|error| StoreError::Migrate(error.to_string()))?;
+        }
+
+        Ok(store)
+    }
+
+    /// Checks out a connection from the pool, or borrows the one of the unit
+    /// of work the store runs the steps of
+    fn conn(&self) -> Result<StoreConnection<'_, SqliteConnection>, ApiError> {
+        self.connections.get()
+    }
+}
+
+/// Runs the closure in an immediate transaction, which takes the write lock up
+/// front, or in a savepoint when a unit of work already holds the lock
+fn immediate<T, F>(conn: &SqliteConnection, f: F) -> Result<T, ApiError>
+where
+    F: FnOnce() -> Result<T, ApiError>,
+{
+    let manager = conn.transaction_manager();
+    if TransactionManager::<SqliteConnection>::get_transaction_depth(manager) == 0 {
+        conn.immediate_transaction(f)
+    } else {
+        conn.transaction(f)
+    }
+}
+
+/// Returns the current time the way timestamps are stored
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+/// Parses a uuid stored as text
+fn parse_uuid(value: &str) -> Result<uuid::Uuid, ApiError> {
+    value.parse().map_err(|_| {
+        ApiError::InternalServerError(
+            InternalCode::Database,
+            format!("Invalid uuid {:?} stored in the database", value),
+        )
+    })
+}
+
+/// Converts a stored timestamp to the representation used by users
+fn system_time(time: NaiveDateTime) -> SystemTime {
+    chrono::DateTime::<Utc>::from_utc(time, Utc).into()
+}
+
+/// Key as stored in SQLite
+#[derive(Queryable)]
+struct KeyRow {
+    id: String,
+    created_at: NaiveDateTime,
+    revoked_at: Option<NaiveDateTime>,
+    label: Option<String>,
+    expires_at: Option<NaiveDateTime>,
+    max_redemptions: i32,
+    redemption_count: i32,
+    created_by: Option<i32>,
+}
+
+impl KeyRow {
+    fn into_key(self) -> Result<Key, ApiError> {
+        Ok(Key {
+            id: parse_uuid(&self.id)?,
+            created_at: self.created_at,
+            revoked_at: self.revoked_at,
+            label: self.label,
+            expires_at: self.expires_at,
+            max_redemptions: self.max_redemptions,
+            redemption_count: self.redemption_count,
+            created_by: self.created_by,
+        })
+    }
+}
+
+/// Key that can be inserted into SQLite
+#[derive(Insertable)]
+#[table_name = "keys"]
+struct NewKeyRow {
+    id: String,
+    created_at: NaiveDateTime,
+    label: Option<String>,
+    expires_at: Option<NaiveDateTime>,
+    max_redemptions: i32,
+    created_by: Option<i32>,
+}
+
+impl NewKeyRow {
+    fn new(id: uuid::Uuid) -> Self {
+        NewKeyRow {
+            id: id.to_string(),
+            created_at: now(),
+            label: None,
+            expires_at: None,
+            max_redemptions: 1,
+            created_by: None,
+        }
+    }
+}
+
+/// User as stored in SQLite
+#[derive(Queryable)]
+struct UserRow {
+    id: i32,
+    email: String,
+    password: String,
+    key_id: Option<String>,
+    created_at: NaiveDateTime,
+    role: String,
+    disabled: bool,
+    deleted_at: Option<NaiveDateTime>,
+    invited_by: Option<i32>,
+    locale: Option<String>,
+}
+
+impl UserRow {
+    fn into_user(self) -> Result<User, ApiError> {
+        Ok(User {
+            id: self.id,
+            email: self.email,
+            password: self.password,
+            key_id: self.key_id.as_deref().map(parse_uuid).transpose()?,
+            created_at: system_time(self.created_at),
+            role: self.role,
+            disabled: self.disabled,
+            deleted_at: self.deleted_at.map(system_time),
+            invited_by: self.invited_by,
+            locale: self.locale,
+        })
+    }
+}
+
+/// Waitlist entry as stored in SQLite
+#[derive(Queryable)]
+struct WaitlistRow {
+    id: i32,
+    email: String,
+    source: Option<String>,
+    token: String,
+    created_at: NaiveDateTime,
+    approved_at: Option<NaiveDateTime>,
+    key_id: Option<String>,
+}
+
+impl WaitlistRow {
+    fn into_entry(self) -> Result<WaitlistEntry, ApiError> {
+        Ok(WaitlistEntry {
+            id: self.id,
+            email: self.email,
+            source: self.source,
+            token: parse_uuid(&self.token)?,
+            created_at: self.created_at,
+            approved_at: self.approved_at,
+            key_id: self.key_id.as_deref().map(parse_uuid).transpose()?,
+        })
+    }
+}
+
+/// Converts every loaded key row
+fn into_keys(rows: Vec<KeyRow>) -> Result<Vec<Key>, ApiError> {
+    rows.into_iter().map(KeyRow::into_key).collect()
+}
+
+type ManagedColumns = (
+    users::id,
+    users::email,
+    users::role,
+    users::disabled,
+    users::created_at,
+    users::deleted_at,
+);
+
+/// Columns a user is seen through by administrators
+const MANAGED_COLUMNS: ManagedColumns = (
+    users::id,
+    users::email,
+    users::role,
+    users::disabled,
+    users::created_at,
+    users::deleted_at,
+);
+
+type KeyQuery = diesel::dsl::IntoBoxed<'static, keys::table, Sqlite>;
+
+/// Inserts the keys and loads them back in the order they were provided
+fn insert_keys(new_keys: &[NewKeyRow], conn: &SqliteConnection) -> Result<Vec<Key>, ApiError> {
+    let ids: Vec<&String> = new_keys.iter().map(|key| &key.id).collect();
+
+    for new_key in new_keys {
+        diesel::insert_into(keys::table)
+            .values(new_key)
+            .execute(conn)?;
+    }
+
+    let mut created = into_keys(
+        keys::table
+            .filter(keys::id.eq_any(ids))
+            .load::<KeyRow>(conn)?,
+    )?;
+
+    created.sort_by_key(|key| {
+        new_keys
+            .iter()
+            .position(|new_key| new_key.id == key.id.to_string())
+    });
+
+    Ok(created)
+}
+
+/// Counts the pending entries ahead of and including the entry
+fn status_of(entry: &WaitlistEntry, conn: &SqliteConnection) -> Result<WaitlistStatus, ApiError> {
+    let position = match entry.approved_at {
+        Some(_) => None,
+        None => Some(
+            waitlist::table
+                .filter(waitlist::approved_at.is_null())
+                .filter(waitlist::id.le(entry.id))
+                .count()
+                .get_result::<i64>(conn)?,
+        ),
+    };
+
+    Ok(WaitlistStatus {
+        token: entry.token,
+        position,
+        approved: entry.approved_at.is_some(),
+        joined_at: entry.created_at,
+    })
+}
+
+/// Builds the keys query with the list filters applied
+fn filtered_keys(params: &ListParams) -> Result<KeyQuery, ApiError> {
+    use self::schema::keys::dsl::*;
+
+    let at = now();
+    let mut query = keys.into_boxed::<Sqlite>();
+
+    match params.filter_bool("redeemed")? {
+        Some(true) => query = query.filter(redemption_count.gt(0)),
+        Some(false) => query = query.filter(redemption_count.eq(0)),
+        None => {}
+    }
+
+    match params.filter_bool("revoked")? {
+        Some(true) => query = query.filter(revoked_at.is_not_null()),
+        Some(false) => query = query.filter(revoked_at.is_null()),
+        None => {}
+    }
+
+    match params.filter_bool("expired")? {
+        Some(true) => query = query.filter(expires_at.le(at)),
+        Some(false) => query = query.filter(expires_at.is_null().or(expires_at.gt(at))),
+        None => {}
+    }
+
+    if let Some(name) = params.filter("label") {
+        query = query.filter(label.eq(name.to_string()));
+    }
+
+    Ok(query)
+}
+
+/// Loads the filtered keys in the requested order, optionally limited to the requested page
+fn load_sorted_keys(
+    params: &ListParams,
+    paginate: bool,
+    conn: &SqliteConnection,
+) -> Result<Vec<Key>, ApiError> {
+    let query = filtered_keys(params)?;
+
+    let mut query = match params.sort.as_str() {
+        "id" => sort_by!(query, params, keys::id, keys::id, String, String),
+        _ => sort_by!(
+            query,
+            params,
+            keys::created_at,
+            keys::id,
+            NaiveDateTime,
+            String
+        ),
+    };
+
+    if paginate {
+        query = query
+            .limit(params.fetch_limit())
+            .offset(params.fetch_offset());
+    }
+
+    into_keys(query.load::<KeyRow>(conn)?)
+}
+
+impl UserStore for SqliteStore {
+    fn list_users(&self, params: &ListParams) -> Result<Page<ViewableUser>, ApiError> {
+        use self::schema::users::dsl::*;
+        use diesel::dsl::count_star;
+
+        let conn = self.conn()?;
+
+        let filtered = || {
+            let mut query = users.filter(deleted_at.is_null()).into_boxed::<Sqlite>();
+
+            // LIKE ignores the case of ascii characters in SQLite
+            if let Some(pattern) = params.filter_prefix("email") {
+                query = query.filter(email.like(pattern).escape('\\'));
+            }
+
+            query
+        };
+
+        let total = filtered().select(count_star()).first::<i64>(&conn)?;
+
+        let query = filtered().select((id, email));
+
+        let query = match params.sort.as_str() {
+            "email" => sort_by!(query, params, email, id, String, i32),
+            _ => sort_by!(query, params, id, id, i32, i32),
+        };
+
+        let rows = query
+            .limit(params.fetch_limit())
+            .offset(params.fetch_offset())
+            .load::<ViewableUser>(&conn)?;
+
+        Ok(Page::from_rows(rows, total, params))
+    }
+
+    fn find_user(&self, user_id: i32) -> Result<User, ApiError> {
+        users::table
+            .find(user_id)
+            .first::<UserRow>(&*self.conn()?)?
+            .into_user()
+    }
+
+    fn find_user_by_email(&self, user_email: &str) -> Result<Option<User>, ApiError> {
+        users::table
+            .filter(users::email.eq(user_email))
+            .filter(users::deleted_at.is_null())
+            .first::<UserRow>(&*self.conn()?)
+            .optional()?
+            .map(UserRow::into_user)
+            .transpose()
+    }
+
+    fn search_users(&self, params: &ListParams) -> Result<Page<ManagedUser>, ApiError> {
+        use self::schema::users::dsl::*;
+        use diesel::dsl::count_star;
+
+        let show_disabled = params.filter_bool("disabled")?;
+        let show_deleted = params.filter_bool("deleted")?.unwrap_or(false);
+
+        let conn = self.conn()?;
+
+        let filtered = || {
+            let mut query = users.into_boxed::<Sqlite>();
+
+            if let Some(pattern) = params.filter_prefix("email") {
+                query = query.filter(email.like(pattern).escape('\\'));
+            }
+
+            if let Some(is_disabled) = show_disabled {
+                query = query.filter(disabled.eq(is_disabled));
+            }
+
+            if show_deleted {
+                query = query.filter(deleted_at.is_not_null());
+            } else {
+                query = query.filter(deleted_at.is_null());
+            }
+
+            query
+        };
+
+        let total = filtered().select(count_star()).first::<i64>(&conn)?;
+
+        let query = filtered().select(MANAGED_COLUMNS);
+
+        let query = match params.sort.as_str() {
+            "email" => sort_by!(query, params, email, id, String, i32),
+            "created_at" => sort_by!(query, params, created_at, id, NaiveDateTime, i32),
+            _ => sort_by!(query, params, id, id, i32, i32),
+        };
+
+        let rows = query
+            .limit(params.fetch_limit())
+            .offset(params.fetch_offset())
+            .load::<ManagedUser>(&conn)?;
+
+        Ok(Page::from_rows(rows, total, params))
+    }
+
+    fn set_disabled(&self, user_id: i32, is_disabled: bool) -> Result<ManagedUser, ApiError> {
+        let conn = self.conn()?;
+
+        immediate(&conn, || {
+            let updated = diesel::update(users::table.find(user_id))
+                .set(users::disabled.eq(is_disabled))
+                .execute(&conn)?;
+
+            if updated == 0 {
+                return Err(ApiError::NotFound);
+            }
+
+            let user = users::table
+                .find(user_id)
+                .select(MANAGED_COLUMNS)
+                .first::<ManagedUser>(&conn)?;
+
+            Ok(user)
+        })
+    }
+
+    fn set_role(&self, user_id: i32, role: &str) -> Result<ManagedUser, ApiError> {
+        let conn = self.conn()?;
+
+        immediate(&conn, || {
+            let updated = diesel::update(users::table.find(user_id))
+                .set(users::role.eq(role))
+                .execute(&conn)?;
+
+            if updated == 0 {
+                return Err(ApiError::NotFound);
+            }
+
+            let user = users::table
+                .find(user_id)
+                .select(MANAGED_COLUMNS)
+                .first::<ManagedUser>(&conn)?;
+
+            Ok(user)
+        })
+    }
+
+    fn soft_delete_user(&self, user_id: i32) -> Result<ManagedUser, ApiError> {
+        let conn = self.conn()?;
+
+        immediate(&conn, || {
+            let updated = diesel::update(
+                users::table
+                    .find(user_id)
+                    .filter(users::deleted_at.is_null()),
+            )
+            .set(users::deleted_at.eq(now()))
+            .execute(&conn)?;
+
+            if updated == 0 {
+                return Err(ApiError::NotFound);
+            }
+
+            let user = users::table
+                .find(user_id)
+                .select(MANAGED_COLUMNS)
+                .first::<ManagedUser>(&conn)?;
+
+            Ok(user)
+        })
+    }
+
+    fn restore_user(&self, user_id: i32) -> Result<ManagedUser, ApiError> {
+        let conn = self.conn()?;
+
+        immediate(&conn, || {
+            let updated = diesel::update(
+                users::table
+                    .find(user_id)
+                    .filter(users::deleted_at.is_not_null()),
+            )
+            .set(users::deleted_at.eq(None::<NaiveDateTime>))
+            .execute(&conn)?;
+
+            if updated == 0 {
+                return Err(ApiError::NotFound);
+            }
+
+            let user = users::table
+                .find(user_id)
+                .select(MANAGED_COLUMNS)
+                .first::<ManagedUser>(&conn)?;
+
+            Ok(user)
+        })
+    }
+
+    fn insert_user(&self, new_user: &NewUserForm, role: &str) -> Result<User, ApiError> {
+        let conn = self.conn()?;
+
+        // the immediate transaction takes the write lock up front, so the
+        // availability check and the increment can't interleave with another signup
+        immediate(&conn, || {
+            let at = now();
+            let key_id = new_user.key_id.map(|key| key.to_string());
+
+            // claim a redemption of the key, which fails once it is used up
+            let key = match &key_id {
+                Some(key) => {
+                    let claimed = diesel::update(
+                        keys::table
+                            .find(key)
+                            .filter(keys::revoked_at.is_null())
+                            .filter(keys::expires_at.is_null().or(keys::expires_at.gt(at)))
+                            .filter(keys::redemption_count.lt(keys::max_redemptions)),
+                    )
+                    .set(keys::redemption_count.eq(keys::redemption_count + 1))
+                    .execute(&conn)?;
+
+                    if claimed == 0 {
+                        let unclaimed = keys::table
+                            .find(key)
+                            .first::<KeyRow>(&conn)
+                            .optional()?
+                            .map(KeyRow::into_key)
+                            .transpose()?;
+
+                        return Err(Key::unredeemable(unclaimed.as_ref()));
+                    }
+
+                    Some(keys::table.find(key).first::<KeyRow>(&conn)?.into_key()?)
+                }
+                None => None,
+            };
+
+            // insert new user in the database, crediting whoever minted the key
+            diesel::insert_into(users::table)
+                .values((
+                    users::email.eq(&new_user.email),
+                    users::password.eq(&new_user.password),
+                    users::key_id.eq(&key_id),
+                    users::created_at.eq(at),
+                    users::invited_by.eq(key.as_ref().and_then(|k| k.created_by)),
+                    users::locale.eq(&new_user.locale),
+                    users::role.eq(role),
+                ))
+                .execute(&conn)?;
+
+            let user = users::table
+                .filter(users::email.eq(&new_user.email))
+                .first::<UserRow>(&conn)?
+                .into_user()?;
+
+            if let Some(key) = &key_id {
+                diesel::insert_into(redemptions::table)
+                    .values((
+                        redemptions::key_id.eq(key),
+                        redemptions::user_id.eq(user.id),
+                        redemptions::redeemed_at.eq(at),
+                    ))
+                    .execute(&conn)?;
+            }
+
+            Ok(user)
+        })
+    }
+
+    fn referral_tree(&self, user_id: i32) -> Result<Vec<Referral>, ApiError> {
+        let rows = diesel::sql_query(REFERRAL_QUERY)
+            .bind::<Integer, _>(user_id)
+            .load::<ReferralRow>(&*self.conn()?)?;
+
+        Ok(build_tree(user_id, rows))
+    }
+}
+
+impl KeyStore for SqliteStore {
+    fn has_key(&self, key: &uuid::Uuid) -> Result<bool, ApiError> {
+        let beta_key = keys::table
+            .find(key.to_string())
+            .filter(keys::revoked_at.is_null())
+            .first::<KeyRow>(&*self.conn()?)
+            .optional()?;
+
+        Ok(beta_key.is_some())
+    }
+
+    fn is_key_available(&self, key: &uuid::Uuid) -> Result<bool, ApiError> {
+        let at = now();
+
+        let beta_key = keys::table
+            .find(key.to_string())
+            .filter(keys::revoked_at.is_null())
+            .filter(keys::expires_at.is_null().or(keys::expires_at.gt(at)))
+            .filter(keys::redemption_count.lt(keys::max_redemptions))
+            .first::<KeyRow>(&*self.conn()?)
+            .optional()?;
+
+        Ok(beta_key.is_some())
+    }
+
+    fn generate_keys(&self, form: &GenerateKeysForm) -> Result<Vec<Key>, ApiError> {
+        let new_keys: Vec<NewKeyRow> = (0..form.count)
+            .map(|_| NewKeyRow {
+                label: form.label.clone(),
+                expires_at: form.expires_at,
+                max_redemptions: form.max_redemptions.unwrap_or(1),
+                ..NewKeyRow::new(uuid::Uuid::new_v4())
+            })
+            .collect();
+
+        let conn = self.conn()?;
+
+        immediate(&conn, || insert_keys(&new_keys, &conn))
+    }
+
+    fn import_keys(&self, ids: &[uuid::Uuid]) -> Result<usize, ApiError> {
+        let new_keys: Vec<NewKeyRow> = ids.iter().map(|id| NewKeyRow::new(*id)).collect();
+
+        let conn = self.conn()?;
+
+        // SQLite has no batch insert with defaults, so keys are inserted one by one
+        immediate(&conn, || {
+            new_keys.iter().try_fold(0, |inserted, new_key| {
+                let count = diesel::insert_or_ignore_into(keys::table)
+                    .values(new_key)
+                    .execute(&conn)?;
+
+                Ok(inserted + count)
+            })
+        })
+    }
+
+    fn revoke_key(&self, key: uuid::Uuid) -> Result<Key, ApiError> {
+        let conn = self.conn()?;
+        let key = key.to_string();
+
+        immediate(&conn, || {
+            let beta_key = keys::table.find(&key).first::<KeyRow>(&conn)?.into_key()?;
+
+            if beta_key.redemption_count >= beta_key.max_redemptions {
+                return Err(ApiError::KeyAlreadyRedeemed);
+            }
+
+            if beta_key.revoked_at.is_some() {
+                return Ok(beta_key);
+            }
+
+            diesel::update(keys::table.find(&key))
+                .set(keys::revoked_at.eq(now()))
+                .execute(&conn)?;
+
+            keys::table.find(&key).first::<KeyRow>(&conn)?.into_key()
+        })
+    }
+
+    fn key_redemptions(&self, key: uuid::Uuid) -> Result<Vec<RedeemedBy>, ApiError> {
+        let redeemed = redemptions::table
+            .inner_join(users::table)
+            .filter(redemptions::key_id.eq(key.to_string()))
+            .select((users::id, users::email, redemptions::redeemed_at))
+            .order(redemptions::redeemed_at.asc())
+            .load::<RedeemedBy>(&*self.conn()?)?;
+
+        Ok(redeemed)
+    }
+
+    fn search_keys(&self, params: &ListParams) -> Result<Page<Key>, ApiError> {
+        use diesel::dsl::count_star;
+
+        let conn = self.conn()?;
+
+        let total = filtered_keys(params)?
+            .select(count_star())
+            .first::<i64>(&conn)?;
+
+        let rows = load_sorted_keys(params, true, &conn)?;
+
+        Ok(Page::from_rows(rows, total, params))
+    }
+
+    fn export_keys(&self, params: &ListParams) -> Result<Vec<Key>, ApiError> {
+        load_sorted_keys(params, false, &*self.conn()?)
+    }
+
+    fn mint_invite(&self, inviter: i32, limit: i64) -> Result<Key, ApiError> {
+        let conn = self.conn()?;
+
+        // the write lock keeps concurrent requests from exceeding the limit
+        immediate(&conn, || {
+            users::table
+                .find(inviter)
+                .select(users::id)
+                .first::<i32>(&conn)?;
+
+            let minted = keys::table
+                .filter(keys::created_by.eq(inviter))
+                .count()
+                .get_result::<i64>(&conn)?;
+
+            if minted >= limit {
+                return Err(ApiError::InviteLimitReached);
+            }
+
+            let new_key = NewKeyRow {
+                label: Some(INVITE_LABEL.to_string()),
+                created_by: Some(inviter),
+                ..NewKeyRow::new(uuid::Uuid::new_v4())
+            };
+
+            Ok(insert_keys(&[new_key], &conn)?.remove(0))
+        })
+    }
+
+    fn invites_sent_by(&self, inviter: i32) -> Result<Vec<Key>, ApiError> {
+        let rows = keys::table
+            .filter(keys::created_by.eq(inviter))
+            .order(keys::created_at.desc())
+            .load::<KeyRow>(&*self.conn()?)?;
+
+        into_keys(rows)
+    }
+}
+
+impl WaitlistStore for SqliteStore {
+    fn join_waitlist(&self, form: &JoinWaitlistForm) -> Result<WaitlistStatus, ApiError> {
+        let conn = self.conn()?;
+        let entry_token = uuid::Uuid::new_v4();
+
+        immediate(&conn, || {
+            let inserted = diesel::insert_or_ignore_into(waitlist::table)
+                .values((
+                    waitlist::email.eq(&form.email),
+                    waitlist::source.eq(&form.source),
+                    waitlist::token.eq(entry_token.to_string()),
+                    waitlist::created_at.eq(now()),
+                ))
+                .execute(&conn)?;
+
+            if inserted == 0 {
+                return Err(ApiError::AlreadyOnWaitlist);
+            }
+
+            let entry = waitlist::table
+                .filter(waitlist::token.eq(entry_token.to_string()))
+                .first::<WaitlistRow>(&conn)?
+                .into_entry()?;
+
+            status_of(&entry, &conn)
+        })
+    }
+
+    fn waitlist_status(&self, status_token: &uuid::Uuid) -> Result<WaitlistStatus, ApiError> {
+        let conn = self.conn()?;
+
+        let entry = waitlist::table
+            .filter(waitlist::token.eq(status_token.to_string()))
+            .first::<WaitlistRow>(&conn)?
+            .into_entry()?;
+
+        status_of(&entry, &conn)
+    }
+
+    fn approve_waitlist(&self, count: u32) -> Result<Vec<WaitlistEntry>, ApiError> {
+        let conn = self.conn()?;
+
+        immediate(&conn, || {
+            let pending = waitlist::table
+                .filter(waitlist::approved_at.is_null())
+                .order(waitlist::id.asc())
+                .limit(i64::from(count))
+                .load::<WaitlistRow>(&conn)?;
+
+            let at = now();
+            let mut approved = Vec::with_capacity(pending.len());
+
+            for row in pending {
+                let new_key = NewKeyRow {
+                    label: Some(WAITLIST_LABEL.to_string()),
+                    ..NewKeyRow::new(uuid::Uuid::new_v4())
+                };
+                let key_id = new_key.id.clone();
+
+                diesel::insert_into(keys::table)
+                    .values(&new_key)
+                    .execute(&conn)?;
+
+                diesel::update(waitlist::table.find(row.id))
+                    .set((waitlist::approved_at.eq(at), waitlist::key_id.eq(&key_id)))
+                    .execute(&conn)?;
+
+                approved.push(WaitlistEntry {
+                    approved_at: Some(at),
+                    key_id: Some(parse_uuid(&key_id)?),
+                    ..row.into_entry()?
+                });
+            }
+
+            Ok(approved)
+        })
+    }
+
+    fn search_waitlist(&self, params: &ListParams) -> Result<Page<WaitlistEntry>, ApiError> {
+        use self::schema::waitlist::dsl::*;
+        use diesel::dsl::count_star;
+
+        let show_approved = params.filter_bool("approved")?;
+
+        let conn = self.conn()?;
+
+        let filtered = || {
+            let mut query = waitlist.into_boxed::<Sqlite>();
+
+            match show_approved {
+                Some(true) => query = query.filter(approved_at.is_not_null()),
+                Some(false) => query = query.filter(approved_at.is_null()),
+                None => {}
+            }
+
+            if let Some(name) = params.filter("source") {
+                query = query.filter(source.eq(name.to_string()));
+            }
+
+            query
+        };
+
+        let total = filtered().select(count_star()).first::<i64>(&conn)?;
+
+        let query = match params.sort.as_str() {
+            "email" => sort_by!(filtered(), params, email, id, String, i32),
+            _ => sort_by!(filtered(), params, id, id, i32, i32),
+        };
+
+        let rows = query
+            .limit(params.fetch_limit())
+            .offset(params.fetch_offset())
+            .load::<WaitlistRow>(&conn)?;
+
+        let entries = rows
+            .into_iter()
+            .map(WaitlistRow::into_entry)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Page::from_rows(entries, total, params))
+    }
+}
+
+impl SigningKeyStore for SqliteStore {
+    fn signing_keys(&self, retired_after: NaiveDateTime) -> Result<Vec<SigningKey>, ApiError> {
+        let keys = signing_keys::table
+            .filter(
+                signing_keys::retired_at
+                    .is_null()
+                    .or(signing_keys::retired_at.gt(retired_after)),
+            )
+            .order(signing_keys::created_at.desc())
+            .load::<SigningKey>(&self.conn()?)?;
+
+        Ok(keys)
+    }
+
+    fn rotate_signing_key(&self, key: &SigningKey) -> Result<(), ApiError> {
+        let conn = self.conn()?;
+
+        immediate(&conn, || {
+            diesel::update(signing_keys::table.filter(signing_keys::retired_at.is_null()))
+                .set(signing_keys::retired_at.eq(key.created_at))
+                .execute(&conn)?;
+
+            diesel::insert_into(signing_keys::table)
+                .values((
+                    signing_keys::kid.eq(&key.kid),
+                    signing_keys::algorithm.eq(&key.algorithm),
+                    signing_keys::private_key.eq(&key.private_key),
+                    signing_keys::public_key.eq(&key.public_key),
+                    signing_keys::created_at.eq(key.created_at),
+                ))
+                .execute(&conn)?;
+
+            Ok(())
+        })
+    }
+}
+
+impl SchemaStore for SqliteStore {
+    fn migrate(&self) -> Result<Vec<String>, ApiError> {
+        let mut output = Vec::new();
+
+        embedded_migrations::run_with_output(&*self.conn()?, &mut output).map_err(|error| {
+            ApiError::InternalServerError(InternalCode::Migration, error.to_string())
+        })?;
+
+        Ok(store::applied_migrations(&output))
+    }
+
+    fn pending_migrations(&self) -> Result<Vec<String>, ApiError> {
+        // opening a database file creates it, so it may not have been migrated at all
+        let conn = self.conn()?;
+        diesel_migrations::setup_database(&*conn)?;
+        let applied = conn.previously_run_migration_versions()?;
+
+        Ok(MIGRATION_VERSIONS
+            .iter()
+            .filter(|version| !applied.contains(**version))
+            .map(|version| version.to_string())
+            .collect())
+    }
+
+    fn ping(&self) -> Result<(), ApiError> {
+        diesel::sql_query("select 1").execute(&*self.conn()?)?;
+        Ok(())
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        Some(PoolState::of(self.connections.pool()))
+    }
+}
+
+impl TransactionStore for SqliteStore {
+    fn transaction(
+        &self,
+        steps: &mut dyn FnMut(&dyn Store) -> Result<(), ApiError>,
+    ) -> Result<(), ApiError> {
+        // like every other write, the unit of work takes the write lock up front
+        self.connections
+            .unit_of_work("BEGIN IMMEDIATE", |connections| {
+                steps(&SqliteStore {
+                    connections: connections.clone(),
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::store::tests::store_tests;
+    use crate::store::Backend;
+
+    /// Opens a fresh in-memory database
+    fn create_store() -> SqliteStore {
+        let settings = DatabaseSettings {
+            backend: Backend::Sqlite,
+            url: String::from(":memory:"),
+            ..Default::default()
+        };
+
+        SqliteStore::open(&settings).expect("failed to open sqlite store")
+    }
+
+    store_tests!(create_store());
+
+    #[test]
+    fn it_migrates_database_files_on_request() {
+        let path = std::env::temp_dir().join(format!("auth-{}.db", uuid::Uuid::new_v4()));
+        let settings = DatabaseSettings {
+            backend: Backend::Sqlite,
+            url: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+
+        let store = SqliteStore::open(&settings).expect("failed to open sqlite store");
+        assert_eq!(store.pending_migrations().unwrap(), MIGRATION_VERSIONS);
+
+        assert_eq!(store.migrate().unwrap(), MIGRATION_VERSIONS);
+        assert!(store.pending_migrations().unwrap().is_empty());
+        assert!(store.migrate().unwrap().is_empty());
+
+        drop(store);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,2 +1,13 @@
+pub mod context;
+pub mod cors;
 pub mod errors;
+pub mod i18n;
+pub mod json;
+pub mod keyring;
+pub mod logging;
+pub mod mailer;
+pub mod metrics;
+pub mod pagination;
+pub mod request_id;
+pub mod telemetry;
 pub mod token;
@@ -0,0 +1,182 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::{Error, HttpMessage, HttpResponse, ResponseError};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::utils::i18n::Locale;
+use crate::utils::request_id::{self, RequestId};
+
+thread_local! {
+    /// Context of the request being handled on this thread, set while its
+    /// handlers run so errors can report its id in its locale
+    static CURRENT: RefCell<Option<RequestContext>> = const { RefCell::new(None) };
+}
+
+/// State of a request shared by the middleware and the futures handling it,
+/// which middleware further in, such as the validator, can still change
+#[derive(Debug, Clone)]
+pub struct RequestContext(Rc<State>);
+
+#[derive(Debug)]
+struct State {
+    id: RefCell<Option<RequestId>>,
+    locale: Cell<Locale>,
+}
+
+impl RequestContext {
+    /// Returns the context of the request, creating it when the calling
+    /// middleware is the first to handle the request. The one creating it
+    /// makes it current with `run` and renders its errors with `error`
+    pub fn of(req: &ServiceRequest) -> (RequestContext, bool) {
+        if let Some(context) = req.extensions().get::<RequestContext>() {
+            return (context.clone(), false);
+        }
+
+        let context = RequestContext(Rc::new(State {
+            id: RefCell::new(None),
+            locale: Cell::new(Locale::DEFAULT),
+        }));
+        req.extensions_mut().insert(context.clone());
+        (context, true)
+    }
+
+    /// Returns the context of the request being handled on this thread, if any
+    pub fn current() -> Option<RequestContext> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    pub fn id(&self) -> Option<RequestId> {
+        self.0.id.borrow().clone()
+    }
+
+    pub fn set_id(&self, id: RequestId) {
+        self.0.id.replace(Some(id));
+    }
+
+    pub fn locale(&self) -> Locale {
+        self.0.locale.get()
+    }
+
+    pub fn set_locale(&self, locale: Locale) {
+        self.0.locale.set(locale);
+    }
+
+    /// Runs the function as part of the request, making its context current
+    pub fn scope<T>(&self, call: impl FnOnce() -> T) -> T {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let result = call();
+        CURRENT.with(|current| current.replace(previous));
+        result
+    }
+
+    /// Calls the inner service, making the context current while it runs and
+    /// while the future handling the request is polled
+    pub fn run<F: Future>(&self, call: impl FnOnce() -> F) -> WithContext<F> {
+        WithContext {
+            context: self.clone(),
+            inner: Box::pin(self.scope(call)),
+        }
+    }
+
+    /// Tags an error of inner middleware, which only becomes a response after
+    /// leaving the app, with the context of its request
+    pub fn error(&self, error: Error) -> Error {
+        ContextError {
+            error,
+            context: self.clone(),
+        }
+        .into()
+    }
+}
+
+/// Future making the context current while the handlers of the request run
+pub struct WithContext<F> {
+    context: RequestContext,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for WithContext<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let inner = &mut this.inner;
+        this.context.scope(|| inner.as_mut().poll(cx))
+    }
+}
+
+/// Error of inner middleware, such as a rejected token, rendered in the
+/// locale of its request and tagged with its id
+#[derive(Debug)]
+struct ContextError {
+    error: Error,
+    context: RequestContext,
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl ResponseError for ContextError {
+    fn error_response(&self) -> HttpResponse {
+        let mut response = self
+            .context
+            .scope(|| self.error.as_response_error().error_response());
+
+        if let Some(id) = self.context.id() {
+            request_id::set_header(response.headers_mut(), &id);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::utils::errors::ApiError;
+    use crate::utils::i18n::{Locale, Localization};
+    use crate::utils::request_id::{RequestTracing, REQUEST_ID_HEADER};
+    use actix_web::dev::Service;
+    use actix_web::{http::StatusCode, test, web, App};
+
+    #[actix_rt::test]
+    async fn it_shares_one_context_between_the_middleware() {
+        let mut app = test::init_service(
+            App::new().wrap(Localization).wrap(RequestTracing).service(
+                web::resource("/invites")
+                    .wrap_fn(|req, _| {
+                        Locale::prefer(&req, Some("de"));
+                        futures::future::err(ApiError::Unauthorized.into())
+                    })
+                    .to(|| async { "" }),
+            ),
+        )
+        .await;
+
+        let invites = test::TestRequest::get()
+            .uri("/invites")
+            .header(REQUEST_ID_HEADER, "invites-1")
+            .header("accept-language", "fr")
+            .to_request();
+        let error = app.call(invites).await.err().unwrap();
+        let response = error.as_response_error().error_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers().get("content-language").unwrap(), "de");
+        assert_eq!(
+            response.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "invites-1"
+        );
+
+        let body = match response.body().as_ref() {
+            Some(actix_web::body::Body::Bytes(bytes)) => bytes.clone(),
+            _ => panic!("expected a problem document"),
+        };
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["request_id"], "invites-1");
+    }
+}
@@ -0,0 +1,579 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::{Error, HttpResponse, ResponseError};
+use futures::future::{ok, Either, LocalBoxFuture, Ready};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::settings::{deserialize_list, deserialize_parsed};
+use crate::utils::errors::ApiError;
+
+/// Cross origin rules for a set of routes
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CorsPolicy {
+    /// Exact origins, `*` for any origin or patterns such as `https://*.example.com`
+    #[serde(deserialize_with = "deserialize_list")]
+    pub allowed_origins: Vec<String>,
+    #[serde(deserialize_with = "deserialize_list")]
+    pub allowed_methods: Vec<String>,
+    /// Request headers clients may send, `*` allowing any header
+    #[serde(deserialize_with = "deserialize_list")]
+    pub allowed_headers: Vec<String>,
+    #[serde(deserialize_with = "deserialize_list")]
+    pub exposed_headers: Vec<String>,
+    #[serde(deserialize_with = "deserialize_parsed")]
+    pub allow_credentials: bool,
+    #[serde(deserialize_with = "deserialize_parsed")]
+    pub max_age: u32,
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+
+        CorsPolicy {
+            allowed_origins: strings(&["http://localhost:3000"]),
+            allowed_methods: strings(&["GET", "POST", "PUT", "PATCH", "DELETE"]),
+            allowed_headers: strings(&["authorization", "accept", "content-type"]),
+            exposed_headers: strings(&["link", "x-total-count", "x-next-cursor", "x-request-id"]),
+            allow_credentials: false,
+            max_age: 3600,
+        }
+    }
+}
+
+/// Cross origin rules for the routes under a path prefix
+#[derive(Deserialize, Debug, Clone)]
+pub struct CorsScope {
+    pub path: String,
+    #[serde(flatten)]
+    pub policy: CorsPolicy,
+}
+
+/// Allowed origin, compiled from the configured value
+#[derive(Debug, PartialEq)]
+enum OriginRule {
+    Any,
+    Exact(String),
+    /// Any subdomain, with the scheme before and the domain and port after it
+    Subdomains {
+        prefix: String,
+        suffix: String,
+    },
+}
+
+impl OriginRule {
+    fn parse(origin: &str) -> Result<OriginRule, String> {
+        let origin = origin.to_lowercase();
+
+        if origin == "*" {
+            return Ok(OriginRule::Any);
+        }
+
+        let (scheme, host) = match origin.split_once("://") {
+            Some((scheme, host)) if !scheme.is_empty() && !host.is_empty() => (scheme, host),
+            _ => return Err(format!("CORS origin {:?} must include a scheme", origin)),
+        };
+
+        if host.contains('/') {
+            return Err(format!("CORS origin {:?} must not include a path", origin));
+        }
+
+        match host.strip_prefix("*.") {
+            Some(domain) if !domain.is_empty() && !domain.contains('*') => {
+                Ok(OriginRule::Subdomains {
+                    prefix: format!("{}://", scheme),
+                    suffix: format!(".{}", domain),
+                })
+            }
+            _ if host.contains('*') => Err(format!(
+                "CORS origin {:?} may only use a wildcard for the leftmost subdomain",
+                origin
+            )),
+            _ => Ok(OriginRule::Exact(origin)),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginRule::Any => true,
+            OriginRule::Exact(allowed) => *allowed == origin,
+            OriginRule::Subdomains { prefix, suffix } => origin
+                .strip_prefix(prefix.as_str())
+                .and_then(|host| host.strip_suffix(suffix.as_str()))
+                .is_some_and(|subdomain| {
+                    !subdomain.is_empty()
+                        && subdomain
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                }),
+        }
+    }
+}
+
+/// Cross origin policy compiled into the headers it sends
+#[derive(Debug)]
+struct Rules {
+    origins: Vec<OriginRule>,
+    methods: HashSet<Method>,
+    allow_methods: HeaderValue,
+    /// Allowed request headers, None allowing any header
+    headers: Option<HashSet<HeaderName>>,
+    allow_headers: Option<HeaderValue>,
+    expose_headers: Option<HeaderValue>,
+    credentials: bool,
+    max_age: HeaderValue,
+}
+
+impl Rules {
+    fn compile(policy: &CorsPolicy) -> Result<Rules, String> {
+        let origins = policy
+            .allowed_origins
+            .iter()
+            .map(|origin| OriginRule::parse(origin))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if policy.allow_credentials && origins.contains(&OriginRule::Any) {
+            return Err(String::from(
+                "CORS credentials can't be allowed for any origin, list the origins instead",
+            ));
+        }
+
+        let methods = policy
+            .allowed_methods
+            .iter()
+            .map(|method| {
+                Method::try_from(method.to_uppercase().as_str())
+                    .map_err(|_| format!("invalid CORS method {:?}", method))
+            })
+            .collect::<Result<HashSet<_>, _>>()?;
+
+        let headers = if policy.allowed_headers.iter().any(|name| name == "*") {
+            None
+        } else {
+            Some(parse_headers(&policy.allowed_headers)?)
+        };
+
+        parse_headers(&policy.exposed_headers)?;
+
+        Ok(Rules {
+            origins,
+            allow_methods: join(policy.allowed_methods.iter().map(|m| m.to_uppercase()))?,
+            methods,
+            allow_headers: match &headers {
+                Some(_) if !policy.allowed_headers.is_empty() => {
+                    Some(join(policy.allowed_headers.iter().cloned())?)
+                }
+                _ => None,
+            },
+            headers,
+            expose_headers: if policy.exposed_headers.is_empty() {
+                None
+            } else {
+                Some(join(policy.exposed_headers.iter().cloned())?)
+            },
+            credentials: policy.allow_credentials,
+            max_age: HeaderValue::from(policy.max_age),
+        })
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        let origin = origin.to_lowercase();
+
+        self.origins.iter().any(|rule| rule.matches(&origin))
+    }
+
+    /// Checks the requested method and headers of a preflight request
+    fn allows_preflight(&self, headers: &HeaderMap) -> bool {
+        let method = headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| Method::from_bytes(method.as_bytes()).ok());
+
+        if !method.is_some_and(|method| self.methods.contains(&method)) {
+            return false;
+        }
+
+        let allowed = match &self.headers {
+            Some(allowed) => allowed,
+            None => return true,
+        };
+
+        let requested = match headers.get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+            Some(requested) => requested.to_str().unwrap_or(""),
+            None => return true,
+        };
+
+        requested
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .all(|name| HeaderName::try_from(name).is_ok_and(|name| allowed.contains(&name)))
+    }
+
+    /// Adds the headers shared by preflight and actual responses
+    fn apply(&self, origin: &HeaderValue, headers: &mut HeaderMap) {
+        let any_origin = !self.credentials && self.origins.contains(&OriginRule::Any);
+
+        if any_origin {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                HeaderValue::from_static("*"),
+            );
+        } else {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        }
+
+        if self.credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    /// Adds the headers of a response to an actual cross origin request
+    fn apply_actual(&self, origin: &HeaderValue, headers: &mut HeaderMap) {
+        self.apply(origin, headers);
+
+        if let Some(expose_headers) = &self.expose_headers {
+            headers.insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                expose_headers.clone(),
+            );
+        }
+    }
+}
+
+/// Error returned by the wrapped services, answered with the CORS headers so
+/// the client can read it
+#[derive(Debug)]
+struct CorsError {
+    error: Error,
+    /// Origin of the request when the policy allows it
+    origin: Option<HeaderValue>,
+    rules: Arc<Rules>,
+}
+
+impl fmt::Display for CorsError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(formatter)
+    }
+}
+
+impl ResponseError for CorsError {
+    fn error_response(&self) -> HttpResponse {
+        let mut response = self.error.as_response_error().error_response();
+        let headers = response.headers_mut();
+
+        vary_on_origin(headers);
+        if let Some(origin) = &self.origin {
+            self.rules.apply_actual(origin, headers);
+        }
+
+        response
+    }
+}
+
+/// Tells caches the response depends on the origin of the request, which
+/// decides whether, and which, CORS headers are sent. Responses to requests
+/// without an origin, and to refused ones, depend on it as much
+fn vary_on_origin(headers: &mut HeaderMap) {
+    let varies = headers
+        .get_all(header::VARY)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|name| name.trim().eq_ignore_ascii_case("origin"));
+
+    if !varies {
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+}
+
+/// Parses the header names, failing on the first invalid one
+fn parse_headers(names: &[String]) -> Result<HashSet<HeaderName>, String> {
+    names
+        .iter()
+        .map(|name| {
+            HeaderName::try_from(name.as_str())
+                .map_err(|_| format!("invalid CORS header {:?}", name))
+        })
+        .collect()
+}
+
+/// Joins the values into a single comma separated header value
+fn join(values: impl Iterator<Item = String>) -> Result<HeaderValue, String> {
+    let joined = values.collect::<Vec<_>>().join(", ");
+
+    HeaderValue::from_str(&joined).map_err(|_| format!("invalid CORS header value {:?}", joined))
+}
+
+/// Middleware applying the CORS policy of the scope a request is routed to,
+/// falling back to the default policy
+#[derive(Clone)]
+pub struct Cors {
+    default: Arc<Rules>,
+    scopes: Arc<Vec<(String, Arc<Rules>)>>,
+}
+
+impl Cors {
+    /// Compiles the default and scoped policies, failing on invalid values
+    pub fn new(default: &CorsPolicy, scopes: &[CorsScope]) -> Result<Cors, String> {
+        let mut compiled = scopes
+            .iter()
+            .map(|scope| {
+                let path = scope.path.trim_end_matches('/').to_string();
+                Rules::compile(&scope.policy).map(|rules| (path, Arc::new(rules)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // the most specific scope wins when prefixes overlap
+        compiled.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+
+        Ok(Cors {
+            default: Arc::new(Rules::compile(default)?),
+            scopes: Arc::new(compiled),
+        })
+    }
+
+    fn rules_for(&self, path: &str) -> Arc<Rules> {
+        self.scopes
+            .iter()
+            .find(|(prefix, _)| {
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .map_or_else(|| self.default.clone(), |(_, rules)| rules.clone())
+    }
+}
+
+impl<S, B> Transform<S> for Cors
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CorsMiddleware {
+            service,
+            cors: self.clone(),
+        })
+    }
+}
+
+/// Service created by the Cors middleware for every worker
+pub struct CorsMiddleware<S> {
+    service: S,
+    cors: Cors,
+}
+
+impl<S, B> Service for CorsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<
+        Ready<Result<Self::Response, Error>>,
+        LocalBoxFuture<'static, Result<Self::Response, Error>>,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let rules = self.cors.rules_for(req.path());
+        let origin = req.headers().get(header::ORIGIN).cloned();
+
+        let is_preflight = origin.is_some()
+            && req.method() == Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+        let allowed = origin.filter(|origin| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| rules.allows_origin(origin))
+        });
+
+        if is_preflight {
+            let origin = match allowed {
+                Some(origin) if rules.allows_preflight(req.headers()) => origin,
+                _ => {
+                    let mut response = ApiError::Forbidden.error_response();
+                    vary_on_origin(response.headers_mut());
+                    return Either::Left(ok(req.into_response(response.into_body())));
+                }
+            };
+
+            let mut response = HttpResponse::Ok().finish();
+            let headers = response.headers_mut();
+
+            vary_on_origin(headers);
+            rules.apply(&origin, headers);
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                rules.allow_methods.clone(),
+            );
+
+            let allow_headers = rules.allow_headers.clone().or_else(|| {
+                req.headers()
+                    .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                    .cloned()
+            });
+            if let Some(allow_headers) = allow_headers {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+            }
+
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, rules.max_age.clone());
+
+            return Either::Left(ok(req.into_response(response.into_body())));
+        }
+
+        let response = self.service.call(req);
+
+        Either::Right(Box::pin(async move {
+            match response.await {
+                Ok(mut response) => {
+                    let headers = response.headers_mut();
+
+                    vary_on_origin(headers);
+                    if let Some(origin) = &allowed {
+                        rules.apply_actual(origin, headers);
+                    }
+                    Ok(response)
+                }
+                // errors from inner middleware, such as a rejected token, only
+                // become responses after leaving this middleware
+                Err(error) => Err(CorsError {
+                    error,
+                    origin: allowed,
+                    rules,
+                }
+                .into()),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    /// Policy allowing the origins with credentials
+    fn policy(origins: &[&str]) -> CorsPolicy {
+        CorsPolicy {
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            allow_credentials: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_matches_wildcard_subdomains() {
+        let rule = OriginRule::parse("https://*.example.com").unwrap();
+
+        assert!(rule.matches("https://app.example.com"));
+        assert!(rule.matches("https://staging.app.example.com"));
+        assert!(!rule.matches("https://example.com"));
+        assert!(!rule.matches("http://app.example.com"));
+        assert!(!rule.matches("https://app.example.com.evil.com"));
+        assert!(!rule.matches("https://evil.com/.example.com"));
+
+        assert!(OriginRule::parse("example.com").is_err());
+        assert!(OriginRule::parse("https://app.*.example.com").is_err());
+        assert!(Rules::compile(&policy(&["*"])).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn it_applies_scoped_policies() {
+        let cors = Cors::new(
+            &policy(&["https://*.example.com"]),
+            &[CorsScope {
+                path: String::from("/admin"),
+                policy: policy(&["https://admin.example.com"]),
+            }],
+        )
+        .unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(cors)
+                .route("/users", web::get().to(HttpResponse::Ok))
+                .route("/admin/users", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let request = |path: &str, origin: &str| {
+            test::TestRequest::get()
+                .uri(path)
+                .header(header::ORIGIN, origin)
+                .to_request()
+        };
+        let allowed_origin = |response: &ServiceResponse| {
+            response
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .map(|origin| origin.to_str().unwrap().to_string())
+        };
+
+        let response =
+            test::call_service(&mut app, request("/users", "https://app.example.com")).await;
+        assert_eq!(
+            allowed_origin(&response).as_deref(),
+            Some("https://app.example.com")
+        );
+
+        let response =
+            test::call_service(&mut app, request("/admin/users", "https://app.example.com")).await;
+        assert_eq!(allowed_origin(&response), None);
+        assert_eq!(response.headers().get(header::VARY).unwrap(), "Origin");
+
+        // caches must not answer cross origin requests with responses to
+        // requests sent without an origin
+        let same_origin = test::TestRequest::get().uri("/users").to_request();
+        let response = test::call_service(&mut app, same_origin).await;
+        assert_eq!(allowed_origin(&response), None);
+        assert_eq!(response.headers().get(header::VARY).unwrap(), "Origin");
+
+        let preflight = test::TestRequest::with_uri("/admin/users")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "https://admin.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .to_request();
+        let response = test::call_service(&mut app, preflight).await;
+        assert!(response.status().is_success());
+        assert_eq!(
+            allowed_origin(&response).as_deref(),
+            Some("https://admin.example.com")
+        );
+
+        let preflight = test::TestRequest::with_uri("/users")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "TRACE")
+            .to_request();
+        let response = test::call_service(&mut app, preflight).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(response.headers().get(header::VARY).unwrap(), "Origin");
+    }
+}
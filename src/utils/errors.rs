@@ -1,148 +1,518 @@
-use actix_web::error::{BlockingError, JsonPayloadError};
+use actix_web::error::{BlockingError, JsonPayloadError, PayloadError};
+use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
 use diesel::{
     r2d2::PoolError,
-    result::{DatabaseErrorKind, Error as DatabaseError},
+    result::{DatabaseErrorInformation, DatabaseErrorKind, Error as DatabaseError},
 };
 
 use failure::Fail;
 use serde::Serialize;
-use validator::{ValidationErrors, ValidationErrorsKind};
+use serde_json::{Map, Value};
+use utoipa::openapi::{ObjectBuilder, SchemaType};
+use utoipa::ToSchema;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::utils::i18n::{self, Locale};
+use crate::utils::request_id::RequestId;
+
+/// Prefix of the URIs identifying the type of a problem, followed by the
+/// kind of ApiError such as `invalid-login`
+pub const PROBLEM_TYPE_PREFIX: &str = "urn:auth:problem:";
+
+/// Media type of error responses
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Declares codes as an enum along with the list of every code, both
+/// generated from the same declaration so neither can miss one
+macro_rules! codes {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident => $code:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl $name {
+            /// Every code, in the order they are declared
+            pub const ALL: &'static [$name] = &[$($name::$variant,)*];
+
+            /// Returns the code as sent to clients
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $($name::$variant => $code,)*
+                }
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+    };
+}
+
+codes! {
+    /// Codes of internal server errors, telling apart what went wrong
+    InternalCode {
+        Database => "DATABASE_ERROR",
+        UnknownDatabase => "UNKNOWN_DATABASE_ERROR",
+        DatabasePool => "DATABASE_POOL_ERROR",
+        DatabaseStopped => "DATABASE_STOPPED",
+        Blocking => "BLOCKING_ERROR",
+        Migration => "MIGRATION_ERROR",
+        Metrics => "METRICS_ERROR",
+        Mail => "MAIL_ERROR",
+        SigningKey => "SIGNING_KEY_ERROR",
+        Store => "STORE_ERROR",
+        Token => "TOKEN_ERROR",
+    }
+}
+
+codes! {
+    /// Codes of the field errors of a VALIDATION_ERROR, naming what was
+    /// invalid. Codes given to validator attributes have to be one of them
+    ValidationCode {
+        InvalidEmail => "INVALID_EMAIL",
+        InvalidRole => "INVALID_ROLE",
+        InvalidLocale => "INVALID_LOCALE",
+        InvalidCount => "INVALID_COUNT",
+        InvalidLabel => "INVALID_LABEL",
+        InvalidMaxRedemptions => "INVALID_MAX_REDEMPTIONS",
+        InvalidSource => "INVALID_SOURCE",
+        InvalidCsv => "INVALID_CSV",
+        InvalidQuery => "INVALID_QUERY",
+        InvalidLimit => "INVALID_LIMIT",
+        InvalidOffset => "INVALID_OFFSET",
+        InvalidCursor => "INVALID_CURSOR",
+        InvalidSort => "INVALID_SORT",
+        InvalidFilter => "INVALID_FILTER",
+        InvalidPagination => "INVALID_PAGINATION",
+        UnknownParameter => "UNKNOWN_PARAMETER",
+        MalformedJson => "MALFORMED_JSON",
+        MissingField => "MISSING_FIELD",
+        UnknownField => "UNKNOWN_FIELD",
+        InvalidType => "INVALID_TYPE",
+    }
+}
+
+/// Returns every code an ApiError response can carry, internal errors last
+pub fn error_codes() -> Vec<&'static str> {
+    let internal = InternalCode::ALL.iter().map(|code| code.as_str());
+
+    std::iter::once(VALIDATION_ERROR)
+        .chain(ApiError::KIND_CODES.iter().copied())
+        .chain(internal)
+        .collect()
+}
+
+/// Code of every ValidationError
+const VALIDATION_ERROR: &str = "VALIDATION_ERROR";
 
 /// Representation of an ApiError
 #[derive(Fail, Debug)]
 pub enum ApiError {
     #[fail(display = "An internal server error occured: {}", _0)]
-    InternalServerError(String, String),
+    InternalServerError(InternalCode, String),
     #[fail(display = "A validation error occurred: {}", _0)]
-    ValidationError(String, String, Vec<String>),
+    ValidationError(String, String, Vec<FieldError>),
     #[fail(display = "Beta key is invalid or taken")]
     InvalidBetaKey,
+    #[fail(display = "A beta key is required to sign up")]
+    BetaKeyRequired,
+    #[fail(display = "The email domain is not allowed to sign up")]
+    EmailDomainNotAllowed,
+    #[fail(display = "Registration is closed")]
+    RegistrationClosed,
     #[fail(display = "The provided email and password are invalid")]
     InvalidLogin,
     #[fail(display = "Unauthorized. Please login to continue")]
     Unauthorized,
+    #[fail(display = "Forbidden. Insufficient permissions")]
+    Forbidden,
+    #[fail(display = "The account has been disabled")]
+    AccountDisabled,
+    #[fail(display = "The requested resource could not be found")]
+    NotFound,
+    #[fail(display = "Beta key has already been redeemed")]
+    KeyAlreadyRedeemed,
+    #[fail(display = "An account already exists for the email")]
+    EmailTaken,
+    #[fail(display = "The account could not be created")]
+    SignupFailed,
+    #[fail(display = "The invite limit has been reached")]
+    InviteLimitReached,
+    #[fail(display = "The email is already on the waitlist")]
+    AlreadyOnWaitlist,
+    #[fail(display = "A record with the same values already exists")]
+    Conflict,
+    #[fail(display = "The database is too busy to handle the request")]
+    DatabaseBusy,
+    #[fail(display = "The request body is too large")]
+    PayloadTooLarge,
+    #[fail(display = "The request body is not JSON")]
+    UnsupportedMediaType,
+}
+
+/// Problem with a single field of a request
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    /// Path to the field, such as `email` or `keys[2].label`
+    pub field: String,
+    #[schema(schema_with = validation_code)]
+    pub code: String,
+    /// Constraints the value failed to meet, such as `min` and `max`
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    #[schema(value_type = Object)]
+    pub params: Map<String, Value>,
+    /// Description of the problem in the locale of the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: ValidationCode) -> Self {
+        FieldError {
+            field: field.to_string(),
+            code: code.as_str().to_string(),
+            params: Map::new(),
+            message: None,
+        }
+    }
+
+    /// Adds a constraint the value failed to meet
+    pub fn with_param<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        self.params.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Converts the error of a validator, leaving out the rejected value so
+    /// passwords and the like aren't echoed back
+    fn from_validation(field: &str, error: &ValidationError) -> Self {
+        let params = error
+            .params
+            .iter()
+            .filter(|(name, _)| name.as_ref() != "value")
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+
+        FieldError {
+            field: field.to_string(),
+            code: error.code.to_string(),
+            params,
+            message: None,
+        }
+    }
+}
+
+impl ApiError {
+    /// Creates a validation error from the problems with individual fields
+    pub fn invalid_fields(errors: Vec<FieldError>) -> Self {
+        ApiError::ValidationError(
+            String::from(VALIDATION_ERROR),
+            Locale::DEFAULT
+                .message(VALIDATION_ERROR)
+                .unwrap_or("Invalid request")
+                .to_string(),
+            errors,
+        )
+    }
+
+    /// Returns the kind of the error, identifying its problem type, and a
+    /// short summary that's the same for every error of that kind
+    pub fn problem(&self) -> (&'static str, &'static str) {
+        match self {
+            ApiError::InternalServerError(_, _) => {
+                ("internal-server-error", "Internal server error")
+            }
+            ApiError::ValidationError(_, _, _) => ("validation-error", "Invalid request"),
+            ApiError::InvalidBetaKey => ("invalid-beta-key", "Invalid beta key"),
+            ApiError::BetaKeyRequired => ("beta-key-required", "Beta key required"),
+            ApiError::EmailDomainNotAllowed => {
+                ("email-domain-not-allowed", "Email domain not allowed")
+            }
+            ApiError::RegistrationClosed => ("registration-closed", "Registration closed"),
+            ApiError::InvalidLogin => ("invalid-login", "Invalid login"),
+            ApiError::Unauthorized => ("unauthorized", "Unauthorized"),
+            ApiError::Forbidden => ("forbidden", "Forbidden"),
+            ApiError::AccountDisabled => ("account-disabled", "Account disabled"),
+            ApiError::NotFound => ("not-found", "Not found"),
+            ApiError::KeyAlreadyRedeemed => ("key-already-redeemed", "Beta key already redeemed"),
+            ApiError::EmailTaken => ("email-taken", "Email taken"),
+            ApiError::SignupFailed => ("signup-failed", "Signup failed"),
+            ApiError::InviteLimitReached => ("invite-limit-reached", "Invite limit reached"),
+            ApiError::AlreadyOnWaitlist => ("already-on-waitlist", "Already on the waitlist"),
+            ApiError::Conflict => ("conflict", "Conflict"),
+            ApiError::DatabaseBusy => ("database-busy", "Service busy"),
+            ApiError::PayloadTooLarge => ("payload-too-large", "Payload too large"),
+            ApiError::UnsupportedMediaType => ("unsupported-media-type", "Unsupported media type"),
+        }
+    }
+
+    /// Returns the message shown to users, which the catalog of the default
+    /// locale holds for every kind of error but internal and validation ones
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::InternalServerError(_, message) => message,
+            ApiError::ValidationError(_, message, _) => message,
+            _ => Locale::DEFAULT
+                .message(self.code())
+                .unwrap_or_else(|| self.problem().1),
+        }
+    }
+}
+
+/// Implements `code` for the kinds of errors that always carry the same code
+/// and lists those codes, the match making a kind left out fail to compile
+macro_rules! kind_codes {
+    ($($variant:ident => $code:literal,)*) => {
+        impl ApiError {
+            /// Codes of the kinds of errors that always carry the same code
+            const KIND_CODES: &'static [&'static str] = &[$($code,)*];
+
+            /// Returns the code clients and dashboards tell the error apart with
+            pub fn code(&self) -> &str {
+                match self {
+                    ApiError::InternalServerError(code, _) => code.as_str(),
+                    ApiError::ValidationError(code, _, _) => code,
+                    $(ApiError::$variant => $code,)*
+                }
+            }
+        }
+    };
+}
+
+kind_codes! {
+    InvalidBetaKey => "INVALID_BETA_KEY",
+    BetaKeyRequired => "BETA_KEY_REQUIRED",
+    EmailDomainNotAllowed => "EMAIL_DOMAIN_NOT_ALLOWED",
+    RegistrationClosed => "REGISTRATION_CLOSED",
+    InvalidLogin => "INVALID_LOGIN",
+    Unauthorized => "UNAUTHORIZED",
+    Forbidden => "FORBIDDEN",
+    AccountDisabled => "ACCOUNT_DISABLED",
+    NotFound => "NOT_FOUND",
+    KeyAlreadyRedeemed => "KEY_ALREADY_REDEEMED",
+    EmailTaken => "EMAIL_TAKEN",
+    SignupFailed => "SIGNUP_FAILED",
+    InviteLimitReached => "INVITE_LIMIT_REACHED",
+    AlreadyOnWaitlist => "ALREADY_ON_WAITLIST",
+    Conflict => "CONFLICT",
+    DatabaseBusy => "DATABASE_BUSY",
+    PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+    UnsupportedMediaType => "UNSUPPORTED_MEDIA_TYPE",
 }
 
 /// Automatically convert ApiErrors to user facing errors
 impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InternalServerError(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::ValidationError(_, _, _)
+            | ApiError::InvalidBetaKey
+            | ApiError::BetaKeyRequired
+            | ApiError::InvalidLogin
+            | ApiError::SignupFailed => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::EmailDomainNotAllowed
+            | ApiError::RegistrationClosed
+            | ApiError::Forbidden
+            | ApiError::AccountDisabled
+            | ApiError::InviteLimitReached => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::KeyAlreadyRedeemed
+            | ApiError::EmailTaken
+            | ApiError::AlreadyOnWaitlist
+            | ApiError::Conflict => StatusCode::CONFLICT,
+            ApiError::DatabaseBusy => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        }
+    }
+
     fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+
         match self {
-            ApiError::InternalServerError(code, message) => {
-                HttpResponse::InternalServerError()
-                    .json::<UserErrorResponse>((code, message).into())
+            ApiError::Unauthorized => {
+                response.header("www-authenticate", "Bearer");
             }
-            ApiError::ValidationError(code, message, errors) => {
-                HttpResponse::BadRequest().json::<UserErrorResponse>((code, message, errors).into())
+            ApiError::DatabaseBusy => {
+                response.header("retry-after", "1");
             }
-            ApiError::InvalidBetaKey => HttpResponse::BadRequest().json::<UserErrorResponse>(
-                (
-                    "INVALID_BETA_KEY",
-                    "The provided beta key is taken or invalid",
-                )
-                    .into(),
-            ),
-            ApiError::InvalidLogin => HttpResponse::BadRequest().json::<UserErrorResponse>(
-                (
-                    "INVALID_LOGIN",
-                    "The provided email and password are invalid",
-                )
-                    .into(),
-            ),
-            ApiError::Unauthorized => HttpResponse::Unauthorized()
-                .header("www-authenticate", "Bearer")
-                .json::<UserErrorResponse>(("UNAUTHORIZED", "Please login to continue").into()),
+            _ => {}
+        }
+
+        // internal errors aren't translated, keeping what went wrong
+        let locale = Locale::current();
+        response.header("content-language", locale.as_str());
+        let detail = locale
+            .message(self.code())
+            .unwrap_or_else(|| self.message());
+
+        let errors = match self {
+            ApiError::ValidationError(_, _, errors) => errors
+                .iter()
+                .map(|error| FieldError {
+                    message: locale
+                        .message(&error.code)
+                        .map(|message| i18n::format(message, &error.params)),
+                    ..error.clone()
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let (kind, title) = self.problem();
+        let problem = Problem {
+            problem_type: format!("{}{}", PROBLEM_TYPE_PREFIX, kind),
+            title: title.to_string(),
+            status: self.status_code().as_u16(),
+            detail: detail.to_string(),
+            code: self.code().to_string(),
+            errors,
+            request_id: RequestId::current(),
+        };
+
+        match serde_json::to_string(&problem) {
+            Ok(body) => response.content_type(PROBLEM_CONTENT_TYPE).body(body),
+            Err(error) => HttpResponse::from_error(error.into()),
         }
     }
 }
 
+/// Converts the errors of the JSON extractor. Bodies are parsed into a value
+/// first, so deserializing only fails on malformed JSON, while `utils::json`
+/// reports fields of the wrong type
 impl From<JsonPayloadError> for ApiError {
     fn from(error: JsonPayloadError) -> ApiError {
         match error {
-            JsonPayloadError::Deserialize(_) => ApiError::ValidationError(
-                String::from("VALIDATION_ERROR"),
-                String::from("A validation error occurred"),
-                Vec::new(),
-            ),
-            _ => ApiError::ValidationError(
-                String::from("VALIDATION_ERROR"),
-                String::from("A validation error occurred"),
-                Vec::new(),
-            ),
+            JsonPayloadError::Overflow | JsonPayloadError::Payload(PayloadError::Overflow) => {
+                ApiError::PayloadTooLarge
+            }
+            JsonPayloadError::ContentType => ApiError::UnsupportedMediaType,
+            JsonPayloadError::Deserialize(error) => {
+                let field = FieldError::new("body", ValidationCode::MalformedJson)
+                    .with_param("line", error.line())
+                    .with_param("column", error.column());
+                ApiError::invalid_fields(vec![field])
+            }
+            JsonPayloadError::Payload(_) => ApiError::invalid_fields(vec![FieldError::new(
+                "body",
+                ValidationCode::MalformedJson,
+            )]),
         }
     }
 }
 
-/// Respresents the response a user will get when an ApiError occurs
-#[derive(Serialize, Debug)]
-struct UserErrorResponse {
+/// Respresents the response a user will get when an ApiError occurs, as
+/// RFC 7807 problem details
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Problem {
+    /// URI identifying the kind of error, such as `urn:auth:problem:invalid-login`
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    /// Message to show to users, in the locale of the request
+    detail: String,
+    #[schema(schema_with = error_code)]
     code: String,
-    message: String,
-    errors: Option<Vec<String>>,
-}
-
-/// Utility for converting a the strings to a usable UserErrorResponse
-impl From<(&str, &str)> for UserErrorResponse {
-    fn from(error: (&str, &str)) -> UserErrorResponse {
-        UserErrorResponse {
-            code: error.0.into(),
-            message: error.1.into(),
-            errors: None,
-        }
-    }
+    /// Fields that failed validation
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+    /// Id of the request, for support to find its logs with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
-/// Utility for converting a the strings references to a usable UserErrorResponse
-impl From<(&String, &String)> for UserErrorResponse {
-    fn from(error: (&String, &String)) -> UserErrorResponse {
-        UserErrorResponse {
-            code: error.0.into(),
-            message: error.1.into(),
-            errors: None,
-        }
-    }
+/// Documents the code of an error response as one of the known codes
+fn error_code() -> ObjectBuilder {
+    ObjectBuilder::new()
+        .schema_type(SchemaType::String)
+        .enum_values(Some(error_codes()))
 }
 
-/// Utility for converting a tuple to a usable UserErrorResponse
-impl From<(&String, &String, &Vec<String>)> for UserErrorResponse {
-    fn from(error: (&String, &String, &Vec<String>)) -> UserErrorResponse {
-        UserErrorResponse {
-            code: error.0.into(),
-            message: error.1.into(),
-            errors: Some(error.2.to_vec().into()),
-        }
-    }
+/// Documents the code of a field error as one of the known codes
+fn validation_code() -> ObjectBuilder {
+    ObjectBuilder::new()
+        .schema_type(SchemaType::String)
+        .enum_values(Some(ValidationCode::ALL.iter().map(|code| code.as_str())))
 }
 
 /// Converts a Database error to an ApiError
 impl From<DatabaseError> for ApiError {
     fn from(error: DatabaseError) -> ApiError {
         match error {
-            DatabaseError::DatabaseError(kind, _) => match kind {
-                DatabaseErrorKind::UniqueViolation => ApiError::InternalServerError(
-                    String::from("DATABASE_UNIQUNESS_ERROR"),
-                    String::from("Uniquness database error occurred"),
-                ),
+            DatabaseError::DatabaseError(kind, info) => match kind {
+                DatabaseErrorKind::UniqueViolation => unique_violation(info.as_ref()),
                 _ => ApiError::InternalServerError(
-                    String::from("DATABASE_ERROR"),
+                    InternalCode::Database,
                     String::from("Database error occurred"),
                 ),
             },
+            DatabaseError::NotFound => ApiError::NotFound,
             _ => ApiError::InternalServerError(
-                String::from("UNKNOWN_DATABASE_ERROR"),
+                InternalCode::UnknownDatabase,
                 String::from("Unknown database error occurred"),
             ),
         }
     }
 }
 
+/// Reports the violation of a unique constraint as the conflict it means to
+/// users, going by the table and columns of the constraint so renamed or new
+/// constraints are still told apart. Constraints that don't stand for one of
+/// the known conflicts are reported as a generic conflict
+fn unique_violation(info: &dyn DatabaseErrorInformation) -> ApiError {
+    let (table, columns) = match unique_columns(info) {
+        Some(key) => key,
+        None => return ApiError::Conflict,
+    };
+    let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+
+    match (table.as_str(), columns.as_slice()) {
+        ("users", ["email"]) => ApiError::EmailTaken,
+        ("redemptions", _) => ApiError::KeyAlreadyRedeemed,
+        ("waitlist", ["email"]) => ApiError::AlreadyOnWaitlist,
+        _ => ApiError::Conflict,
+    }
+}
+
+/// Returns the table and columns of a violated unique constraint. Postgres
+/// reports the table and lists the columns in the detail, such as
+/// `Key (email)=(a@b.com) already exists.`, while SQLite lists them in its
+/// message, such as `UNIQUE constraint failed: users.email`
+fn unique_columns(info: &dyn DatabaseErrorInformation) -> Option<(String, Vec<String>)> {
+    if let Some(table) = info.table_name() {
+        let details = info.details()?;
+        let start = details.find("Key (")? + "Key (".len();
+        let end = details.find(")=(")?;
+        let columns = details.get(start..end)?.split(", ").map(String::from);
+
+        return Some((table.to_string(), columns.collect()));
+    }
+
+    let qualified = info.message().strip_prefix("UNIQUE constraint failed: ")?;
+    let mut table = None;
+    let mut columns = Vec::new();
+    for column in qualified.split(", ") {
+        let (name, column) = column.split_once('.')?;
+        table = Some(name.to_string());
+        columns.push(column.to_string());
+    }
+
+    Some((table?, columns))
+}
+
 /// Converts a web::block Blocking error to an ApiError
 impl From<BlockingError<ApiError>> for ApiError {
     fn from(error: BlockingError<ApiError>) -> ApiError {
         match error {
             BlockingError::Error(api_error) => api_error,
             BlockingError::Canceled => ApiError::InternalServerError(
-                String::from("BLOCKING_ERROR"),
+                InternalCode::Blocking,
                 String::from("Thread execution was blocked"),
             ),
         }
@@ -153,33 +523,263 @@ impl From<BlockingError<ApiError>> for ApiError {
 impl From<PoolError> for ApiError {
     fn from(_error: PoolError) -> ApiError {
         ApiError::InternalServerError(
-            String::from("DATABASE_POOL_ERROR"),
+            InternalCode::DatabasePool,
             String::from("Could not get connection to database"),
         )
     }
 }
 
-/// Converts a validation error into an ApiError
+/// Converts a validation error into an ApiError, naming the path to every
+/// field that failed
 impl From<ValidationErrors> for ApiError {
     fn from(errors: ValidationErrors) -> ApiError {
-        let mut codes: Vec<String> = Vec::new();
-
-        // build a vector of all error codes to return
-        for (_, kind) in errors.into_errors().iter() {
-            match kind {
-                ValidationErrorsKind::Field(errors) => {
-                    for e in errors {
-                        codes.push(e.code.to_string());
-                    }
+        let mut fields = Vec::new();
+        collect_field_errors(&errors, "", &mut fields);
+
+        // the validator keeps fields in a map, so order them for stable responses
+        fields.sort_by(|a, b| (&a.field, &a.code).cmp(&(&b.field, &b.code)));
+
+        ApiError::invalid_fields(fields)
+    }
+}
+
+/// Flattens the errors of nested structs and lists into errors of the fields
+/// they contain, such as `keys[2].label`
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, fields: &mut Vec<FieldError>) {
+    for (name, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => fields.extend(
+                errors
+                    .iter()
+                    .map(|error| FieldError::from_validation(&path, error)),
+            ),
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(errors, &path, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect_field_errors(errors, &format!("{}[{}]", path, index), fields);
                 }
-                _ => println!("Unhandled validation of kind: {:?}", kind),
             }
         }
+    }
+}
 
-        ApiError::ValidationError(
-            String::from("VALIDATION_ERROR"),
-            String::from("A validation error occurred"),
-            codes,
-        )
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use actix_web::body::{Body, ResponseBody};
+    use serde::Deserialize;
+    use validator::Validate;
+
+    #[derive(Validate, Deserialize, Debug)]
+    struct Label {
+        #[validate(length(min = 1, max = 8, code = "INVALID_LABEL"))]
+        name: String,
+    }
+
+    #[derive(Validate, Deserialize, Debug)]
+    #[serde(deny_unknown_fields)]
+    struct Batch {
+        #[validate(email(code = "INVALID_EMAIL"))]
+        email: String,
+        #[validate]
+        label: Label,
+        #[validate]
+        labels: Vec<Label>,
+    }
+
+    fn label(name: &str) -> Label {
+        Label {
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn it_names_the_path_to_nested_fields() {
+        let batch = Batch {
+            email: String::from("nope"),
+            label: label(""),
+            labels: vec![label("ok"), label("much too long")],
+        };
+
+        let fields = match ApiError::from(batch.validate().unwrap_err()) {
+            ApiError::ValidationError(_, _, fields) => fields,
+            other => panic!("expected validation error, got {:?}", other),
+        };
+
+        assert_eq!(
+            fields,
+            vec![
+                FieldError::new("email", ValidationCode::InvalidEmail),
+                FieldError::new("label.name", ValidationCode::InvalidLabel)
+                    .with_param("min", 1)
+                    .with_param("max", 8),
+                FieldError::new("labels[1].name", ValidationCode::InvalidLabel)
+                    .with_param("min", 1)
+                    .with_param("max", 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_where_a_json_body_is_malformed() {
+        let error = serde_json::from_str::<Value>("{\"email\": ").unwrap_err();
+
+        match ApiError::from(JsonPayloadError::Deserialize(error)) {
+            ApiError::ValidationError(_, _, fields) => assert_eq!(
+                fields,
+                vec![FieldError::new("body", ValidationCode::MalformedJson)
+                    .with_param("line", 1)
+                    .with_param("column", 10)]
+            ),
+            other => panic!("expected validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_maps_unique_violations_to_the_taken_resource() {
+        let violation = |message: &str| {
+            let info = Box::new(message.to_string());
+            ApiError::from(DatabaseError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                info,
+            ))
+        };
+
+        match violation("UNIQUE constraint failed: users.email") {
+            ApiError::EmailTaken => {}
+            other => panic!("expected taken email error, got {:?}", other),
+        }
+        match violation("UNIQUE constraint failed: redemptions.key_id, redemptions.user_id") {
+            ApiError::KeyAlreadyRedeemed => {}
+            other => panic!("expected redeemed key error, got {:?}", other),
+        }
+        match violation("UNIQUE constraint failed: keys.label") {
+            ApiError::Conflict => {}
+            other => panic!("expected conflict, got {:?}", other),
+        }
+        match violation("duplicate key value") {
+            ApiError::Conflict => {}
+            other => panic!("expected conflict, got {:?}", other),
+        }
+    }
+
+    /// Error information shaped like the one Postgres reports
+    struct PgViolation(&'static str, &'static str);
+
+    impl DatabaseErrorInformation for PgViolation {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint"
+        }
+        fn details(&self) -> Option<&str> {
+            Some(self.1)
+        }
+        fn hint(&self) -> Option<&str> {
+            None
+        }
+        fn table_name(&self) -> Option<&str> {
+            Some(self.0)
+        }
+        fn column_name(&self) -> Option<&str> {
+            None
+        }
+        fn constraint_name(&self) -> Option<&str> {
+            Some("renamed_constraint")
+        }
+    }
+
+    #[test]
+    fn it_maps_unique_violations_by_table_and_columns() {
+        let violation = |table, details| {
+            let info = Box::new(PgViolation(table, details));
+            ApiError::from(DatabaseError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                info,
+            ))
+        };
+
+        match violation("users", "Key (email)=(a@b.com) already exists.") {
+            ApiError::EmailTaken => {}
+            other => panic!("expected taken email error, got {:?}", other),
+        }
+        match violation("waitlist", "Key (email)=(a@b.com) already exists.") {
+            ApiError::AlreadyOnWaitlist => {}
+            other => panic!("expected waitlist error, got {:?}", other),
+        }
+        match violation("users", "Key (locale, email)=(en, a@b.com) already exists.") {
+            ApiError::Conflict => {}
+            other => panic!("expected conflict, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_responds_with_problem_details() {
+        let error =
+            ApiError::invalid_fields(vec![FieldError::new("email", ValidationCode::InvalidEmail)]);
+        let response = error.error_response();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/problem+json"
+        );
+
+        let body = match response.body() {
+            ResponseBody::Body(Body::Bytes(bytes)) => bytes.clone(),
+            _ => panic!("expected a body"),
+        };
+        let problem: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(problem["type"], "urn:auth:problem:validation-error");
+        assert_eq!(problem["title"], "Invalid request");
+        assert_eq!(problem["status"], 400);
+        assert_eq!(problem["code"], "VALIDATION_ERROR");
+        assert_eq!(problem["errors"][0]["field"], "email");
+        assert_eq!(problem["errors"][0]["code"], "INVALID_EMAIL");
+        assert!(problem["errors"][0].get("params").is_none());
+    }
+
+    #[test]
+    fn it_lists_every_error_code() {
+        let errors = vec![
+            ApiError::InvalidBetaKey,
+            ApiError::BetaKeyRequired,
+            ApiError::EmailDomainNotAllowed,
+            ApiError::RegistrationClosed,
+            ApiError::InvalidLogin,
+            ApiError::Unauthorized,
+            ApiError::Forbidden,
+            ApiError::AccountDisabled,
+            ApiError::NotFound,
+            ApiError::KeyAlreadyRedeemed,
+            ApiError::InviteLimitReached,
+            ApiError::AlreadyOnWaitlist,
+            ApiError::DatabaseBusy,
+            ApiError::from(ValidationErrors::new()),
+            ApiError::from(DatabaseError::NotFound),
+            ApiError::from(DatabaseError::RollbackTransaction),
+            ApiError::from(DatabaseError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                Box::new(String::from("duplicate key")),
+            )),
+            ApiError::from(DatabaseError::DatabaseError(
+                DatabaseErrorKind::ForeignKeyViolation,
+                Box::new(String::from("missing key")),
+            )),
+            ApiError::from(BlockingError::Canceled),
+        ];
+
+        for error in errors {
+            assert!(
+                error_codes().contains(&error.code()),
+                "{} missing",
+                error.code()
+            );
+        }
     }
 }
@@ -0,0 +1,458 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::ACCEPT_LANGUAGE;
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::task::{Context, Poll};
+
+use crate::utils::context::RequestContext;
+
+/// Language tags messages are translated to, the first being the default
+pub const LOCALES: &[&str] = &["en", "fr", "de", "es"];
+
+/// Messages of the error and validation codes, for every supported locale.
+/// Placeholders such as `{max}` are replaced by the params of field errors
+const CATALOGS: &[(&str, &[(&str, &str)])] = &[
+    (
+        "en",
+        &[
+            ("VALIDATION_ERROR", "A validation error occurred"),
+            ("INVALID_BETA_KEY", "The provided beta key is taken or invalid"),
+            ("BETA_KEY_REQUIRED", "A beta key is required to sign up"),
+            ("EMAIL_DOMAIN_NOT_ALLOWED", "Emails on this domain are not allowed to sign up"),
+            ("REGISTRATION_CLOSED", "Registration is currently closed"),
+            ("INVALID_LOGIN", "The provided email and password are invalid"),
+            ("UNAUTHORIZED", "Please login to continue"),
+            ("FORBIDDEN", "You do not have permission to perform this action"),
+            ("ACCOUNT_DISABLED", "The account has been disabled"),
+            ("NOT_FOUND", "The requested resource could not be found"),
+            ("KEY_ALREADY_REDEEMED", "The beta key has already been redeemed"),
+            ("EMAIL_TAKEN", "An account already exists for this email"),
+            ("SIGNUP_FAILED", "The account could not be created"),
+            ("INVITE_LIMIT_REACHED", "You have no invites left"),
+            ("ALREADY_ON_WAITLIST", "The email is already on the waitlist"),
+            ("CONFLICT", "A record with the same values already exists"),
+            ("DATABASE_BUSY", "The service is busy, please try again"),
+            ("PAYLOAD_TOO_LARGE", "The request body is too large"),
+            ("UNSUPPORTED_MEDIA_TYPE", "The request body must be sent as application/json"),
+            ("INVALID_EMAIL", "Must be a valid email address"),
+            ("INVALID_ROLE", "Must be one of {allowed}"),
+            ("INVALID_COUNT", "Must be between {min} and {max}"),
+            ("INVALID_LABEL", "Must be between {min} and {max} characters long"),
+            ("INVALID_MAX_REDEMPTIONS", "Must be between {min} and {max}"),
+            ("INVALID_SOURCE", "Must be between {min} and {max} characters long"),
+            ("INVALID_LOCALE", "Must be one of {allowed}"),
+            ("INVALID_CSV", "Line {line} does not contain a valid key"),
+            ("INVALID_QUERY", "The query string could not be parsed"),
+            ("INVALID_LIMIT", "Must be a whole number"),
+            ("INVALID_OFFSET", "Must be a whole number of at least 0"),
+            ("INVALID_CURSOR", "Must be a cursor returned with a previous page"),
+            ("INVALID_SORT", "Must be one of {allowed}, prefixed with - to sort descending"),
+            ("INVALID_FILTER", "Must be true or false"),
+            ("INVALID_PAGINATION", "Can't be combined with an offset"),
+            ("UNKNOWN_PARAMETER", "Is not a known parameter"),
+            ("MALFORMED_JSON", "The body is not valid JSON"),
+            ("MISSING_FIELD", "This field is required"),
+            ("UNKNOWN_FIELD", "This field is not allowed"),
+            ("INVALID_TYPE", "This field has the wrong type"),
+        ],
+    ),
+    (
+        "fr",
+        &[
+            ("VALIDATION_ERROR", "La requête contient des erreurs"),
+            ("INVALID_BETA_KEY", "La clé bêta fournie est déjà utilisée ou invalide"),
+            ("BETA_KEY_REQUIRED", "Une clé bêta est nécessaire pour s'inscrire"),
+            ("EMAIL_DOMAIN_NOT_ALLOWED", "Les adresses de ce domaine ne peuvent pas s'inscrire"),
+            ("REGISTRATION_CLOSED", "Les inscriptions sont actuellement fermées"),
+            ("INVALID_LOGIN", "L'adresse e-mail ou le mot de passe est incorrect"),
+            ("UNAUTHORIZED", "Veuillez vous connecter pour continuer"),
+            ("FORBIDDEN", "Vous n'avez pas la permission d'effectuer cette action"),
+            ("ACCOUNT_DISABLED", "Le compte a été désactivé"),
+            ("NOT_FOUND", "La ressource demandée est introuvable"),
+            ("KEY_ALREADY_REDEEMED", "La clé bêta a déjà été utilisée"),
+            ("EMAIL_TAKEN", "Un compte existe déjà pour cette adresse e-mail"),
+            ("SIGNUP_FAILED", "Le compte n'a pas pu être créé"),
+            ("INVITE_LIMIT_REACHED", "Vous n'avez plus d'invitations"),
+            ("ALREADY_ON_WAITLIST", "L'adresse e-mail est déjà sur la liste d'attente"),
+            ("CONFLICT", "Un enregistrement avec les mêmes valeurs existe déjà"),
+            ("DATABASE_BUSY", "Le service est occupé, veuillez réessayer"),
+            ("PAYLOAD_TOO_LARGE", "Le corps de la requête est trop volumineux"),
+            ("UNSUPPORTED_MEDIA_TYPE", "Le corps de la requête doit être envoyé en application/json"),
+            ("INVALID_EMAIL", "Doit être une adresse e-mail valide"),
+            ("INVALID_ROLE", "Doit être l'une des valeurs suivantes : {allowed}"),
+            ("INVALID_COUNT", "Doit être compris entre {min} et {max}"),
+            ("INVALID_LABEL", "Doit contenir entre {min} et {max} caractères"),
+            ("INVALID_MAX_REDEMPTIONS", "Doit être compris entre {min} et {max}"),
+            ("INVALID_SOURCE", "Doit contenir entre {min} et {max} caractères"),
+            ("INVALID_LOCALE", "Doit être l'une des valeurs suivantes : {allowed}"),
+            ("INVALID_CSV", "La ligne {line} ne contient pas de clé valide"),
+            ("INVALID_QUERY", "Les paramètres de la requête sont illisibles"),
+            ("INVALID_LIMIT", "Doit être un nombre entier"),
+            ("INVALID_OFFSET", "Doit être un nombre entier positif ou nul"),
+            ("INVALID_CURSOR", "Doit être un curseur renvoyé avec une page précédente"),
+            ("INVALID_SORT", "Doit être l'une des valeurs suivantes : {allowed}, précédée de - pour un tri décroissant"),
+            ("INVALID_FILTER", "Doit valoir true ou false"),
+            ("INVALID_PAGINATION", "Ne peut pas être combiné avec un offset"),
+            ("UNKNOWN_PARAMETER", "N'est pas un paramètre connu"),
+            ("MALFORMED_JSON", "Le corps n'est pas un JSON valide"),
+            ("MISSING_FIELD", "Ce champ est obligatoire"),
+            ("UNKNOWN_FIELD", "Ce champ n'est pas autorisé"),
+            ("INVALID_TYPE", "Ce champ n'a pas le bon type"),
+        ],
+    ),
+    (
+        "de",
+        &[
+            ("VALIDATION_ERROR", "Die Anfrage enthält Fehler"),
+            ("INVALID_BETA_KEY", "Der Beta-Schlüssel ist vergeben oder ungültig"),
+            ("BETA_KEY_REQUIRED", "Zur Registrierung wird ein Beta-Schlüssel benötigt"),
+            ("EMAIL_DOMAIN_NOT_ALLOWED", "Adressen dieser Domain können sich nicht registrieren"),
+            ("REGISTRATION_CLOSED", "Die Registrierung ist derzeit geschlossen"),
+            ("INVALID_LOGIN", "E-Mail-Adresse oder Passwort ist falsch"),
+            ("UNAUTHORIZED", "Bitte melde dich an, um fortzufahren"),
+            ("FORBIDDEN", "Du hast keine Berechtigung für diese Aktion"),
+            ("ACCOUNT_DISABLED", "Das Konto wurde deaktiviert"),
+            ("NOT_FOUND", "Die angeforderte Ressource wurde nicht gefunden"),
+            ("KEY_ALREADY_REDEEMED", "Der Beta-Schlüssel wurde bereits eingelöst"),
+            ("EMAIL_TAKEN", "Für diese E-Mail-Adresse existiert bereits ein Konto"),
+            ("SIGNUP_FAILED", "Das Konto konnte nicht erstellt werden"),
+            ("INVITE_LIMIT_REACHED", "Du hast keine Einladungen mehr"),
+            ("ALREADY_ON_WAITLIST", "Die E-Mail-Adresse steht bereits auf der Warteliste"),
+            ("CONFLICT", "Ein Eintrag mit denselben Werten existiert bereits"),
+            ("DATABASE_BUSY", "Der Dienst ist ausgelastet, bitte versuche es erneut"),
+            ("PAYLOAD_TOO_LARGE", "Der Inhalt der Anfrage ist zu groß"),
+            ("UNSUPPORTED_MEDIA_TYPE", "Der Inhalt der Anfrage muss als application/json gesendet werden"),
+            ("INVALID_EMAIL", "Muss eine gültige E-Mail-Adresse sein"),
+            ("INVALID_ROLE", "Muss einer der folgenden Werte sein: {allowed}"),
+            ("INVALID_COUNT", "Muss zwischen {min} und {max} liegen"),
+            ("INVALID_LABEL", "Muss zwischen {min} und {max} Zeichen lang sein"),
+            ("INVALID_MAX_REDEMPTIONS", "Muss zwischen {min} und {max} liegen"),
+            ("INVALID_SOURCE", "Muss zwischen {min} und {max} Zeichen lang sein"),
+            ("INVALID_LOCALE", "Muss einer der folgenden Werte sein: {allowed}"),
+            ("INVALID_CSV", "Zeile {line} enthält keinen gültigen Schlüssel"),
+            ("INVALID_QUERY", "Die Abfrageparameter konnten nicht gelesen werden"),
+            ("INVALID_LIMIT", "Muss eine ganze Zahl sein"),
+            ("INVALID_OFFSET", "Muss eine ganze Zahl von mindestens 0 sein"),
+            ("INVALID_CURSOR", "Muss ein Cursor einer vorherigen Seite sein"),
+            ("INVALID_SORT", "Muss einer der folgenden Werte sein: {allowed}, mit vorangestelltem - für absteigende Sortierung"),
+            ("INVALID_FILTER", "Muss true oder false sein"),
+            ("INVALID_PAGINATION", "Kann nicht mit einem Offset kombiniert werden"),
+            ("UNKNOWN_PARAMETER", "Ist kein bekannter Parameter"),
+            ("MALFORMED_JSON", "Der Inhalt ist kein gültiges JSON"),
+            ("MISSING_FIELD", "Dieses Feld ist erforderlich"),
+            ("UNKNOWN_FIELD", "Dieses Feld ist nicht erlaubt"),
+            ("INVALID_TYPE", "Dieses Feld hat den falschen Typ"),
+        ],
+    ),
+    (
+        "es",
+        &[
+            ("VALIDATION_ERROR", "La solicitud contiene errores"),
+            ("INVALID_BETA_KEY", "La clave beta ya está en uso o no es válida"),
+            ("BETA_KEY_REQUIRED", "Se necesita una clave beta para registrarse"),
+            ("EMAIL_DOMAIN_NOT_ALLOWED", "Las direcciones de este dominio no pueden registrarse"),
+            ("REGISTRATION_CLOSED", "El registro está cerrado en este momento"),
+            ("INVALID_LOGIN", "El correo electrónico o la contraseña no son correctos"),
+            ("UNAUTHORIZED", "Inicia sesión para continuar"),
+            ("FORBIDDEN", "No tienes permiso para realizar esta acción"),
+            ("ACCOUNT_DISABLED", "La cuenta ha sido desactivada"),
+            ("NOT_FOUND", "No se encontró el recurso solicitado"),
+            ("KEY_ALREADY_REDEEMED", "La clave beta ya ha sido canjeada"),
+            ("EMAIL_TAKEN", "Ya existe una cuenta con este correo electrónico"),
+            ("SIGNUP_FAILED", "No se pudo crear la cuenta"),
+            ("INVITE_LIMIT_REACHED", "No te quedan invitaciones"),
+            ("ALREADY_ON_WAITLIST", "El correo electrónico ya está en la lista de espera"),
+            ("CONFLICT", "Ya existe un registro con los mismos valores"),
+            ("DATABASE_BUSY", "El servicio está ocupado, inténtalo de nuevo"),
+            ("PAYLOAD_TOO_LARGE", "El cuerpo de la solicitud es demasiado grande"),
+            ("UNSUPPORTED_MEDIA_TYPE", "El cuerpo de la solicitud debe enviarse como application/json"),
+            ("INVALID_EMAIL", "Debe ser una dirección de correo válida"),
+            ("INVALID_ROLE", "Debe ser uno de los siguientes valores: {allowed}"),
+            ("INVALID_COUNT", "Debe estar entre {min} y {max}"),
+            ("INVALID_LABEL", "Debe tener entre {min} y {max} caracteres"),
+            ("INVALID_MAX_REDEMPTIONS", "Debe estar entre {min} y {max}"),
+            ("INVALID_SOURCE", "Debe tener entre {min} y {max} caracteres"),
+            ("INVALID_LOCALE", "Debe ser uno de los siguientes valores: {allowed}"),
+            ("INVALID_CSV", "La línea {line} no contiene una clave válida"),
+            ("INVALID_QUERY", "No se pudieron leer los parámetros de la consulta"),
+            ("INVALID_LIMIT", "Debe ser un número entero"),
+            ("INVALID_OFFSET", "Debe ser un número entero mayor o igual a 0"),
+            ("INVALID_CURSOR", "Debe ser un cursor devuelto con una página anterior"),
+            ("INVALID_SORT", "Debe ser uno de los siguientes valores: {allowed}, precedido de - para ordenar de forma descendente"),
+            ("INVALID_FILTER", "Debe ser true o false"),
+            ("INVALID_PAGINATION", "No se puede combinar con un offset"),
+            ("UNKNOWN_PARAMETER", "No es un parámetro conocido"),
+            ("MALFORMED_JSON", "El cuerpo no es un JSON válido"),
+            ("MISSING_FIELD", "Este campo es obligatorio"),
+            ("UNKNOWN_FIELD", "Este campo no está permitido"),
+            ("INVALID_TYPE", "Este campo tiene un tipo incorrecto"),
+        ],
+    ),
+];
+
+/// Language messages are written in, one of the supported `LOCALES`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Locale(&'static str);
+
+impl Locale {
+    /// Locale used when the client and user don't prefer a supported one
+    pub const DEFAULT: Locale = Locale("en");
+
+    /// Returns the supported locale of a language tag such as `fr-CA`,
+    /// matching its primary language
+    pub fn parse(tag: &str) -> Option<Locale> {
+        let language = tag.trim().split(['-', '_']).next()?;
+
+        LOCALES
+            .iter()
+            .find(|locale| locale.eq_ignore_ascii_case(language))
+            .map(|locale| Locale(locale))
+    }
+
+    /// Returns the supported locale the client prefers the most in the value
+    /// of an `Accept-Language` header
+    pub fn negotiate(accept_language: &str) -> Option<Locale> {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .next()
+                    .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+
+                Some((tag, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+
+        // the sort is stable, so tags of equal quality keep the client's order
+        ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        ranges.into_iter().find_map(|(tag, _)| Locale::parse(tag))
+    }
+
+    /// Returns the locale of the request being handled on this thread
+    pub fn current() -> Locale {
+        RequestContext::current().map_or(Locale::DEFAULT, |context| context.locale())
+    }
+
+    /// Prefers the locale of the user the request is authenticated as over
+    /// the one the client asked for
+    pub fn prefer(req: &ServiceRequest, tag: Option<&str>) {
+        let locale = match tag.and_then(Locale::parse) {
+            Some(locale) => locale,
+            None => return,
+        };
+
+        if let Some(context) = req.extensions().get::<RequestContext>() {
+            context.set_locale(locale);
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+
+    /// Returns the message of an error or validation code in this locale,
+    /// falling back to the default locale for untranslated codes
+    pub fn message(&self, code: &str) -> Option<&'static str> {
+        let find = |locale: &str| {
+            CATALOGS
+                .iter()
+                .find(|(tag, _)| *tag == locale)
+                .and_then(|(_, messages)| messages.iter().find(|(c, _)| *c == code))
+                .map(|(_, message)| *message)
+        };
+
+        find(self.0).or_else(|| find(Locale::DEFAULT.0))
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Replaces the placeholders of a message with the params of a field error,
+/// lists being joined with commas
+pub fn format(message: &str, params: &Map<String, Value>) -> String {
+    params
+        .iter()
+        .fold(message.to_string(), |message, (name, value)| {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                Value::Array(values) => values
+                    .iter()
+                    .map(|value| {
+                        value
+                            .as_str()
+                            .map_or_else(|| value.to_string(), String::from)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                other => other.to_string(),
+            };
+
+            message.replace(&format!("{{{}}}", name), &value)
+        })
+}
+
+/// Middleware negotiating the locale errors are written in from the
+/// `Accept-Language` header, which the locale of an authenticated user
+/// overrides
+#[derive(Clone, Default)]
+pub struct Localization;
+
+impl<S, B> Transform<S> for Localization
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = LocalizationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(LocalizationMiddleware { service })
+    }
+}
+
+/// Service created by the Localization middleware for every worker
+pub struct LocalizationMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for LocalizationMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let requested = req
+            .headers()
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Locale::negotiate)
+            .unwrap_or(Locale::DEFAULT);
+
+        let (context, created) = RequestContext::of(&req);
+        context.set_locale(requested);
+
+        let handled = context.run(|| self.service.call(req));
+
+        Box::pin(async move {
+            handled
+                .await
+                .map_err(|error| if created { context.error(error) } else { error })
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::utils::errors::{error_codes, ApiError, FieldError, InternalCode, ValidationCode};
+    use actix_web::dev::Service;
+    use actix_web::{test, web, App};
+
+    #[test]
+    fn it_negotiates_the_preferred_supported_locale() {
+        let negotiate = |header| Locale::negotiate(header).map(|l| l.as_str());
+
+        assert_eq!(negotiate("fr-CA,fr;q=0.9,en;q=0.8"), Some("fr"));
+        assert_eq!(negotiate("ja, de;q=0.5, en;q=0.7"), Some("en"));
+        assert_eq!(negotiate("es;q=0, DE"), Some("de"));
+        assert_eq!(negotiate("ja, *;q=0.1"), None);
+        assert_eq!(Locale::parse("pt_BR"), None);
+    }
+
+    #[test]
+    fn it_translates_every_code_in_every_locale() {
+        // internal errors keep the message describing what went wrong
+        let internal: Vec<_> = InternalCode::ALL.iter().map(|code| code.as_str()).collect();
+        let public = error_codes()
+            .into_iter()
+            .filter(|code| !internal.contains(code));
+        let fields = ValidationCode::ALL.iter().map(|code| code.as_str());
+
+        for code in public.chain(fields) {
+            for (locale, messages) in CATALOGS {
+                assert!(
+                    messages.iter().any(|(c, _)| *c == code),
+                    "{} missing from {}",
+                    code,
+                    locale
+                );
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_writes_errors_in_the_requested_or_preferred_locale() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Localization)
+                .route(
+                    "/signup",
+                    web::post().to(|| async {
+                        let error = FieldError::new("label", ValidationCode::InvalidLabel)
+                            .with_param("min", 1)
+                            .with_param("max", 100);
+                        Err::<String, _>(ApiError::invalid_fields(vec![error]))
+                    }),
+                )
+                .service(
+                    web::resource("/invites")
+                        .wrap_fn(|req, _| {
+                            Locale::prefer(&req, Some("de-AT"));
+                            futures::future::err(ApiError::Unauthorized.into())
+                        })
+                        .to(|| async { "" }),
+                ),
+        )
+        .await;
+
+        let signup = test::TestRequest::post()
+            .uri("/signup")
+            .header("accept-language", "fr-FR, en;q=0.5")
+            .to_request();
+        let response = test::call_service(&mut app, signup).await;
+        assert_eq!(response.headers().get("content-language").unwrap(), "fr");
+        let body: Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+        assert_eq!(body["code"], "VALIDATION_ERROR");
+        assert_eq!(body["detail"], "La requête contient des erreurs");
+        assert_eq!(body["errors"][0]["code"], "INVALID_LABEL");
+        assert_eq!(
+            body["errors"][0]["message"],
+            "Doit contenir entre 1 et 100 caractères"
+        );
+
+        // the locale of the user wins over the header
+        let invites = test::TestRequest::get()
+            .uri("/invites")
+            .header("accept-language", "es")
+            .to_request();
+        let error = app.call(invites).await.err().unwrap();
+        let response = error.as_response_error().error_response();
+        assert_eq!(response.headers().get("content-language").unwrap(), "de");
+    }
+}
@@ -0,0 +1,122 @@
+use actix_web::dev::Payload;
+use actix_web::{web, Error, FromRequest, HttpRequest};
+use futures::future::{FutureExt, LocalBoxFuture};
+use serde::de::DeserializeOwned;
+use serde_value::{DeserializerError, ValueDeserializer};
+
+use crate::utils::errors::{ApiError, FieldError, ValidationCode};
+
+/// Path serde_path_to_error reports for the body itself
+const ROOT_PATH: &str = ".";
+
+/// JSON body extractor naming the path of the field a body failed to
+/// deserialize at, such as `labels[1].name`. Reading the body is left to
+/// `web::Json`, so the limit and error handler of its JsonConfig still apply
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned + 'static> FromRequest for Json<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let body = web::Json::<serde_json::Value>::from_request(req, payload);
+
+        async move {
+            let web::Json(body) = body.await?;
+            Ok(Json(from_value(body)?))
+        }
+        .boxed_local()
+    }
+}
+
+/// Deserializes a parsed body, describing the field it failed at
+pub fn from_value<T: DeserializeOwned>(body: serde_json::Value) -> Result<T, ApiError> {
+    let value = serde_value::to_value(body).map_err(|_| {
+        ApiError::invalid_fields(vec![FieldError::new("body", ValidationCode::MalformedJson)])
+    })?;
+
+    serde_path_to_error::deserialize(ValueDeserializer::<DeserializerError>::new(value))
+        .map_err(|error| ApiError::invalid_fields(vec![field_error(&error)]))
+}
+
+/// Describes why the field at the path of the error couldn't be deserialized.
+/// Missing fields are reported at the path of the object lacking them
+fn field_error(error: &serde_path_to_error::Error<DeserializerError>) -> FieldError {
+    let path = error.path().to_string();
+    let field = |name: &str| match path.as_str() {
+        ROOT_PATH => name.to_string(),
+        path => format!("{}.{}", path, name),
+    };
+
+    match error.inner() {
+        DeserializerError::MissingField(name) => {
+            FieldError::new(&field(name), ValidationCode::MissingField)
+        }
+        DeserializerError::UnknownField(_, _) => {
+            FieldError::new(&path, ValidationCode::UnknownField)
+        }
+        _ if path == ROOT_PATH => FieldError::new("body", ValidationCode::InvalidType),
+        _ => FieldError::new(&path, ValidationCode::InvalidType),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(deny_unknown_fields)]
+    struct Label {
+        name: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[serde(deny_unknown_fields)]
+    struct Batch {
+        email: String,
+        labels: Vec<Label>,
+    }
+
+    #[test]
+    fn it_names_the_path_of_the_field_a_body_failed_at() {
+        let field = |json: &str| {
+            let body = serde_json::from_str(json).unwrap();
+            match from_value::<Batch>(body) {
+                Err(ApiError::ValidationError(_, _, mut fields)) => fields.remove(0),
+                other => panic!("expected validation error, got {:?}", other),
+            }
+        };
+
+        assert_eq!(
+            field(r#"{"labels": []}"#),
+            FieldError::new("email", ValidationCode::MissingField)
+        );
+        assert_eq!(
+            field(r#"{"email": "a@b.com", "labels": [{"name": "a"}, {}]}"#),
+            FieldError::new("labels[1].name", ValidationCode::MissingField)
+        );
+        assert_eq!(
+            field(r#"{"email": "a@b.com", "labels": [{"name": "a", "nope": 1}]}"#),
+            FieldError::new("labels[0].nope", ValidationCode::UnknownField)
+        );
+        assert_eq!(
+            field(r#"{"email": 5, "labels": []}"#),
+            FieldError::new("email", ValidationCode::InvalidType)
+        );
+        assert_eq!(
+            field(r#"{"email": "a@b.com", "labels": [{"name": false}]}"#),
+            FieldError::new("labels[0].name", ValidationCode::InvalidType)
+        );
+        assert_eq!(
+            field("[]"),
+            FieldError::new("body", ValidationCode::InvalidType)
+        );
+
+        let body = serde_json::json!({"email": "a@b.com", "labels": [{"name": "a"}]});
+        let batch = from_value::<Batch>(body).unwrap();
+        assert_eq!(batch.email, "a@b.com");
+        assert_eq!(batch.labels[0].name, "a");
+    }
+}
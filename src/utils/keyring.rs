@@ -0,0 +1,302 @@
+use auth_verifier::{Claims, Jwk, JwkSet, Verifier, VerifyError};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use openssl::rsa::Rsa;
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::db::Database;
+use crate::models::signing_key::SigningKey;
+use crate::settings::TokenSettings;
+use crate::store::Store;
+use crate::utils::errors::{ApiError, InternalCode};
+
+/// Keys loaded from the store. Tokens are verified by a verifier trusting
+/// the published keys, as resource servers verify them
+struct Keys {
+    signing: Option<(String, EncodingKey)>,
+    verifier: Verifier,
+    published: JwkSet,
+    loaded_at: Option<Instant>,
+}
+
+impl Keys {
+    /// Returns the keys verifying the published ones and the secret
+    fn new(secret: &str, published: JwkSet) -> Self {
+        Keys {
+            signing: None,
+            verifier: Verifier::with_keys(published.clone()).with_secret(secret),
+            published,
+            loaded_at: None,
+        }
+    }
+}
+
+/// Summary of the loaded keys, reported by the readiness check
+#[derive(Serialize, Debug)]
+pub struct KeyringStatus {
+    /// Kid of the key signing tokens, none while signing with the secret
+    pub signing_kid: Option<String>,
+    pub verifying_keys: usize,
+    /// Seconds since the keys were last loaded, none until they are
+    pub loaded_secs_ago: Option<u64>,
+}
+
+impl KeyringStatus {
+    /// Returns true if the keys were loaded no longer than the provided
+    /// number of seconds ago
+    pub fn loaded_within(&self, secs: u64) -> bool {
+        self.loaded_secs_ago.is_some_and(|ago| ago <= secs)
+    }
+}
+
+/// Keys tokens are signed and verified with. Tokens are signed with the
+/// newest signing key and verified with the key named by their kid, falling
+/// back to the configured secret until a signing key has been generated
+#[derive(Clone)]
+pub struct Keyring {
+    secret: Arc<String>,
+    expiry_secs: u64,
+    keys: Arc<RwLock<Keys>>,
+}
+
+impl Keyring {
+    /// Creates a keyring signing with the configured secret until keys are loaded
+    pub fn new(settings: &TokenSettings) -> Self {
+        Keyring {
+            secret: Arc::new(settings.secret.clone()),
+            expiry_secs: settings.expiry_secs,
+            keys: Arc::new(RwLock::new(Keys::new(&settings.secret, JwkSet::default()))),
+        }
+    }
+
+    /// Replaces the loaded keys, signing with the newest active one
+    pub fn load(&self, signing_keys: &[SigningKey]) -> Result<(), ApiError> {
+        let mut signing = None;
+        let mut published = JwkSet::default();
+
+        for key in signing_keys {
+            published.keys.push(public_jwk(key)?);
+
+            let newest = signing.is_none() && key.retired_at.is_none();
+            if newest {
+                let encoding = EncodingKey::from_rsa_pem(key.private_key.as_bytes())
+                    .map_err(|error| invalid_key(key, error))?;
+                signing = Some((key.kid.clone(), encoding));
+            }
+        }
+
+        let mut keys = Keys::new(&self.secret, published);
+        keys.signing = signing;
+        keys.loaded_at = Some(Instant::now());
+        *self.keys.write().map_err(|_| poisoned())? = keys;
+
+        Ok(())
+    }
+
+    /// Loads the active keys, and the retired keys that may still have
+    /// signed unexpired tokens, from the store
+    pub fn refresh(&self, store: &dyn Store) -> Result<(), ApiError> {
+        let expiry = chrono::Duration::seconds(self.expiry_secs as i64);
+        let retired_after = chrono::Utc::now().naive_utc() - expiry;
+
+        self.load(&store.signing_keys(retired_after)?)
+    }
+
+    /// Returns the public keys of the loaded signing keys, including the
+    /// retired ones that may still have signed unexpired tokens
+    pub fn jwks(&self) -> Result<JwkSet, ApiError> {
+        let keys = self.keys.read().map_err(|_| poisoned())?;
+        Ok(keys.published.clone())
+    }
+
+    /// Describes the loaded keys
+    pub fn status(&self) -> Result<KeyringStatus, ApiError> {
+        let keys = self.keys.read().map_err(|_| poisoned())?;
+
+        Ok(KeyringStatus {
+            signing_kid: keys.signing.as_ref().map(|(kid, _)| kid.clone()),
+            verifying_keys: keys.published.keys.len(),
+            loaded_secs_ago: keys
+                .loaded_at
+                .map(|loaded_at| loaded_at.elapsed().as_secs()),
+        })
+    }
+
+    /// Signs the claims with the current signing key
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, ApiError> {
+        let keys = self.keys.read().map_err(|_| poisoned())?;
+
+        let encoded = match &keys.signing {
+            Some((kid, key)) => {
+                let mut header = Header::new(Algorithm::RS256);
+                header.kid = Some(kid.clone());
+                encode(&header, claims, key)
+            }
+            None => encode(
+                &Header::default(),
+                claims,
+                &EncodingKey::from_secret(self.secret.as_bytes()),
+            ),
+        };
+
+        encoded
+            .map_err(|error| ApiError::InternalServerError(InternalCode::Token, error.to_string()))
+    }
+
+    /// Verifies the token with the key named by its kid and decodes its claims
+    pub fn decode(&self, token: &str) -> Result<Claims, ApiError> {
+        let keys = self.keys.read().map_err(|_| poisoned())?;
+
+        keys.verifier
+            .verify_cached(token)
+            .map_err(|error| match error {
+                VerifyError::InvalidToken => ApiError::Unauthorized,
+                error => ApiError::InternalServerError(InternalCode::SigningKey, error.to_string()),
+            })
+    }
+}
+
+/// Reloads the keyring from the database at the provided interval, so keys
+/// rotated by the admin cli are picked up without a restart
+pub async fn refresh_periodically(keyring: Keyring, db: Database, every: Duration) {
+    let mut interval = actix_rt::time::interval(every);
+
+    loop {
+        interval.tick().await;
+
+        let keyring = keyring.clone();
+        if let Err(error) = db.run(move |store| keyring.refresh(store)).await {
+            warn!("failed to refresh signing keys: {}", error);
+        }
+    }
+}
+
+/// Converts the public key of a signing key to a JWK
+fn public_jwk(key: &SigningKey) -> Result<Jwk, ApiError> {
+    let rsa = Rsa::public_key_from_pem(key.public_key.as_bytes())
+        .map_err(|error| invalid_key(key, error))?;
+    let encode = |bytes: Vec<u8>| base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD);
+
+    Ok(Jwk::rsa(
+        &key.kid,
+        encode(rsa.n().to_vec()),
+        encode(rsa.e().to_vec()),
+    ))
+}
+
+/// Error returned when a stored key can't be parsed
+fn invalid_key<E: std::fmt::Display>(key: &SigningKey, error: E) -> ApiError {
+    ApiError::InternalServerError(
+        InternalCode::SigningKey,
+        format!("Signing key {} is invalid: {}", key.kid, error),
+    )
+}
+
+/// Error returned when a thread panicked while replacing the keys
+fn poisoned() -> ApiError {
+    ApiError::InternalServerError(
+        InternalCode::SigningKey,
+        String::from("The signing keys are unavailable"),
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::SigningKeyStore;
+    use crate::utils::token::tests::create_token;
+    use jsonwebtoken::decode_header;
+
+    fn keyring() -> Keyring {
+        Keyring::new(&TokenSettings {
+            secret: String::from("secret"),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn it_tells_whether_the_keys_were_loaded_recently() {
+        let status = |loaded_secs_ago| KeyringStatus {
+            signing_kid: None,
+            verifying_keys: 0,
+            loaded_secs_ago,
+        };
+
+        assert!(status(Some(180)).loaded_within(180));
+        assert!(!status(Some(181)).loaded_within(180));
+        assert!(!status(None).loaded_within(180));
+    }
+
+    #[test]
+    fn it_signs_with_the_newest_key_and_verifies_retired_ones() {
+        let store = MemoryStore::new();
+        let keyring = keyring();
+        let token = create_token();
+
+        // without signing keys, tokens are signed with the secret
+        let with_secret = keyring.encode(&token).unwrap();
+        assert!(decode_header(&with_secret).unwrap().kid.is_none());
+
+        let first = SigningKey::generate().unwrap();
+        store.rotate_signing_key(&first).unwrap();
+        keyring.refresh(&store).unwrap();
+
+        let with_first = keyring.encode(&token).unwrap();
+        let header = decode_header(&with_first).unwrap();
+        assert_eq!(header.alg, Algorithm::RS256);
+        assert_eq!(header.kid, Some(first.kid.clone()));
+
+        store
+            .rotate_signing_key(&SigningKey::generate().unwrap())
+            .unwrap();
+        keyring.refresh(&store).unwrap();
+
+        let with_second = keyring.encode(&token).unwrap();
+        assert_ne!(decode_header(&with_second).unwrap().kid, Some(first.kid));
+
+        for encoded in &[with_secret, with_first, with_second] {
+            let decoded = keyring.decode(encoded).unwrap();
+            assert_eq!(decoded.email, token.email);
+        }
+    }
+
+    #[test]
+    fn it_refuses_tokens_signed_by_unknown_keys() {
+        let store = MemoryStore::new();
+        let issuer = keyring();
+        store
+            .rotate_signing_key(&SigningKey::generate().unwrap())
+            .unwrap();
+        issuer.refresh(&store).unwrap();
+
+        let encoded = issuer.encode(&create_token()).unwrap();
+
+        match keyring().decode(&encoded) {
+            Err(ApiError::Unauthorized) => {}
+            other => panic!("expected unauthorized error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_publishes_keys_resource_servers_verify_tokens_with() {
+        let store = MemoryStore::new();
+        let keyring = keyring();
+        assert!(keyring.jwks().unwrap().keys.is_empty());
+
+        store
+            .rotate_signing_key(&SigningKey::generate().unwrap())
+            .unwrap();
+        keyring.refresh(&store).unwrap();
+
+        let mut token = create_token();
+        token.scope = Some(String::from("reports:read"));
+        let encoded = keyring.encode(&token).unwrap();
+
+        let verifier = auth_verifier::Verifier::with_keys(keyring.jwks().unwrap());
+        let claims = verifier.verify(&encoded).await.unwrap();
+        assert_eq!(claims.email, token.email);
+        assert!(claims.has_scope("reports:read"));
+    }
+}
@@ -0,0 +1,50 @@
+use serde::Deserialize;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+use crate::settings::{LogSettings, TelemetrySettings};
+use crate::utils::telemetry::Telemetry;
+
+/// Format logs are written in
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// One JSON object per line, with the fields of the spans it happened in,
+    /// such as the request id
+    Json,
+    /// Human readable lines, for development
+    Text,
+}
+
+/// Writes the events of the service, and of the `log` macros of its
+/// dependencies, to stdout in the configured format, and exports its spans
+/// when a collector is configured. Spans are exported until the returned
+/// handle is dropped
+pub fn init(settings: &LogSettings, telemetry: &TelemetrySettings) -> Result<Telemetry, String> {
+    let filter = EnvFilter::try_new(&settings.filter).map_err(|error| error.to_string())?;
+    let (telemetry, tracer) = Telemetry::start(telemetry)?;
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
+
+    let initialized = match settings.format {
+        LogFormat::Json => registry
+            .with(
+                fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_current_span(false)
+                    .with_span_list(true)
+                    .with_writer(std::io::stdout),
+            )
+            .try_init(),
+        LogFormat::Text => registry
+            .with(fmt::layer().with_writer(std::io::stdout))
+            .try_init(),
+    };
+
+    initialized.map_err(|error| error.to_string())?;
+
+    Ok(telemetry)
+}
@@ -0,0 +1,151 @@
+use lettre::message::Mailbox;
+use lettre::{Message, SmtpTransport, Transport};
+use std::sync::Arc;
+
+use crate::settings::MailSettings;
+use crate::utils::errors::{ApiError, InternalCode};
+
+/// Mailer shared between the workers of the application
+pub type SharedMailer = Arc<dyn Mailer>;
+
+/// Representation of an email to be delivered
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers emails sent by the application
+pub trait Mailer: Send + Sync {
+    /// Delivers the provided email
+    fn send(&self, email: &Email) -> Result<(), ApiError>;
+}
+
+/// Mailer that logs emails instead of delivering them, used while no SMTP
+/// server is configured. Bodies are left out as they hold usable beta keys
+#[derive(Debug, Default)]
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, email: &Email) -> Result<(), ApiError> {
+        info!(
+            "email to {} with subject {:?} not delivered, no SMTP server is configured",
+            email.to, email.subject
+        );
+
+        Ok(())
+    }
+}
+
+/// Mailer delivering emails through an SMTP server
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    /// Creates a mailer for the SMTP server of the settings, which is only
+    /// connected to once an email is sent
+    pub fn new(settings: &MailSettings) -> Result<Self, String> {
+        let transport = SmtpTransport::from_url(&settings.smtp_url)
+            .map_err(|error| format!("mail.smtp_url is invalid: {}", error))?
+            .build();
+        let from = settings
+            .from
+            .parse()
+            .map_err(|error| format!("mail.from is invalid: {}", error))?;
+
+        Ok(SmtpMailer { transport, from })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), ApiError> {
+        let to: Mailbox = email.to.parse().map_err(mail_error)?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject.as_str())
+            .body(email.body.clone())
+            .map_err(mail_error)?;
+
+        self.transport.send(&message).map_err(mail_error)?;
+
+        Ok(())
+    }
+}
+
+/// Returns the mailer configured by the settings, which logs emails unless
+/// an SMTP server is set
+pub fn from_settings(settings: &MailSettings) -> Result<SharedMailer, String> {
+    if settings.smtp_url.is_empty() {
+        return Ok(Arc::new(LogMailer));
+    }
+
+    Ok(Arc::new(SmtpMailer::new(settings)?))
+}
+
+/// Converts a failure to build or deliver an email to an ApiError
+fn mail_error<E: std::fmt::Display>(error: E) -> ApiError {
+    ApiError::InternalServerError(
+        InternalCode::Mail,
+        format!("Could not deliver the email: {}", error),
+    )
+}
+
+/// Builds the email inviting someone to sign up with the provided key
+pub fn invitation(to: &str, inviter: &str, key: &uuid::Uuid) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: String::from("You have been invited"),
+        body: format!(
+            "{} has invited you to sign up. Use the following beta key when signing up: {}",
+            inviter, key
+        ),
+    }
+}
+
+/// Builds the email handing a beta key to someone approved from the waitlist
+pub fn waitlist_approval(to: &str, key: &uuid::Uuid) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: String::from("Your beta key is ready"),
+        body: format!(
+            "You have been approved from the waitlist. Use the following beta key when signing up: {}",
+            key
+        ),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn settings(smtp_url: &str, from: &str) -> MailSettings {
+        MailSettings {
+            smtp_url: smtp_url.to_string(),
+            from: from.to_string(),
+        }
+    }
+
+    #[test]
+    fn it_delivers_through_smtp_once_configured() {
+        assert!(from_settings(&settings("", "not an address")).is_ok());
+        assert!(from_settings(&settings("smtp://localhost:1", "auth@example.com")).is_ok());
+        assert!(SmtpMailer::new(&settings("http://localhost", "auth@example.com")).is_err());
+        assert!(SmtpMailer::new(&settings("smtp://localhost", "not an address")).is_err());
+    }
+
+    #[test]
+    fn it_reports_undeliverable_emails() {
+        let mailer = SmtpMailer::new(&settings("smtp://127.0.0.1:1", "auth@example.com")).unwrap();
+
+        let email = waitlist_approval("someone@example.com", &uuid::Uuid::new_v4());
+
+        match mailer.send(&email) {
+            Err(ApiError::InternalServerError(code, _)) => assert_eq!(code, InternalCode::Mail),
+            other => panic!("expected mail error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use actix_web::dev::{Path, ResourceMap, Service, ServiceRequest, ServiceResponse, Transform, Url};
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use crate::store::PoolState;
+use crate::utils::errors::{ApiError, InternalCode};
+
+/// Route label of requests that didn't match any route, so scanners can't
+/// create a series per path
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Route label of requests rejected by middleware before their route was
+/// recorded, such as requests without credentials
+const UNKNOWN_ROUTE: &str = "unknown";
+
+/// Metrics of the service, rendered at /metrics in the Prometheus text format.
+/// Wrap the app with it to record the count and latency of requests per route
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    errors: IntCounterVec,
+    logins: IntCounterVec,
+    signups: IntCounter,
+    token_validations: IntCounterVec,
+    pool_connections: IntGauge,
+    pool_idle_connections: IntGauge,
+    pool_max_connections: IntGauge,
+}
+
+impl Metrics {
+    /// Creates the metrics in a registry of their own
+    pub fn new() -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "Requests handled, by route and status",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle requests, by route",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new("auth_errors_total", "Errors returned, by error code"),
+            &["code"],
+        )
+        .unwrap();
+        let logins = IntCounterVec::new(
+            Opts::new("auth_logins_total", "Login attempts, by outcome"),
+            &["outcome"],
+        )
+        .unwrap();
+        let signups = IntCounter::new("auth_signups_total", "Users who signed up").unwrap();
+        let token_validations = IntCounterVec::new(
+            Opts::new(
+                "auth_token_validations_total",
+                "Bearer tokens checked by the authentication middleware, by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let pool_connections = IntGauge::new(
+            "auth_db_pool_connections",
+            "Connections opened by the database pool",
+        )
+        .unwrap();
+        let pool_idle_connections = IntGauge::new(
+            "auth_db_pool_idle_connections",
+            "Connections of the database pool waiting to be used",
+        )
+        .unwrap();
+        let pool_max_connections = IntGauge::new(
+            "auth_db_pool_max_connections",
+            "Connections the database pool may open",
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry
+            .register(Box::new(request_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(logins.clone())).unwrap();
+        registry.register(Box::new(signups.clone())).unwrap();
+        registry
+            .register(Box::new(token_validations.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_idle_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_max_connections.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            requests,
+            request_duration,
+            errors,
+            logins,
+            signups,
+            token_validations,
+            pool_connections,
+            pool_idle_connections,
+            pool_max_connections,
+        }
+    }
+
+    /// Counts a login attempt as a success, a failure to authenticate or an error
+    pub fn record_login(&self, result: &Result<Option<impl Sized>, ApiError>) {
+        let outcome = match result {
+            Ok(Some(_)) => "success",
+            Ok(None) | Err(ApiError::AccountDisabled) => "failure",
+            Err(_) => "error",
+        };
+        self.logins.with_label_values(&[outcome]).inc();
+    }
+
+    /// Counts a user who signed up
+    pub fn record_signup(&self) {
+        self.signups.inc();
+    }
+
+    /// Counts a bearer token accepted or rejected by the authentication middleware
+    pub fn record_token_validation(&self, accepted: bool) {
+        let outcome = if accepted { "accepted" } else { "rejected" };
+        self.token_validations.with_label_values(&[outcome]).inc();
+    }
+
+    /// Renders every metric, along with the current state of the database pool
+    pub fn render(&self, pool: Option<PoolState>) -> Result<String, ApiError> {
+        if let Some(pool) = pool {
+            self.pool_connections.set(i64::from(pool.connections));
+            self.pool_idle_connections
+                .set(i64::from(pool.idle_connections));
+            self.pool_max_connections
+                .set(i64::from(pool.max_connections));
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|error| {
+                ApiError::InternalServerError(InternalCode::Metrics, error.to_string())
+            })?;
+
+        String::from_utf8(buffer).map_err(|error| {
+            ApiError::InternalServerError(InternalCode::Metrics, error.to_string())
+        })
+    }
+
+    /// Records a handled request
+    fn observe(&self, method: &str, route: &str, status: StatusCode, started: Instant) {
+        self.requests
+            .with_label_values(&[method, route, status.as_str()])
+            .inc();
+        self.request_duration
+            .with_label_values(&[method, route])
+            .observe(started.elapsed().as_secs_f64());
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+/// Route a request was matched to, recorded by middleware inside the app
+/// for requests whose error never becomes a response within it
+#[derive(Clone, Default)]
+struct MatchedRoute(Rc<RefCell<Option<String>>>);
+
+/// Records the route of a request about to be rejected by the authentication
+/// middleware. Middleware wrapping a scope runs before the rest of the path is
+/// matched, so such requests are counted against the scope, as in `/admin/*`
+pub fn record_route(req: &ServiceRequest) {
+    let matched = match req.extensions().get::<MatchedRoute>() {
+        Some(matched) => matched.clone(),
+        None => return,
+    };
+
+    let path = req.path();
+    let route = match path.strip_suffix(req.match_info().path()) {
+        Some("") => route_of(path, req.match_info(), req.resource_map()),
+        Some(scope) => format!("{}/*", scope),
+        None => UNKNOWN_ROUTE.to_string(),
+    };
+
+    *matched.0.borrow_mut() = Some(route);
+}
+
+/// Returns the pattern of the route the path matched, such as
+/// `/admin/users/{id}`, rebuilt from the path and its matched segments
+fn route_of(path: &str, params: &Path<Url>, routes: &ResourceMap) -> String {
+    if !routes.has_resource(path) {
+        return UNMATCHED_ROUTE.to_string();
+    }
+
+    let mut params = params.iter().peekable();
+    path.split('/')
+        .map(|segment| match params.peek() {
+            Some((name, value)) if *value == segment => {
+                let pattern = format!("{{{}}}", name);
+                params.next();
+                pattern
+            }
+            _ => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl<S, B> Transform<S> for Metrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddleware {
+            service,
+            metrics: self.clone(),
+        })
+    }
+}
+
+/// Service created by the Metrics middleware for every worker
+pub struct MetricsMiddleware<S> {
+    service: S,
+    metrics: Metrics,
+}
+
+impl<S, B> Service for MetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        let metrics = self.metrics.clone();
+        let matched = MatchedRoute::default();
+        req.extensions_mut().insert(matched.clone());
+        let response = self.service.call(req);
+
+        Box::pin(async move {
+            let response = response.await;
+
+            // errors of inner middleware, such as rejected tokens, only become
+            // responses once they leave the app, so their status is worked out here
+            let (route, status, error) = match &response {
+                Ok(response) => {
+                    let req = response.request();
+                    (
+                        route_of(req.path(), req.match_info(), req.resource_map()),
+                        response.status(),
+                        response.response().error().and_then(api_error_code),
+                    )
+                }
+                Err(error) => (
+                    matched
+                        .0
+                        .borrow_mut()
+                        .take()
+                        .unwrap_or_else(|| UNKNOWN_ROUTE.to_string()),
+                    error.as_response_error().error_response().status(),
+                    api_error_code(error),
+                ),
+            };
+
+            metrics.observe(&method, &route, status, started);
+            if let Some(code) = error {
+                metrics.errors.with_label_values(&[&code]).inc();
+            }
+
+            response
+        })
+    }
+}
+
+/// Returns the code of the error when it is an ApiError
+fn api_error_code(error: &Error) -> Option<String> {
+    error
+        .as_error::<ApiError>()
+        .map(|error| error.code().to_string())
+}
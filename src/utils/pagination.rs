@@ -0,0 +1,634 @@
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest, HttpResponse};
+use futures::future::{ready, Ready};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::str::FromStr;
+use utoipa::openapi::path::{Parameter, ParameterBuilder, ParameterIn};
+use utoipa::openapi::{ObjectBuilder, Required, SchemaType};
+use utoipa::IntoParams;
+
+use crate::utils::errors::{ApiError, FieldError, ValidationCode};
+
+/// Query parameters reserved for pagination and sorting
+const RESERVED_PARAMS: &[&str] = &["limit", "offset", "cursor", "sort"];
+
+/// Direction a listing is sorted in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Describes how a model can be listed by a list endpoint
+pub trait Listable {
+    /// Fields the listing can be sorted by, the first being the default
+    const SORT_FIELDS: &'static [&'static str];
+    /// Query parameters the listing can be filtered by
+    const FILTERS: &'static [&'static str];
+    const DEFAULT_LIMIT: i64 = 25;
+    const MAX_LIMIT: i64 = 100;
+
+    /// Returns the keyset cursor pointing at this item for the provided sort field
+    fn cursor(&self, sort: &str) -> Cursor;
+}
+
+/// Opaque keyset cursor made up of the sort column value and the row id, along
+/// with the sort it was taken from so it can't be used to page another one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cursor {
+    #[serde(rename = "v")]
+    pub value: String,
+    pub id: String,
+    #[serde(rename = "s")]
+    pub sort: String,
+    #[serde(rename = "o")]
+    pub order: SortOrder,
+}
+
+impl Cursor {
+    /// Creates a cursor from the sort column value and the row id of an
+    /// ascending listing by id, see `Cursor::sorted` for other listings
+    pub fn new<V: ToString, I: ToString>(value: V, id: I) -> Self {
+        Cursor {
+            value: value.to_string(),
+            id: id.to_string(),
+            sort: "id".to_string(),
+            order: SortOrder::Asc,
+        }
+    }
+
+    /// Records the sort field and direction of the listing the cursor comes from
+    pub fn sorted(mut self, sort: &str, order: SortOrder) -> Self {
+        self.sort = sort.to_string();
+        self.order = order;
+        self
+    }
+
+    /// Encodes the cursor to a url safe string
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor is always serializable");
+        base64::encode_config(&json, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Decodes a cursor previously produced by `Cursor::encode`
+    pub fn decode(encoded: &str) -> Result<Self, ApiError> {
+        base64::decode_config(encoded, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| invalid("cursor", ValidationCode::InvalidCursor))
+    }
+
+    /// Parses the sort column value stored in the cursor
+    pub fn value<V: FromStr>(&self) -> Result<V, ApiError> {
+        self.value
+            .parse()
+            .map_err(|_| invalid("cursor", ValidationCode::InvalidCursor))
+    }
+
+    /// Parses the row id stored in the cursor
+    pub fn id<I: FromStr>(&self) -> Result<I, ApiError> {
+        self.id
+            .parse()
+            .map_err(|_| invalid("cursor", ValidationCode::InvalidCursor))
+    }
+}
+
+/// Parsed pagination, sorting and filtering parameters of a list request
+#[derive(Debug, Clone)]
+pub struct ListParams {
+    pub limit: i64,
+    pub offset: i64,
+    pub cursor: Option<Cursor>,
+    pub sort: String,
+    pub order: SortOrder,
+    filters: HashMap<String, String>,
+}
+
+impl ListParams {
+    /// Parses the query string of a list request for the provided model
+    pub fn parse<T: Listable>(query: &str) -> Result<Self, ApiError> {
+        let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query)
+            .map_err(|_| invalid("query", ValidationCode::InvalidQuery))?;
+
+        let mut params = ListParams {
+            limit: T::DEFAULT_LIMIT,
+            offset: 0,
+            cursor: None,
+            sort: T::SORT_FIELDS[0].to_string(),
+            order: SortOrder::Asc,
+            filters: HashMap::new(),
+        };
+
+        let mut has_offset = false;
+
+        for (key, value) in pairs {
+            match key.as_str() {
+                "limit" => {
+                    let limit = value
+                        .parse::<i64>()
+                        .map_err(|_| invalid("limit", ValidationCode::InvalidLimit))?;
+                    params.limit = limit.clamp(1, T::MAX_LIMIT);
+                }
+                "offset" => {
+                    let offset = value
+                        .parse::<i64>()
+                        .map_err(|_| invalid("offset", ValidationCode::InvalidOffset))?;
+                    if offset < 0 {
+                        return Err(invalid("offset", ValidationCode::InvalidOffset));
+                    }
+                    params.offset = offset;
+                    has_offset = true;
+                }
+                "cursor" => params.cursor = Some(Cursor::decode(&value)?),
+                "sort" => {
+                    let (order, field) = match value.strip_prefix('-') {
+                        Some(field) => (SortOrder::Desc, field),
+                        None => (SortOrder::Asc, value.as_str()),
+                    };
+
+                    if !T::SORT_FIELDS.contains(&field) {
+                        return Err(ApiError::invalid_fields(vec![FieldError::new(
+                            "sort",
+                            ValidationCode::InvalidSort,
+                        )
+                        .with_param("allowed", T::SORT_FIELDS)]));
+                    }
+
+                    params.sort = field.to_string();
+                    params.order = order;
+                }
+                _ if T::FILTERS.contains(&key.as_str()) => {
+                    params.filters.insert(key, value);
+                }
+                _ => return Err(invalid(&key, ValidationCode::UnknownParameter)),
+            }
+        }
+
+        // keyset and offset pagination can't be mixed
+        if has_offset && params.cursor.is_some() {
+            return Err(invalid("cursor", ValidationCode::InvalidPagination));
+        }
+
+        // a cursor only points into the listing sorted the way it was taken from
+        if let Some(cursor) = &params.cursor {
+            if cursor.sort != params.sort || cursor.order != params.order {
+                return Err(invalid("cursor", ValidationCode::InvalidCursor));
+            }
+        }
+
+        Ok(params)
+    }
+
+    /// Returns the raw value of a filter
+    pub fn filter(&self, name: &str) -> Option<&str> {
+        self.filters.get(name).map(String::as_str)
+    }
+
+    /// Returns the value of a boolean filter
+    pub fn filter_bool(&self, name: &str) -> Result<Option<bool>, ApiError> {
+        match self.filter(name) {
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(_) => Err(invalid(name, ValidationCode::InvalidFilter)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a LIKE pattern matching values starting with the value of a filter
+    pub fn filter_prefix(&self, name: &str) -> Option<String> {
+        self.filter(name).map(like_prefix)
+    }
+
+    /// Returns the number of rows to fetch, one more than the limit so the
+    /// presence of a next page can be detected
+    pub fn fetch_limit(&self) -> i64 {
+        self.limit + 1
+    }
+
+    /// Returns the offset to fetch rows from, keyset pagination always starting
+    /// right after the cursor
+    pub fn fetch_offset(&self) -> i64 {
+        if self.cursor.is_some() {
+            0
+        } else {
+            self.offset
+        }
+    }
+}
+
+/// Extractor parsing the list parameters of a request for the provided model
+pub struct ListQuery<T> {
+    params: ListParams,
+    model: PhantomData<T>,
+}
+
+impl<T> ListQuery<T> {
+    /// Unwraps the parsed list parameters
+    pub fn into_inner(self) -> ListParams {
+        self.params
+    }
+}
+
+impl<T> Deref for ListQuery<T> {
+    type Target = ListParams;
+
+    fn deref(&self) -> &ListParams {
+        &self.params
+    }
+}
+
+impl<T: Listable> FromRequest for ListQuery<T> {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, ApiError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            ListParams::parse::<T>(req.query_string()).map(|params| ListQuery {
+                params,
+                model: PhantomData,
+            }),
+        )
+    }
+}
+
+/// Documents the pagination, sorting and filtering parameters of the listing
+impl<T: Listable> IntoParams for ListQuery<T> {
+    fn into_params(_: impl Fn() -> Option<ParameterIn>) -> Vec<Parameter> {
+        let parameter = |name: &str, description: String, schema: ObjectBuilder| {
+            ParameterBuilder::new()
+                .name(name)
+                .parameter_in(ParameterIn::Query)
+                .required(Required::False)
+                .description(Some(description))
+                .schema(Some(schema))
+                .build()
+        };
+        let integer = || ObjectBuilder::new().schema_type(SchemaType::Integer);
+        let string = || ObjectBuilder::new().schema_type(SchemaType::String);
+        let sorts = T::SORT_FIELDS
+            .iter()
+            .flat_map(|field| vec![field.to_string(), format!("-{}", field)]);
+
+        let mut params = vec![
+            parameter(
+                "limit",
+                format!("Number of items per page, at most {}", T::MAX_LIMIT),
+                integer()
+                    .minimum(Some(1.0))
+                    .maximum(Some(T::MAX_LIMIT as f64))
+                    .default(Some(T::DEFAULT_LIMIT.into())),
+            ),
+            parameter(
+                "offset",
+                String::from("Number of items to skip, can't be combined with a cursor"),
+                integer().minimum(Some(0.0)),
+            ),
+            parameter(
+                "cursor",
+                String::from("X-Next-Cursor header of the previous page"),
+                string(),
+            ),
+            parameter(
+                "sort",
+                String::from("Field to sort by, in descending order when prefixed with -"),
+                string()
+                    .enum_values(Some(sorts))
+                    .default(Some(T::SORT_FIELDS[0].into())),
+            ),
+        ];
+
+        params.extend(
+            T::FILTERS
+                .iter()
+                .map(|filter| parameter(filter, format!("Filters by {}", filter), string())),
+        );
+
+        params
+    }
+}
+
+/// A single page of a listing along with what is needed to link to its neighbours
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next_cursor: Option<Cursor>,
+    params: ListParams,
+}
+
+impl<T: Listable + Serialize> Page<T> {
+    /// Builds a page from rows fetched with `ListParams::fetch_limit`
+    pub fn from_rows(mut rows: Vec<T>, total: i64, params: &ListParams) -> Self {
+        let has_next = rows.len() as i64 > params.limit;
+        rows.truncate(params.limit as usize);
+
+        let next_cursor = if has_next {
+            rows.last()
+                .map(|item| item.cursor(&params.sort).sorted(&params.sort, params.order))
+        } else {
+            None
+        };
+
+        Page {
+            items: rows,
+            total,
+            next_cursor,
+            params: params.clone(),
+        }
+    }
+
+    /// Responds with the items as a JSON array, exposing the total count and
+    /// neighbouring pages through the `X-Total-Count`, `X-Next-Cursor` and `Link` headers
+    pub fn respond(&self, req: &HttpRequest) -> HttpResponse {
+        let mut response = HttpResponse::Ok();
+
+        response.header("X-Total-Count", self.total.to_string());
+
+        if let Some(cursor) = &self.next_cursor {
+            response.header("X-Next-Cursor", cursor.encode());
+        }
+
+        let links = self.links(req);
+
+        if !links.is_empty() {
+            response.header("Link", links.join(", "));
+        }
+
+        response.json(&self.items)
+    }
+
+    /// Builds the RFC 8288 links to the neighbouring pages
+    fn links(&self, req: &HttpRequest) -> Vec<String> {
+        let info = req.connection_info();
+        let base = format!("{}://{}{}", info.scheme(), info.host(), req.path());
+
+        // keep every filter and the sort while replacing the pagination parameters
+        let retained: Vec<(String, String)> =
+            serde_urlencoded::from_str::<Vec<(String, String)>>(req.query_string())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(key, _)| !RESERVED_PARAMS.contains(&key.as_str()) || key == "sort")
+                .collect();
+
+        let link = |pagination: &[(&str, String)], rel: &str| {
+            let mut pairs = retained.clone();
+            pairs.push(("limit".to_string(), self.params.limit.to_string()));
+            pairs.extend(pagination.iter().map(|(k, v)| (k.to_string(), v.clone())));
+
+            let query = serde_urlencoded::to_string(&pairs).unwrap_or_default();
+
+            format!("<{}?{}>; rel=\"{}\"", base, query, rel)
+        };
+
+        let mut links = vec![link(&[], "first")];
+
+        if self.params.cursor.is_some() {
+            if let Some(cursor) = &self.next_cursor {
+                links.push(link(&[("cursor", cursor.encode())], "next"));
+            }
+
+            return links;
+        }
+
+        let limit = self.params.limit;
+        let offset = self.params.offset;
+
+        if offset > 0 {
+            let prev = (offset - limit).max(0);
+            links.push(link(&[("offset", prev.to_string())], "prev"));
+        }
+
+        if self.next_cursor.is_some() {
+            links.push(link(&[("offset", (offset + limit).to_string())], "next"));
+        }
+
+        if self.total > 0 {
+            let last = (self.total - 1) / limit * limit;
+            links.push(link(&[("offset", last.to_string())], "last"));
+        }
+
+        links
+    }
+}
+
+/// Applies keyset filtering after the request cursor and ordering on the provided
+/// column to a boxed query, using the id column as a tie breaker
+macro_rules! sort_by {
+    ($query:expr, $params:expr, $column:expr, $id:expr, $value:ty, $id_value:ty) => {{
+        use crate::utils::pagination::SortOrder;
+
+        let mut query = $query;
+
+        if let Some(cursor) = $params.cursor.as_ref() {
+            let value = cursor.value::<$value>()?;
+            let after = cursor.id::<$id_value>()?;
+
+            query = match $params.order {
+                SortOrder::Asc => query.filter(
+                    $column
+                        .gt(value.clone())
+                        .or($column.eq(value).and($id.gt(after))),
+                ),
+                SortOrder::Desc => query.filter(
+                    $column
+                        .lt(value.clone())
+                        .or($column.eq(value).and($id.lt(after))),
+                ),
+            };
+        }
+
+        match $params.order {
+            SortOrder::Asc => query.order(($column.asc(), $id.asc())),
+            SortOrder::Desc => query.order(($column.desc(), $id.desc())),
+        }
+    }};
+}
+
+pub(crate) use sort_by;
+
+/// Escapes LIKE wildcards and returns a pattern matching values starting with the prefix
+pub fn like_prefix(prefix: &str) -> String {
+    let escaped = prefix
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("{}%", escaped)
+}
+
+/// Creates the validation error returned for malformed list parameters
+fn invalid(field: &str, code: ValidationCode) -> ApiError {
+    ApiError::invalid_fields(vec![FieldError::new(field, code)])
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[derive(Serialize, Debug)]
+    struct Item {
+        id: i32,
+        name: String,
+    }
+
+    impl Listable for Item {
+        const SORT_FIELDS: &'static [&'static str] = &["id", "name"];
+        const FILTERS: &'static [&'static str] = &["name"];
+        const DEFAULT_LIMIT: i64 = 2;
+        const MAX_LIMIT: i64 = 10;
+
+        fn cursor(&self, sort: &str) -> Cursor {
+            match sort {
+                "name" => Cursor::new(&self.name, self.id),
+                _ => Cursor::new(self.id, self.id),
+            }
+        }
+    }
+
+    fn items(ids: &[i32]) -> Vec<Item> {
+        ids.iter()
+            .map(|id| Item {
+                id: *id,
+                name: format!("item{}", id),
+            })
+            .collect()
+    }
+
+    fn error_codes(result: Result<ListParams, ApiError>) -> Vec<String> {
+        match result {
+            Err(ApiError::ValidationError(_, _, errors)) => errors
+                .into_iter()
+                .map(|error| format!("{}:{}", error.field, error.code))
+                .collect(),
+            other => panic!("expected validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_parses_defaults() {
+        let params = ListParams::parse::<Item>("").unwrap();
+
+        assert_eq!(params.limit, 2);
+        assert_eq!(params.offset, 0);
+        assert_eq!(params.sort, "id");
+        assert_eq!(params.order, SortOrder::Asc);
+        assert!(params.cursor.is_none());
+    }
+
+    #[test]
+    fn it_parses_sort_filters_and_clamps_limit() {
+        let params = ListParams::parse::<Item>("sort=-name&limit=500&name=foo").unwrap();
+
+        assert_eq!(params.limit, 10);
+        assert_eq!(params.sort, "name");
+        assert_eq!(params.order, SortOrder::Desc);
+        assert_eq!(params.filter("name"), Some("foo"));
+    }
+
+    #[test]
+    fn it_rejects_invalid_parameters() {
+        assert_eq!(
+            error_codes(ListParams::parse::<Item>("sort=password")),
+            vec!["sort:INVALID_SORT"]
+        );
+        assert_eq!(
+            error_codes(ListParams::parse::<Item>("foo=bar")),
+            vec!["foo:UNKNOWN_PARAMETER"]
+        );
+        assert_eq!(
+            error_codes(ListParams::parse::<Item>("offset=-1")),
+            vec!["offset:INVALID_OFFSET"]
+        );
+        assert_eq!(
+            error_codes(ListParams::parse::<Item>("cursor=garbage")),
+            vec!["cursor:INVALID_CURSOR"]
+        );
+
+        let cursor = Cursor::new(1, 1).encode();
+        assert_eq!(
+            error_codes(ListParams::parse::<Item>(&format!(
+                "offset=2&cursor={}",
+                cursor
+            ))),
+            vec!["cursor:INVALID_PAGINATION"]
+        );
+    }
+
+    #[test]
+    fn it_round_trips_cursors() {
+        let cursor = Cursor::new("foo@bar.com", 42).sorted("name", SortOrder::Desc);
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+
+        assert_eq!(decoded, cursor);
+        assert_eq!(decoded.id::<i32>().unwrap(), 42);
+        assert_eq!(decoded.sort, "name");
+        assert_eq!(decoded.order, SortOrder::Desc);
+    }
+
+    #[test]
+    fn it_rejects_cursors_of_another_sort() {
+        let cursor = Cursor::new("item2", 2)
+            .sorted("name", SortOrder::Desc)
+            .encode();
+
+        let params = ListParams::parse::<Item>(&format!("cursor={}&sort=-name", cursor)).unwrap();
+        assert_eq!(params.cursor.unwrap().value, "item2");
+
+        for query in &["", "&sort=name", "&sort=-id"] {
+            assert_eq!(
+                error_codes(ListParams::parse::<Item>(&format!(
+                    "cursor={}{}",
+                    cursor, query
+                ))),
+                vec!["cursor:INVALID_CURSOR"]
+            );
+        }
+    }
+
+    #[test]
+    fn it_escapes_like_wildcards() {
+        assert_eq!(like_prefix("a_b%"), "a\\_b\\%%");
+    }
+
+    #[test]
+    fn it_builds_offset_links() {
+        let req = TestRequest::with_uri("/users?name=item&offset=2").to_http_request();
+        let params = ListParams::parse::<Item>(req.query_string()).unwrap();
+
+        let page = Page::from_rows(items(&[3, 4, 5]), 7, &params);
+        let links = page.links(&req);
+
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.next_cursor, Some(Cursor::new(4, 4)));
+        assert_eq!(
+            links,
+            vec![
+                "<http://localhost:8080/users?name=item&limit=2>; rel=\"first\"",
+                "<http://localhost:8080/users?name=item&limit=2&offset=0>; rel=\"prev\"",
+                "<http://localhost:8080/users?name=item&limit=2&offset=4>; rel=\"next\"",
+                "<http://localhost:8080/users?name=item&limit=2&offset=6>; rel=\"last\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_builds_cursor_links() {
+        let cursor = Cursor::new(2, 2).encode();
+        let req = TestRequest::with_uri(&format!("/users?cursor={}", cursor)).to_http_request();
+        let params = ListParams::parse::<Item>(req.query_string()).unwrap();
+
+        let page = Page::from_rows(items(&[3]), 3, &params);
+
+        assert!(page.next_cursor.is_none());
+        assert_eq!(
+            page.links(&req),
+            vec!["<http://localhost:8080/users?limit=2>; rel=\"first\""]
+        );
+    }
+}
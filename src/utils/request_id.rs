@@ -0,0 +1,241 @@
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, FromRequest, HttpRequest};
+use futures::future::{ok, ready, LocalBoxFuture, Ready};
+use std::fmt;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tracing_futures::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::utils::context::RequestContext;
+use crate::utils::errors::ApiError;
+use crate::utils::telemetry;
+
+/// Header request ids are read from and sent back in
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request id accepted from clients, longer ones are replaced
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Id of the request being handled, taken from the `X-Request-Id` header or
+/// generated when missing
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Returns the id sent by the client when it's usable, or a new one
+    fn from_header(value: Option<&HeaderValue>) -> Self {
+        let sent = value
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+            .filter(|id| id.chars().all(|c| c.is_ascii_graphic()));
+
+        match sent {
+            Some(id) => RequestId(id.to_string()),
+            None => RequestId(uuid::Uuid::new_v4().to_string()),
+        }
+    }
+
+    /// Returns the id of the request being handled on this thread, if any
+    pub fn current() -> Option<String> {
+        RequestContext::current()
+            .and_then(|context| context.id())
+            .map(|id| id.0)
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Extracts the id of the request
+impl FromRequest for RequestId {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, ApiError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let id = req
+            .extensions()
+            .get::<RequestContext>()
+            .and_then(|context| context.id());
+        ready(Ok(id.unwrap_or_else(|| RequestId::from_header(None))))
+    }
+}
+
+/// Sends the request id back to the client
+pub(crate) fn set_header(headers: &mut actix_web::http::HeaderMap, id: &RequestId) {
+    if let Ok(value) = HeaderValue::from_str(&id.0) {
+        headers.insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+}
+
+/// Middleware giving every request an id, sent back in the `X-Request-Id`
+/// header, and handling it within a span carrying that id so every log line
+/// written for the request can be found. The span continues the trace of the
+/// caller when it sends a `traceparent` header
+#[derive(Clone, Default)]
+pub struct RequestTracing;
+
+impl<S, B> Transform<S> for RequestTracing
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestTracingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestTracingMiddleware { service })
+    }
+}
+
+/// Service created by the RequestTracing middleware for every worker
+pub struct RequestTracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestTracingMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let id = RequestId::from_header(req.headers().get(REQUEST_ID_HEADER));
+        let (context, created) = RequestContext::of(&req);
+        context.set_id(id.clone());
+
+        let span = tracing::info_span!(
+            "request",
+            otel.name = %format_args!("{} {}", req.method(), req.path()),
+            otel.kind = "server",
+            request_id = %id,
+            method = %req.method(),
+            path = %req.path(),
+        );
+        span.set_parent(telemetry::remote_context(req.headers()));
+        let started = Instant::now();
+        let handled = span.in_scope(|| context.run(|| self.service.call(req)));
+
+        Box::pin(
+            async move {
+                let response = handled.await;
+                let elapsed_ms = started.elapsed().as_millis() as u64;
+
+                match response {
+                    Ok(mut response) => {
+                        tracing::info!(
+                            status = response.status().as_u16(),
+                            elapsed_ms,
+                            "request handled"
+                        );
+                        set_header(response.headers_mut(), &id);
+                        Ok(response)
+                    }
+                    Err(error) => {
+                        let status = error.as_response_error().error_response().status();
+                        tracing::info!(status = status.as_u16(), elapsed_ms, "request rejected");
+                        Err(if created { context.error(error) } else { error })
+                    }
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use actix_web::dev::Service;
+    use actix_web::{http::StatusCode, test, web, App};
+
+    #[actix_rt::test]
+    async fn it_echoes_or_generates_the_request_id() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RequestTracing)
+                .route("/", web::get().to(|id: RequestId| async move { id.0 })),
+        )
+        .await;
+
+        let sent = test::TestRequest::get()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .to_request();
+        let response = test::call_service(&mut app, sent).await;
+        assert_eq!(
+            response.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "abc-123"
+        );
+        assert_eq!(test::read_body(response).await, "abc-123");
+
+        let invalid = test::TestRequest::get()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "has spaces")
+            .to_request();
+        let response = test::call_service(&mut app, invalid).await;
+        let id = response.headers().get(REQUEST_ID_HEADER).unwrap();
+        assert!(uuid::Uuid::parse_str(id.to_str().unwrap()).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn it_reports_the_request_id_with_errors() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RequestTracing)
+                .route(
+                    "/login",
+                    web::post().to(|| async { Err::<String, _>(ApiError::InvalidLogin) }),
+                )
+                .service(
+                    web::resource("/guarded")
+                        .wrap_fn(|_, _| futures::future::err(ApiError::Unauthorized.into()))
+                        .to(|| async { "" }),
+                ),
+        )
+        .await;
+
+        let login = test::TestRequest::post()
+            .uri("/login")
+            .header(REQUEST_ID_HEADER, "login-1")
+            .to_request();
+        let response = test::call_service(&mut app, login).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(response).await).unwrap();
+        assert_eq!(body["code"], "INVALID_LOGIN");
+        assert_eq!(body["request_id"], "login-1");
+
+        // errors of inner middleware only become responses outside the app
+        let guarded = test::TestRequest::get()
+            .uri("/guarded")
+            .header(REQUEST_ID_HEADER, "guarded-1")
+            .to_request();
+        let error = app.call(guarded).await.err().unwrap();
+        let response = error.as_response_error().error_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "guarded-1"
+        );
+    }
+}
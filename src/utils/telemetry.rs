@@ -0,0 +1,215 @@
+use actix_web::http::HeaderMap;
+use opentelemetry::global;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self as sdktrace, Sampler, Tracer};
+use opentelemetry::sdk::Resource;
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use tokio::runtime::Runtime;
+
+use crate::settings::TelemetrySettings;
+
+/// Exports the spans of the service to an OTLP collector in the background,
+/// flushing the ones still queued when dropped
+pub struct Telemetry {
+    /// Runtime the exporter runs on, as actix runs on an older tokio than the
+    /// exporter needs
+    runtime: Option<Runtime>,
+}
+
+impl Telemetry {
+    /// Starts exporting spans when an endpoint is configured, returning the
+    /// tracer they are recorded with
+    pub fn start(settings: &TelemetrySettings) -> Result<(Telemetry, Option<Tracer>), String> {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        if settings.otlp_endpoint.is_empty() {
+            return Ok((Telemetry { runtime: None }, None));
+        }
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("telemetry")
+            .enable_all()
+            .build()
+            .map_err(|error| error.to_string())?;
+
+        // the batch exporter spawns onto the runtime it's installed in
+        let tracer = {
+            let _entered = runtime.enter();
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .http()
+                        .with_endpoint(settings.otlp_endpoint.as_str()),
+                )
+                .with_trace_config(config(settings))
+                .install_batch(opentelemetry::runtime::Tokio)
+                .map_err(|error| error.to_string())?
+        };
+
+        Ok((
+            Telemetry {
+                runtime: Some(runtime),
+            },
+            Some(tracer),
+        ))
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if self.runtime.is_some() {
+            global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Names the service and samples the traces it starts
+pub fn config(settings: &TelemetrySettings) -> sdktrace::Config {
+    let ratio = Sampler::TraceIdRatioBased(settings.sample_ratio);
+
+    sdktrace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(ratio)))
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            settings.service_name.clone(),
+        )]))
+}
+
+/// Returns the trace the caller continues through the W3C `traceparent`
+/// header, or an empty context when it didn't send one
+pub fn remote_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Reads propagated trace context from request headers
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::app::{self, Auth};
+    use crate::utils::request_id::RequestTracing;
+    use actix_web::{test, App};
+    use opentelemetry::sdk::export::trace::SpanData;
+    use opentelemetry::sdk::trace::{Span, SpanProcessor, TracerProvider};
+    use opentelemetry::trace::{TraceResult, TracerProvider as _};
+    use std::sync::{Arc, Mutex, OnceLock};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Keeps the spans ended in this process, in place of a collector
+    #[derive(Clone, Debug, Default)]
+    pub struct MemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl MemoryExporter {
+        /// Returns the exported spans of the trace
+        pub fn trace(&self, trace_id: &str) -> Vec<SpanData> {
+            let spans = self.0.lock().unwrap();
+            spans
+                .iter()
+                .filter(|span| span.span_context.trace_id().to_hex() == trace_id)
+                .cloned()
+                .collect()
+        }
+    }
+
+    impl SpanProcessor for MemoryExporter {
+        fn on_start(&self, _: &mut Span, _: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            self.0.lock().unwrap().push(span);
+        }
+
+        fn force_flush(&self) -> TraceResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> TraceResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Records the spans of every test in this process from now on
+    pub fn exporter() -> MemoryExporter {
+        static EXPORTER: OnceLock<(TracerProvider, MemoryExporter)> = OnceLock::new();
+
+        let (_, exporter) = EXPORTER.get_or_init(|| {
+            let exporter = MemoryExporter::default();
+            let provider = TracerProvider::builder()
+                .with_span_processor(exporter.clone())
+                .with_config(config(&TelemetrySettings::default()))
+                .build();
+
+            global::set_text_map_propagator(TraceContextPropagator::new());
+            let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("auth", None));
+            tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+                .expect("a global subscriber is already set");
+
+            (provider, exporter)
+        });
+
+        exporter.clone()
+    }
+
+    #[actix_rt::test]
+    async fn it_exports_the_spans_of_a_login_within_the_callers_trace() {
+        let exporter = exporter();
+        let auth = Auth::start(app::tests::settings()).await.unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RequestTracing)
+                .configure(|cfg| auth.configure(cfg)),
+        )
+        .await;
+
+        let credentials = serde_json::json!({"email": "traced@app.com", "password": "password"});
+        let signup = test::TestRequest::post()
+            .uri("/signup")
+            .set_json(&credentials)
+            .to_request();
+        test::call_service(&mut app, signup).await;
+
+        let login = test::TestRequest::post()
+            .uri("/login")
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .set_json(&credentials)
+            .to_request();
+        test::call_service(&mut app, login).await;
+
+        let spans = exporter.trace("4bf92f3577b34da6a3ce929d0e0e4736");
+        let span = |name: &str| {
+            spans
+                .iter()
+                .find(|span| span.name == name)
+                .unwrap_or_else(|| panic!("{} missing from {:?}", name, spans))
+        };
+
+        let request = span("POST /login");
+        assert_eq!(request.parent_span_id.to_hex(), "00f067aa0ba902b7");
+
+        let login = span("login");
+        assert_eq!(login.parent_span_id, request.span_context.span_id());
+
+        // the password is checked on a blocking thread, off the database ones
+        let database = span("database");
+        assert_eq!(database.parent_span_id, login.span_context.span_id());
+        let bcrypt = span("bcrypt");
+        assert_eq!(bcrypt.parent_span_id, login.span_context.span_id());
+    }
+}
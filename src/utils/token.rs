@@ -1,86 +1,63 @@
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use serde::{Deserialize, Serialize};
-use std::env;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::utils::errors::ApiError;
 use crate::models::user::User;
+use crate::settings::TokenSettings;
 
-/// Represents the contents of a jwt
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Token {
-    pub sub: i32,
-    pub email: String,
-    pub company: String,
-    pub iat: u64,
-    pub exp: u64,
-}
-
-impl Token {
-    /// Creates an instance of a token from the provided user
-    pub fn from_user(user: &User) -> Self {
-        let start = SystemTime::now();
-        let iat = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs();
-        let exp = iat + Duration::from_secs(60 * 60 * 24 * 7).as_secs();
-
-        Token {
-            sub: user.id.clone(),
-            email: user.email.clone(),
-            company: "my_company".to_string(),
-            iat,
-            exp,
-        }
-    }
+/// Represents the contents of a jwt, the claims services verifying it with
+/// `auth_verifier` read. Handlers extract it once `validator` verified it
+pub type Token = auth_verifier::Claims;
 
-    /// Decodes the provided token to the Token struct
-    pub fn decode(token: &str) -> Result<jsonwebtoken::TokenData<Token>, ApiError> {
-        let secret = env::var("USERS_SECRET").expect("secret has not been defined");
-        let token = decode::<Token>(
-            &token,
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::default(),
-        );
+/// Creates an instance of a token from the provided user
+pub fn issue(user: &User, settings: &TokenSettings) -> Token {
+    let start = SystemTime::now();
+    let iat = start
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+    let exp = iat + settings.expiry_secs;
 
-        match token {
-            Ok(c) => {
-                return Ok(c);
-            }
-            Err(_) => {
-                return Err(ApiError::Unauthorized);
-            }
-        }
-    }
-
-    /// Encodes the provided token struct to a string
-    pub fn encode(&self) -> String {
-        let secret = env::var("USERS_SECRET").expect("secret has not been defined");
-        encode(
-            &Header::default(),
-            self,
-            &EncodingKey::from_secret(secret.as_ref()),
-        )
-        .unwrap()
+    Token {
+        sub: user.id,
+        email: user.email.clone(),
+        role: user.role.clone(),
+        scope: settings
+            .scopes
+            .get(&user.role)
+            .filter(|scopes| !scopes.is_empty())
+            .map(|scopes| scopes.join(" ")),
+        iat,
+        exp,
     }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::models::user::User;
+    use crate::settings::Settings;
+    use crate::utils::keyring::Keyring;
 
-    pub fn create_token() -> Token {
-        let user = User {
+    /// Returns the token settings configured through the environment
+    fn settings() -> TokenSettings {
+        Settings::from_env().expect("invalid settings").token
+    }
+
+    fn user() -> User {
+        User {
             id: 1,
             email: "foo@bar.com".to_string(),
             password: "password".to_string(),
-            key_id: uuid::Uuid::new_v4(),
+            key_id: Some(uuid::Uuid::new_v4()),
             created_at: std::time::SystemTime::now(),
-        };
+            role: "user".to_string(),
+            disabled: false,
+            deleted_at: None,
+            invited_by: None,
+            locale: None,
+        }
+    }
 
-        Token::from_user(&user)
+    pub fn create_token() -> Token {
+        issue(&user(), &settings())
     }
 
     #[test]
@@ -90,13 +67,29 @@ pub mod tests {
         assert!(token.email == "foo@bar.com".to_string());
     }
 
+    #[test]
+    pub fn it_grants_the_scopes_of_the_role() {
+        let mut settings = settings();
+        assert!(create_token().scope.is_none());
+
+        settings.scopes.insert(
+            String::from("user"),
+            vec![String::from("profile:read"), String::from("reports:read")],
+        );
+        let token = issue(&user(), &settings);
+        assert_eq!(token.scope.as_deref(), Some("profile:read reports:read"));
+    }
+
     #[test]
     pub fn it_decodes_token() {
         let token = create_token();
-        let encoded_token = token.encode();
+        let keyring = Keyring::new(&settings());
+        let encoded_token = keyring.encode(&token).unwrap();
 
-        let decoded_token = Token::decode(&encoded_token).expect("Failed to decode token");
+        let decoded_token = keyring
+            .decode(&encoded_token)
+            .expect("Failed to decode token");
 
-        assert!(decoded_token.claims.email == token.email);
+        assert!(decoded_token.email == token.email);
     }
 }
@@ -0,0 +1,53 @@
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::VerifyError;
+
+/// Claims of a token issued by the auth service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: i32,
+    pub email: String,
+    #[serde(default)]
+    pub role: String,
+    /// Space separated scopes granted to the token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+impl Claims {
+    /// Returns true if the token was issued to a user with the role
+    pub fn has_role(&self, role: &str) -> bool {
+        self.role == role
+    }
+
+    /// Returns true if the token was granted the scope
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes().any(|granted| granted == scope)
+    }
+
+    /// Returns the scopes granted to the token
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.scope.as_deref().unwrap_or_default().split_whitespace()
+    }
+}
+
+/// Extracts the claims of the bearer token verified by one of the validators
+impl FromRequest for Claims {
+    type Error = VerifyError;
+    type Future = Ready<Result<Self, VerifyError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Claims>()
+                .cloned()
+                .ok_or(VerifyError::InvalidToken),
+        )
+    }
+}
@@ -0,0 +1,154 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt;
+
+/// Representation of a token that could not be verified
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The token is malformed, expired or signed by an unknown key
+    InvalidToken,
+    /// The token is valid but lacks the required role
+    MissingRole(String),
+    /// The token is valid but lacks the required scope
+    MissingScope(String),
+    /// The keys could not be fetched
+    KeysUnavailable(String),
+    /// No `Verifier` was registered with the app
+    NotConfigured,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::InvalidToken => write!(f, "The token is invalid or expired"),
+            VerifyError::MissingRole(role) => write!(f, "The {} role is required", role),
+            VerifyError::MissingScope(scope) => write!(f, "The {} scope is required", scope),
+            VerifyError::KeysUnavailable(error) => {
+                write!(f, "The signing keys are unavailable: {}", error)
+            }
+            VerifyError::NotConfigured => write!(f, "No token verifier has been configured"),
+        }
+    }
+}
+
+/// Prefix of the URIs identifying the kinds of problems, shared with the
+/// auth service
+const PROBLEM_TYPE_PREFIX: &str = "urn:auth:problem:";
+
+/// Media type of error responses
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Body of error responses, the RFC 7807 problem details the auth service
+/// answers with, without the request id this crate doesn't assign
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: &'a str,
+    status: u16,
+    detail: String,
+    code: &'a str,
+}
+
+impl VerifyError {
+    /// Returns the kind of the error with its title, its code and the
+    /// challenge sent back with it
+    fn describe(&self) -> (&str, &str, &str, Option<&str>) {
+        match self {
+            VerifyError::InvalidToken => (
+                "unauthorized",
+                "Unauthorized",
+                "UNAUTHORIZED",
+                Some("invalid_token"),
+            ),
+            VerifyError::MissingRole(_) => ("forbidden", "Forbidden", "FORBIDDEN", None),
+            VerifyError::MissingScope(_) => (
+                "forbidden",
+                "Forbidden",
+                "FORBIDDEN",
+                Some("insufficient_scope"),
+            ),
+            VerifyError::KeysUnavailable(_) => (
+                "keys-unavailable",
+                "Keys unavailable",
+                "KEYS_UNAVAILABLE",
+                None,
+            ),
+            VerifyError::NotConfigured => (
+                "internal-server-error",
+                "Internal server error",
+                "INTERNAL_SERVER_ERROR",
+                None,
+            ),
+        }
+    }
+}
+
+impl ResponseError for VerifyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            VerifyError::InvalidToken => StatusCode::UNAUTHORIZED,
+            VerifyError::MissingRole(_) | VerifyError::MissingScope(_) => StatusCode::FORBIDDEN,
+            VerifyError::KeysUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            VerifyError::NotConfigured => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let (kind, title, code, challenge) = self.describe();
+        let mut response = HttpResponse::build(self.status_code());
+
+        if let Some(challenge) = challenge {
+            let header = match self {
+                VerifyError::MissingScope(scope) => {
+                    format!("Bearer error=\"{}\", scope=\"{}\"", challenge, scope)
+                }
+                _ => format!("Bearer error=\"{}\"", challenge),
+            };
+            response.header("www-authenticate", header);
+        }
+
+        let problem = Problem {
+            problem_type: format!("{}{}", PROBLEM_TYPE_PREFIX, kind),
+            title,
+            status: self.status_code().as_u16(),
+            detail: self.to_string(),
+            code,
+        };
+
+        match serde_json::to_string(&problem) {
+            Ok(body) => response.content_type(PROBLEM_CONTENT_TYPE).body(body),
+            Err(error) => HttpResponse::from_error(error.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn it_responds_with_problem_details() {
+        let response = VerifyError::MissingScope(String::from("reports:read")).error_response();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            PROBLEM_CONTENT_TYPE
+        );
+        assert_eq!(
+            response.headers().get("www-authenticate").unwrap(),
+            "Bearer error=\"insufficient_scope\", scope=\"reports:read\""
+        );
+
+        let body = match response.body().as_ref() {
+            Some(actix_web::body::Body::Bytes(bytes)) => bytes.clone(),
+            _ => panic!("expected a problem document"),
+        };
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["type"], "urn:auth:problem:forbidden");
+        assert_eq!(problem["status"], 403);
+        assert_eq!(problem["code"], "FORBIDDEN");
+        assert_eq!(problem["detail"], "The reports:read scope is required");
+    }
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+
+/// Key type of RSA keys
+pub const RSA_KEY_TYPE: &str = "RSA";
+
+/// Public key tokens may be signed with, in JSON Web Key format
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: String,
+    #[serde(rename = "use", default, skip_serializing_if = "Option::is_none")]
+    pub key_use: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    /// Base64url encoded modulus
+    pub n: String,
+    /// Base64url encoded exponent
+    pub e: String,
+}
+
+impl Jwk {
+    /// Creates an RS256 signing key from its base64url encoded modulus and exponent
+    pub fn rsa(kid: &str, n: String, e: String) -> Self {
+        Jwk {
+            kty: RSA_KEY_TYPE.to_string(),
+            kid: kid.to_string(),
+            key_use: Some(String::from("sig")),
+            alg: Some(String::from("RS256")),
+            n,
+            e,
+        }
+    }
+}
+
+/// Document listing the keys tokens may be signed with
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+impl JwkSet {
+    /// Finds the RSA key with the provided kid
+    pub fn find(&self, kid: &str) -> Option<&Jwk> {
+        self.keys
+            .iter()
+            .find(|key| key.kid == kid && key.kty == RSA_KEY_TYPE)
+    }
+}
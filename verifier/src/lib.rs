@@ -0,0 +1,50 @@
+//! Verifies tokens issued by the auth service in other actix-web services,
+//! without depending on its database layer.
+//!
+//! Tokens are checked against the public keys the service publishes at
+//! `/.well-known/jwks.json`, fetched once and cached by kid, and routes can
+//! require a role or a scope:
+//!
+//! ```no_run
+//! use actix_web::{web, App, HttpServer};
+//! use actix_web_httpauth::middleware::HttpAuthentication;
+//! use auth_verifier::{require_scope, validator, Claims, Verifier};
+//!
+//! #[actix_rt::main]
+//! async fn main() -> std::io::Result<()> {
+//!     let verifier = Verifier::new("https://auth.example.com/.well-known/jwks.json");
+//!
+//!     HttpServer::new(move || {
+//!         App::new()
+//!             .data(verifier.clone())
+//!             .service(
+//!                 web::resource("/me")
+//!                     .wrap(HttpAuthentication::bearer(validator))
+//!                     .to(|claims: Claims| async move { claims.email }),
+//!             )
+//!             .service(
+//!                 web::resource("/reports")
+//!                     .wrap(HttpAuthentication::bearer(require_scope("reports:read")))
+//!                     .to(|| async { "reports" }),
+//!             )
+//!     })
+//!     .bind("0.0.0.0:8081")?
+//!     .run()
+//!     .await
+//! }
+//! ```
+
+#[macro_use]
+extern crate log;
+
+pub mod claims;
+pub mod errors;
+pub mod jwks;
+pub mod middleware;
+pub mod verifier;
+
+pub use crate::claims::Claims;
+pub use crate::errors::VerifyError;
+pub use crate::jwks::{Jwk, JwkSet};
+pub use crate::middleware::{require_role, require_scope, validator};
+pub use crate::verifier::Verifier;
@@ -0,0 +1,145 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::{Error, HttpMessage};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use futures::future::LocalBoxFuture;
+use std::sync::Arc;
+
+use crate::claims::Claims;
+use crate::errors::VerifyError;
+use crate::verifier::Verifier;
+
+/// Verifies the bearer token with the `Verifier` registered as app data and
+/// makes its claims available to handlers
+async fn authenticate(req: &ServiceRequest, credentials: &BearerAuth) -> Result<Claims, Error> {
+    let verifier = req
+        .app_data::<Verifier>()
+        .ok_or(VerifyError::NotConfigured)?;
+    let claims = verifier.verify(credentials.token()).await?;
+
+    req.extensions_mut().insert(claims.clone());
+
+    Ok(claims)
+}
+
+/// Validator for `HttpAuthentication::bearer` accepting any valid token
+pub async fn validator(
+    req: ServiceRequest,
+    credentials: BearerAuth,
+) -> Result<ServiceRequest, Error> {
+    authenticate(&req, &credentials).await?;
+    Ok(req)
+}
+
+/// Validator for `HttpAuthentication::bearer` accepting valid tokens of users
+/// with the role
+pub fn require_role(
+    role: &str,
+) -> impl Fn(ServiceRequest, BearerAuth) -> LocalBoxFuture<'static, Result<ServiceRequest, Error>> + Clone
+{
+    let role = Arc::new(role.to_string());
+
+    move |req, credentials| {
+        let role = role.clone();
+
+        Box::pin(async move {
+            let claims = authenticate(&req, &credentials).await?;
+            if !claims.has_role(&role) {
+                return Err(VerifyError::MissingRole(role.to_string()).into());
+            }
+
+            Ok(req)
+        })
+    }
+}
+
+/// Validator for `HttpAuthentication::bearer` accepting valid tokens granted
+/// the scope
+pub fn require_scope(
+    scope: &str,
+) -> impl Fn(ServiceRequest, BearerAuth) -> LocalBoxFuture<'static, Result<ServiceRequest, Error>> + Clone
+{
+    let scope = Arc::new(scope.to_string());
+
+    move |req, credentials| {
+        let scope = scope.clone();
+
+        Box::pin(async move {
+            let claims = authenticate(&req, &credentials).await?;
+            if !claims.has_scope(&scope) {
+                return Err(VerifyError::MissingScope(scope.to_string()).into());
+            }
+
+            Ok(req)
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::jwks::JwkSet;
+    use crate::verifier::tests::{claims, TestKey};
+    use actix_web::dev::Service;
+    use actix_web::{http::StatusCode, test, web, App};
+    use actix_web_httpauth::middleware::HttpAuthentication;
+
+    #[actix_rt::test]
+    async fn it_enforces_roles_and_scopes() {
+        let key = TestKey::generate("first");
+        let verifier = Verifier::with_keys(JwkSet {
+            keys: vec![key.jwk.clone()],
+        });
+
+        let mut app = test::init_service(
+            App::new()
+                .data(verifier)
+                .service(
+                    web::resource("/me")
+                        .wrap(HttpAuthentication::bearer(validator))
+                        .to(|claims: Claims| async move { claims.email }),
+                )
+                .service(
+                    web::resource("/admin")
+                        .wrap(HttpAuthentication::bearer(require_role("admin")))
+                        .to(|| async { "admin" }),
+                )
+                .service(
+                    web::resource("/reports")
+                        .wrap(HttpAuthentication::bearer(require_scope("reports:read")))
+                        .to(|| async { "reports" }),
+                ),
+        )
+        .await;
+
+        let user = format!("Bearer {}", key.sign(&claims("user", Some("reports:read"))));
+        let admin = format!("Bearer {}", key.sign(&claims("admin", None)));
+
+        let cases = vec![
+            ("/me", Some(&user), StatusCode::OK),
+            ("/me", None, StatusCode::UNAUTHORIZED),
+            ("/admin", Some(&user), StatusCode::FORBIDDEN),
+            ("/admin", Some(&admin), StatusCode::OK),
+            ("/reports", Some(&user), StatusCode::OK),
+            ("/reports", Some(&admin), StatusCode::FORBIDDEN),
+        ];
+
+        for (uri, bearer, expected) in cases {
+            let mut req = test::TestRequest::get().uri(uri);
+            if let Some(bearer) = bearer {
+                req = req.header("authorization", bearer.as_str());
+            }
+
+            let status = match app.call(req.to_request()).await {
+                Ok(response) => response.status(),
+                Err(error) => error.as_response_error().error_response().status(),
+            };
+            assert_eq!(status, expected, "GET {} with {:?}", uri, bearer);
+        }
+
+        let me = test::TestRequest::get()
+            .uri("/me")
+            .header("authorization", user.as_str())
+            .to_request();
+        assert_eq!(test::read_response(&mut app, me).await, "user@email.com");
+    }
+}
@@ -0,0 +1,394 @@
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::claims::Claims;
+use crate::errors::VerifyError;
+use crate::jwks::{Jwk, JwkSet};
+
+/// How long fetched keys are trusted before they are fetched again
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Minimum time between fetches triggered by tokens signed with unknown keys
+const DEFAULT_MIN_REFETCH: Duration = Duration::from_secs(30);
+
+/// Where the keys tokens are verified with come from
+enum Source {
+    Url(String),
+    Static,
+}
+
+/// Keys fetched from the source, by kid
+#[derive(Default)]
+struct Cache {
+    keys: HashMap<String, Jwk>,
+    fetched_at: Option<Instant>,
+}
+
+/// Verifies tokens against the keys published by the auth service. Keys are
+/// cached by kid and fetched again once stale, or when a token names a key
+/// that hasn't been seen yet, such as right after a rotation
+#[derive(Clone)]
+pub struct Verifier {
+    source: Arc<Source>,
+    cache: Arc<RwLock<Cache>>,
+    secret: Option<Arc<String>>,
+    cache_ttl: Duration,
+    min_refetch: Duration,
+}
+
+impl Verifier {
+    /// Creates a verifier fetching keys from the JWKS url of the auth service
+    pub fn new(jwks_url: &str) -> Self {
+        Verifier::with_source(Source::Url(jwks_url.to_string()), Cache::default())
+    }
+
+    /// Creates a verifier trusting a fixed set of keys
+    pub fn with_keys(keys: JwkSet) -> Self {
+        let cache = Cache {
+            keys: keys
+                .keys
+                .into_iter()
+                .map(|key| (key.kid.clone(), key))
+                .collect(),
+            fetched_at: None,
+        };
+
+        Verifier::with_source(Source::Static, cache)
+    }
+
+    fn with_source(source: Source, cache: Cache) -> Self {
+        Verifier {
+            source: Arc::new(source),
+            cache: Arc::new(RwLock::new(cache)),
+            secret: None,
+            cache_ttl: DEFAULT_CACHE_TTL,
+            min_refetch: DEFAULT_MIN_REFETCH,
+        }
+    }
+
+    /// Also accepts tokens without a kid signed with the shared secret, as
+    /// issued before the auth service generated its first signing key
+    pub fn with_secret(mut self, secret: &str) -> Self {
+        self.secret = Some(Arc::new(secret.to_string()));
+        self
+    }
+
+    /// Sets how long fetched keys are trusted before being fetched again
+    pub fn cache_for(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Sets the minimum time between fetches caused by unknown kids, so
+    /// forged tokens can't make every request hit the auth service
+    pub fn refetch_at_most_every(mut self, interval: Duration) -> Self {
+        self.min_refetch = interval;
+        self
+    }
+
+    /// Verifies the signature and expiry of the token and decodes its claims
+    pub async fn verify(&self, token: &str) -> Result<Claims, VerifyError> {
+        let key = match kid(token)? {
+            Some(kid) => Some(self.key(&kid).await?),
+            None => None,
+        };
+
+        self.decode(token, key.as_ref())
+    }
+
+    /// Verifies the token with the keys at hand, without fetching them again,
+    /// which is all a verifier trusting a fixed set of keys ever does
+    pub fn verify_cached(&self, token: &str) -> Result<Claims, VerifyError> {
+        let key = match kid(token)? {
+            Some(kid) => {
+                let cache = self.cache.read().map_err(|_| poisoned())?;
+                Some(
+                    cache
+                        .keys
+                        .get(&kid)
+                        .cloned()
+                        .ok_or(VerifyError::InvalidToken)?,
+                )
+            }
+            None => None,
+        };
+
+        self.decode(token, key.as_ref())
+    }
+
+    /// Decodes the claims of the token signed with the key, or with the
+    /// secret when the token names no key
+    fn decode(&self, token: &str, key: Option<&Jwk>) -> Result<Claims, VerifyError> {
+        let decoded = match key {
+            Some(jwk) => {
+                let key = DecodingKey::from_rsa_components(&jwk.n, &jwk.e);
+
+                decode::<Claims>(token, &key, &Validation::new(Algorithm::RS256))
+            }
+            None => {
+                let secret = self.secret.as_ref().ok_or(VerifyError::InvalidToken)?;
+
+                decode::<Claims>(
+                    token,
+                    &DecodingKey::from_secret(secret.as_bytes()),
+                    &Validation::default(),
+                )
+            }
+        };
+
+        decoded
+            .map(|data| data.claims)
+            .map_err(|_| VerifyError::InvalidToken)
+    }
+
+    /// Returns the key with the provided kid, fetching the keys again when
+    /// they are stale or the kid is unknown
+    async fn key(&self, kid: &str) -> Result<Jwk, VerifyError> {
+        let (cached, fetch) = {
+            let cache = self.cache.read().map_err(|_| poisoned())?;
+            let age = cache.fetched_at.map(|fetched_at| fetched_at.elapsed());
+            let cached = cache.keys.get(kid).cloned();
+
+            let fetch = match (&*self.source, age) {
+                (Source::Static, _) => false,
+                (Source::Url(_), None) => true,
+                (Source::Url(_), Some(age)) if cached.is_some() => age > self.cache_ttl,
+                (Source::Url(_), Some(age)) => age > self.min_refetch,
+            };
+
+            (cached, fetch)
+        };
+
+        if !fetch {
+            return cached.ok_or(VerifyError::InvalidToken);
+        }
+
+        match self.refresh().await {
+            Ok(keys) => keys.find(kid).cloned().ok_or(VerifyError::InvalidToken),
+            // keep verifying with stale keys while the auth service is unreachable
+            Err(error) => match cached {
+                Some(key) => {
+                    warn!("using cached signing key {}: {}", kid, error);
+                    Ok(key)
+                }
+                None => Err(error),
+            },
+        }
+    }
+
+    /// Fetches the keys from the source and replaces the cached ones
+    async fn refresh(&self) -> Result<JwkSet, VerifyError> {
+        let url = match &*self.source {
+            Source::Url(url) => url,
+            Source::Static => return Err(VerifyError::KeysUnavailable(String::from("static"))),
+        };
+
+        let fetched = fetch(url).await;
+
+        let mut cache = self.cache.write().map_err(|_| poisoned())?;
+        // failed fetches count too, so an unreachable service isn't hammered
+        cache.fetched_at = Some(Instant::now());
+
+        let keys = fetched?;
+        cache.keys = keys
+            .keys
+            .iter()
+            .map(|key| (key.kid.clone(), key.clone()))
+            .collect();
+        debug!("fetched {} signing keys from {}", keys.keys.len(), url);
+
+        Ok(keys)
+    }
+}
+
+/// Returns the kid the token names in its header
+fn kid(token: &str) -> Result<Option<String>, VerifyError> {
+    let header = decode_header(token).map_err(|_| VerifyError::InvalidToken)?;
+    Ok(header.kid)
+}
+
+/// Downloads the JWKS document at the url
+async fn fetch(url: &str) -> Result<JwkSet, VerifyError> {
+    let unavailable = |error: String| VerifyError::KeysUnavailable(error);
+
+    let mut response = awc::Client::default()
+        .get(url)
+        .send()
+        .await
+        .map_err(|error| unavailable(error.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(unavailable(format!(
+            "{} returned {}",
+            url,
+            response.status()
+        )));
+    }
+
+    response
+        .json::<JwkSet>()
+        .await
+        .map_err(|error| unavailable(error.to_string()))
+}
+
+/// Error returned when a thread panicked while replacing the keys
+fn poisoned() -> VerifyError {
+    VerifyError::KeysUnavailable(String::from("the key cache is poisoned"))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse};
+    use futures::future::ready;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use openssl::rsa::Rsa;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// RSA key pair signing test tokens
+    pub struct TestKey {
+        pub kid: String,
+        pub jwk: Jwk,
+        encoding: EncodingKey,
+    }
+
+    impl TestKey {
+        pub fn generate(kid: &str) -> Self {
+            let rsa = Rsa::generate(2048).unwrap();
+            let encode = |bytes: Vec<u8>| base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD);
+
+            TestKey {
+                kid: kid.to_string(),
+                jwk: Jwk::rsa(kid, encode(rsa.n().to_vec()), encode(rsa.e().to_vec())),
+                encoding: EncodingKey::from_rsa_pem(&rsa.private_key_to_pem().unwrap()).unwrap(),
+            }
+        }
+
+        pub fn sign(&self, claims: &Claims) -> String {
+            let mut header = Header::new(Algorithm::RS256);
+            header.kid = Some(self.kid.clone());
+            encode(&header, claims, &self.encoding).unwrap()
+        }
+    }
+
+    pub fn claims(role: &str, scope: Option<&str>) -> Claims {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        Claims {
+            sub: 1,
+            email: String::from("user@email.com"),
+            role: role.to_string(),
+            scope: scope.map(String::from),
+            iat: now,
+            exp: now + 60,
+        }
+    }
+
+    fn assert_invalid(result: Result<Claims, VerifyError>) {
+        match result {
+            Err(VerifyError::InvalidToken) => {}
+            other => panic!("expected invalid token error, got {:?}", other),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_verifies_tokens_with_static_keys() {
+        let key = TestKey::generate("first");
+        let verifier = Verifier::with_keys(JwkSet {
+            keys: vec![key.jwk.clone()],
+        });
+
+        let verified = verifier
+            .verify(&key.sign(&claims("admin", Some("users:read"))))
+            .await
+            .unwrap();
+        assert_eq!(verified.email, "user@email.com");
+        assert!(verified.has_role("admin"));
+        assert!(verified.has_scope("users:read"));
+        assert!(!verified.has_scope("users:write"));
+
+        let mut expired = claims("user", None);
+        expired.exp = expired.iat - 3600;
+        assert_invalid(verifier.verify(&key.sign(&expired)).await);
+
+        let unknown = TestKey::generate("second");
+        assert_invalid(verifier.verify(&unknown.sign(&claims("user", None))).await);
+
+        // the secret is only trusted when configured
+        let with_secret = encode(
+            &Header::default(),
+            &claims("user", None),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert_invalid(verifier.verify(&with_secret).await);
+        assert!(verifier
+            .clone()
+            .with_secret("secret")
+            .verify(&with_secret)
+            .await
+            .is_ok());
+
+        // keys at hand verify the same way without awaiting a fetch
+        assert!(verifier
+            .verify_cached(&key.sign(&claims("user", None)))
+            .is_ok());
+        assert_invalid(verifier.verify_cached(&unknown.sign(&claims("user", None))));
+    }
+
+    #[actix_rt::test]
+    async fn it_fetches_keys_again_for_unknown_kids() {
+        let first = TestKey::generate("first");
+        let second = TestKey::generate("second");
+        let published = Arc::new(RwLock::new(vec![first.jwk.clone()]));
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let server = {
+            let published = published.clone();
+            let fetches = fetches.clone();
+
+            actix_web::test::start(move || {
+                let published = published.clone();
+                let fetches = fetches.clone();
+
+                App::new().route(
+                    "/.well-known/jwks.json",
+                    web::get().to(move || {
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        let keys = published.read().unwrap().clone();
+                        ready(HttpResponse::Ok().json(JwkSet { keys }))
+                    }),
+                )
+            })
+        };
+
+        let verifier = Verifier::new(&server.url("/.well-known/jwks.json"))
+            .refetch_at_most_every(Duration::from_millis(100));
+
+        assert!(verifier
+            .verify(&first.sign(&claims("user", None)))
+            .await
+            .is_ok());
+        assert!(verifier
+            .verify(&first.sign(&claims("user", None)))
+            .await
+            .is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // a rotated key is picked up, but unknown kids don't refetch right away
+        published.write().unwrap().push(second.jwk.clone());
+        let rotated = second.sign(&claims("user", None));
+        assert_invalid(verifier.verify(&rotated).await);
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        actix_rt::time::delay_for(Duration::from_millis(150)).await;
+        assert!(verifier.verify(&rotated).await.is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+}